@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::info;
+use serde::Deserialize;
+
+use crate::model::dead_letter::{DeadLetterResponseDto, DeadLetterStatus, UpdateDeadLetterPayload};
+use crate::mqtt::HandlerError;
+use super::error::{parse_object_id, ApiError};
+use super::ApiState;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ListDeadLettersQuery {
+    pub status: Option<DeadLetterStatus>,
+    pub topic: Option<String>,
+    pub skip: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+/// 分页列出死信
+pub async fn list_dead_letters(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<ListDeadLettersQuery>,
+) -> Result<Json<Vec<DeadLetterResponseDto>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::BadRequest(format!("limit 必须在1到{}之间", MAX_PAGE_SIZE)));
+    }
+    let dead_letters = state
+        .dead_letter_service
+        .list(query.status, query.topic.as_deref(), query.skip.unwrap_or(0), limit)
+        .await?;
+    Ok(Json(dead_letters.into_iter().map(Into::into).collect()))
+}
+
+/// 查看单条死信
+pub async fn get_dead_letter(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<Json<DeadLetterResponseDto>, ApiError> {
+    parse_object_id(&id)?;
+    let dead_letter = state
+        .dead_letter_service
+        .get(&id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("死信不存在: {}", id)))?;
+    Ok(Json(dead_letter.into()))
+}
+
+/// 修正死信的主题或消息内容
+pub async fn update_dead_letter(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+    Json(body): Json<UpdateDeadLetterPayload>,
+) -> Result<Json<DeadLetterResponseDto>, ApiError> {
+    parse_object_id(&id)?;
+    let payload = match (body.payload, body.payload_base64) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::BadRequest("payload 与 payloadBase64 只能提供一个".to_string()));
+        }
+        (Some(text), None) => Some(text.into_bytes()),
+        (None, Some(encoded)) => Some(
+            BASE64
+                .decode(encoded)
+                .map_err(|e| ApiError::BadRequest(format!("payloadBase64 解码失败: {}", e)))?,
+        ),
+        (None, None) => None,
+    };
+    if !state.dead_letter_service.update(&id, body.topic, payload).await? {
+        return Err(ApiError::NotFound(format!("死信不存在: {}", id)));
+    }
+    get_dead_letter(State(state), Path(id)).await
+}
+
+/// 删除死信
+pub async fn delete_dead_letter(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    parse_object_id(&id)?;
+    if !state.dead_letter_service.delete(&id).await? {
+        return Err(ApiError::NotFound(format!("死信不存在: {}", id)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 通过正常的消息处理器重放死信
+///
+/// 重放前先标记死信，同一死信的并发重放请求返回409。消息交给处理同一无人机实时消息的工作任务，
+/// 不会与实时消息交错写入。
+pub async fn replay_dead_letter(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<Json<DeadLetterResponseDto>, ApiError> {
+    let obj_id = parse_object_id(&id)?;
+    let Some(dead_letter) = state.dead_letter_service.claim_replay(&obj_id).await? else {
+        return Err(match state.dead_letter_service.get(&id).await? {
+            None => ApiError::NotFound(format!("死信不存在: {}", id)),
+            Some(dead_letter) if dead_letter.status == DeadLetterStatus::Replayed => {
+                ApiError::Conflict(format!("死信已重放: {}", id))
+            }
+            Some(_) => ApiError::Conflict(format!("死信正在重放: {}", id)),
+        });
+    };
+
+    // 重放是人工操作，不保留原消息的有效期
+    let metadata = dead_letter.metadata();
+    let Some(mut routed) = state.dispatcher.router().resolve(&dead_letter.topic, dead_letter.payload.bytes, metadata) else {
+        let reason = format!("没有匹配的路由: {}", dead_letter.topic);
+        state.dead_letter_service.record_replay(&obj_id, Some(&reason)).await?;
+        return Err(ApiError::Unprocessable(reason));
+    };
+    routed.message.received_at = dead_letter.received_at.to_chrono();
+
+    match state.dispatcher.replay(routed).await {
+        Ok(()) => {
+            info!("死信重放成功: {}", id);
+            state.dead_letter_service.record_replay(&obj_id, None).await?;
+        }
+        Err(e) => {
+            let reason = match &e {
+                HandlerError::Rejected(reason) | HandlerError::Failed(reason) => reason.clone(),
+            };
+            state.dead_letter_service.record_replay(&obj_id, Some(&reason)).await?;
+            return Err(match e {
+                HandlerError::Rejected(_) => ApiError::Unprocessable(reason),
+                HandlerError::Failed(_) => ApiError::Internal(reason),
+            });
+        }
+    }
+    get_dead_letter(State(state), Path(id)).await
+}
@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::live::DroneStateDto;
+use super::error::{ApiError, ApiQuery};
+use super::ApiState;
+
+#[derive(Debug, Deserialize)]
+pub struct DroneStatesQuery {
+    /// 逗号分隔的无人机序列号，不指定时返回全部无人机
+    pub drones: Option<String>,
+}
+
+/// 列出无人机最近的位置和flight状态，数据来自内存缓存，与实时事件格式一致
+pub async fn list_drone_states(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(query): ApiQuery<DroneStatesQuery>,
+) -> Json<Vec<DroneStateDto>> {
+    let drones: Option<HashSet<&str>> = query.drones.as_deref().map(|drones| {
+        drones
+            .split(',')
+            .map(str::trim)
+            .filter(|drone| !drone.is_empty())
+            .collect()
+    });
+    Json(state.last_known.snapshot(|_, drone_id| {
+        drones.as_ref().is_none_or(|drones| drones.contains(drone_id))
+    }))
+}
+
+/// 查询一架无人机最近的位置和flight状态
+pub async fn get_drone_state(
+    State(state): State<Arc<ApiState>>,
+    Path(serial): Path<String>,
+) -> Result<Json<DroneStateDto>, ApiError> {
+    state
+        .last_known
+        .get(&serial)
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("没有无人机的状态: {}", serial)))
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use log::info;
+use serde::Deserialize;
+
+use crate::model::drone::{CreateDroneRequestDto, DroneResponseDto, UpdateDroneRequestDto};
+use super::error::ApiError;
+use super::ApiState;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ListDronesQuery {
+    pub skip: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+/// 分页列出已登记的无人机
+pub async fn list_drones(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<ListDronesQuery>,
+) -> Result<Json<Vec<DroneResponseDto>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::BadRequest(format!("limit 必须在1到{}之间", MAX_PAGE_SIZE)));
+    }
+    let drones = state.drone_service.list(query.skip.unwrap_or(0), limit).await?;
+    Ok(Json(drones.into_iter().map(Into::into).collect()))
+}
+
+/// 登记无人机
+pub async fn create_drone(
+    State(state): State<Arc<ApiState>>,
+    Json(body): Json<CreateDroneRequestDto>,
+) -> Result<(StatusCode, Json<DroneResponseDto>), ApiError> {
+    validate_serial(&body.serial)?;
+    let serial = body.serial.clone();
+    let drone = state
+        .drone_service
+        .create(body)
+        .await?
+        .ok_or_else(|| ApiError::Conflict(format!("无人机已登记: {}", serial)))?;
+    info!("已登记无人机: {}", serial);
+    Ok((StatusCode::CREATED, Json(drone.into())))
+}
+
+/// 按序列号查询无人机
+pub async fn get_drone(
+    State(state): State<Arc<ApiState>>,
+    Path(serial): Path<String>,
+) -> Result<Json<DroneResponseDto>, ApiError> {
+    let drone = state
+        .drone_service
+        .get_by_serial(&serial)
+        .await?
+        .ok_or_else(|| drone_not_found(&serial))?;
+    Ok(Json(drone.into()))
+}
+
+/// 更新无人机信息
+pub async fn update_drone(
+    State(state): State<Arc<ApiState>>,
+    Path(serial): Path<String>,
+    Json(body): Json<UpdateDroneRequestDto>,
+) -> Result<Json<DroneResponseDto>, ApiError> {
+    let drone = state
+        .drone_service
+        .update(&serial, body)
+        .await?
+        .ok_or_else(|| drone_not_found(&serial))?;
+    Ok(Json(drone.into()))
+}
+
+/// 删除无人机登记信息
+pub async fn delete_drone(
+    State(state): State<Arc<ApiState>>,
+    Path(serial): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if !state.drone_service.delete(&serial).await? {
+        return Err(drone_not_found(&serial));
+    }
+    state.provisioning_service.forget(&serial);
+    state.last_known.remove(&serial);
+    state.history.remove(&serial);
+    info!("已删除无人机: {}", serial);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 结束无人机当前的飞行，下一条遥测会开启新的航迹和flight
+pub async fn end_drone_flight(
+    State(state): State<Arc<ApiState>>,
+    Path(serial): Path<String>,
+) -> Result<Json<DroneResponseDto>, ApiError> {
+    let drone = state
+        .drone_service
+        .end_active_flight(&serial)
+        .await?
+        .ok_or_else(|| drone_not_found(&serial))?;
+    state.provisioning_service.forget(&serial);
+    info!("无人机 {} 的飞行已结束", serial);
+    Ok(Json(drone.into()))
+}
+
+fn drone_not_found(serial: &str) -> ApiError {
+    ApiError::NotFound(format!("无人机不存在: {}", serial))
+}
+
+/// 序列号会作为MQTT主题的一层，不能包含分隔符和通配符
+fn validate_serial(serial: &str) -> Result<(), ApiError> {
+    if serial.trim().is_empty() || serial.contains(['/', '+', '#']) {
+        return Err(ApiError::BadRequest(format!("无效的无人机序列号: \"{}\"", serial)));
+    }
+    Ok(())
+}
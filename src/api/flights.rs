@@ -0,0 +1,93 @@
+use std::sync::Arc;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::model::flight::{FlightMetric, FlightMetricsResponseDto};
+use crate::model::ship_track::StitchedTrackResponseDto;
+use crate::service::downsample::DownsampleMethod;
+use super::error::{parse_object_id, ApiError, ApiQuery};
+use super::ApiState;
+
+/// 未指定 `points` 时每个指标返回的最大点数
+const DEFAULT_CHART_POINTS: usize = 500;
+const MAX_CHART_POINTS: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub struct FlightMetricsQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// 逗号分隔的指标名称，默认全部指标
+    pub metrics: Option<String>,
+    /// 每个指标降采样后的最大点数
+    pub points: Option<usize>,
+    pub method: Option<DownsampleMethod>,
+}
+
+/// 按分段顺序拼接flight的完整航迹
+pub async fn get_flight_track(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<Json<StitchedTrackResponseDto>, ApiError> {
+    let flight_id = parse_object_id(&id)?;
+    let flight = state
+        .flight_service
+        .get(&id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("flight不存在: {}", id)))?;
+    let segments = state
+        .track_service
+        .list_segments(&flight_id, Some(flight.track_id))
+        .await?;
+    if segments.is_empty() {
+        return Err(ApiError::NotFound(format!("flight {} 没有航迹", id)));
+    }
+    Ok(Json(StitchedTrackResponseDto::stitch(flight_id, segments)))
+}
+
+/// 查询flight在时间范围内的状态指标，按图表需要降采样
+pub async fn get_flight_metrics(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+    ApiQuery(query): ApiQuery<FlightMetricsQuery>,
+) -> Result<Json<FlightMetricsResponseDto>, ApiError> {
+    let flight_id = parse_object_id(&id)?;
+    if matches!((query.from, query.to), (Some(from), Some(to)) if from > to) {
+        return Err(ApiError::BadRequest("from 不能晚于 to".to_string()));
+    }
+    let points = query.points.unwrap_or(DEFAULT_CHART_POINTS);
+    if !(3..=MAX_CHART_POINTS).contains(&points) {
+        return Err(ApiError::BadRequest(format!("points 必须在3到{}之间", MAX_CHART_POINTS)));
+    }
+    let metrics = match query.metrics.as_deref() {
+        None => FlightMetric::ALL.to_vec(),
+        Some(names) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| FlightMetric::parse(name).ok_or_else(|| ApiError::BadRequest(format!("未知的指标: {}", name))))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    if metrics.is_empty() {
+        return Err(ApiError::BadRequest("metrics 不能为空".to_string()));
+    }
+    if state.flight_service.get(&id).await?.is_none() {
+        return Err(ApiError::NotFound(format!("flight不存在: {}", id)));
+    }
+    let response = state
+        .flight_service
+        .metrics(
+            &flight_id,
+            query.from.map(Into::into),
+            query.to.map(Into::into),
+            &metrics,
+            points,
+            query.method.unwrap_or_default(),
+        )
+        .await?;
+    Ok(Json(response))
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use axum::{
+    extract::State,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+use crate::model::geo::{validate_position, Geometry};
+use crate::model::ship_track::{NearbyDroneDto, ShipTrackSummaryDto, TrackAreaMatchDto};
+use super::error::{ApiError, ApiJson, ApiQuery};
+use super::ApiState;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+/// 未指定 `max_age_secs` 时，只返回5分钟内更新过位置的无人机
+const DEFAULT_MAX_AGE_SECS: i64 = 300;
+
+#[derive(Debug, Deserialize)]
+pub struct IntersectingTracksRequest {
+    /// GeoJSON `Polygon`
+    pub geometry: Geometry,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NearbyDronesQuery {
+    pub longitude: f64,
+    pub latitude: f64,
+    /// 半径（米）
+    pub radius: f64,
+    pub max_age_secs: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TracksInAreaQuery {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub limit: Option<i64>,
+}
+
+/// 查询与多边形相交的航迹
+pub async fn intersecting_tracks(
+    State(state): State<Arc<ApiState>>,
+    ApiJson(body): ApiJson<IntersectingTracksRequest>,
+) -> Result<Json<Vec<ShipTrackSummaryDto>>, ApiError> {
+    let limit = page_limit(body.limit)?;
+    if !matches!(body.geometry, Geometry::Polygon { .. }) {
+        return Err(ApiError::BadRequest("geometry 必须是 Polygon".to_string()));
+    }
+    body.geometry.validate().map_err(ApiError::BadRequest)?;
+    let tracks = state.track_service.find_intersecting(&body.geometry, limit).await?;
+    Ok(Json(tracks.into_iter().map(Into::into).collect()))
+}
+
+/// 查询某点附近的无人机
+pub async fn nearby_drones(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(query): ApiQuery<NearbyDronesQuery>,
+) -> Result<Json<Vec<NearbyDroneDto>>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    validate_position(&[query.longitude, query.latitude]).map_err(ApiError::BadRequest)?;
+    if !(query.radius > 0.0 && query.radius.is_finite()) {
+        return Err(ApiError::BadRequest("radius 必须大于0".to_string()));
+    }
+    let max_age = query.max_age_secs.unwrap_or(DEFAULT_MAX_AGE_SECS);
+    if max_age <= 0 {
+        return Err(ApiError::BadRequest("max_age_secs 必须大于0".to_string()));
+    }
+    let since = Utc::now() - Duration::seconds(max_age);
+    let drones = state
+        .track_service
+        .find_nearby_drones([query.longitude, query.latitude], query.radius, since.into(), limit)
+        .await?;
+    Ok(Json(drones))
+}
+
+/// 查询时间窗口内经过矩形区域的航迹及区域内的航迹点
+pub async fn tracks_in_area(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(query): ApiQuery<TracksInAreaQuery>,
+) -> Result<Json<Vec<TrackAreaMatchDto>>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    let (min, max) = ([query.min_lon, query.min_lat], [query.max_lon, query.max_lat]);
+    validate_position(&min).map_err(ApiError::BadRequest)?;
+    validate_position(&max).map_err(ApiError::BadRequest)?;
+    if min[0] >= max[0] || min[1] >= max[1] {
+        return Err(ApiError::BadRequest("min_lon/min_lat 必须小于 max_lon/max_lat".to_string()));
+    }
+    // 跨度达到半球的多边形在MongoDB中需要特殊的坐标参考系
+    if max[0] - min[0] >= 180.0 {
+        return Err(ApiError::BadRequest("经度跨度必须小于180度".to_string()));
+    }
+    if query.from > query.to {
+        return Err(ApiError::BadRequest("from 不能晚于 to".to_string()));
+    }
+    let tracks = state
+        .track_service
+        .find_in_area(min, max, query.from.into(), query.to.into(), limit)
+        .await?;
+    Ok(Json(tracks.into_iter().map(Into::into).collect()))
+}
+
+fn page_limit(limit: Option<i64>) -> Result<i64, ApiError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::BadRequest(format!("limit 必须在1到{}之间", MAX_PAGE_SIZE)));
+    }
+    Ok(limit)
+}
@@ -0,0 +1,11 @@
+pub mod dead_letters;
+pub mod drone_states;
+pub mod drones;
+pub mod error;
+pub mod flights;
+pub mod geo;
+pub mod server;
+pub mod status;
+pub mod tracks;
+
+pub use server::{routes, ApiState};
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use tokio::sync::watch;
+
+use crate::live::{LastKnownState, LiveClients, LocationHistory};
+use crate::mqtt::{ConnectionStatus, Dispatcher};
+use crate::server::AppState;
+use crate::service::dead_letter_service::DeadLetterService;
+use crate::service::drone_service::DroneService;
+use crate::service::flight_service::FlightService;
+use crate::service::provisioning_service::ProvisioningService;
+use crate::service::ship_track_service::ShipTrackService;
+use super::dead_letters::{
+    delete_dead_letter, get_dead_letter, list_dead_letters, replay_dead_letter, update_dead_letter,
+};
+use super::drone_states::{get_drone_state, list_drone_states};
+use super::drones::{create_drone, delete_drone, end_drone_flight, get_drone, list_drones, update_drone};
+use super::flights::{get_flight_metrics, get_flight_track};
+use super::geo::{intersecting_tracks, nearby_drones, tracks_in_area};
+use super::status::{live_clients_handler, mqtt_status_handler};
+use super::tracks::{
+    append_track_coordinates, create_track, delete_track, get_latest_track, get_track, list_tracks,
+};
+
+/// REST接口共享状态
+pub struct ApiState {
+    pub mqtt_status: watch::Receiver<ConnectionStatus>,
+    pub dead_letter_service: Arc<DeadLetterService>,
+    /// 用于重放死信，重放的消息与实时消息由同一工作任务处理
+    pub dispatcher: Arc<Dispatcher>,
+    pub drone_service: Arc<DroneService>,
+    pub provisioning_service: Arc<ProvisioningService>,
+    pub track_service: Arc<ShipTrackService>,
+    pub flight_service: Arc<FlightService>,
+    /// 实时推送客户端的延迟统计
+    pub live_clients: Arc<LiveClients>,
+    /// 无人机最近的位置和flight状态
+    pub last_known: Arc<LastKnownState>,
+    /// SSE断线重连时补发的位置事件
+    pub history: Arc<LocationHistory>,
+}
+
+/// REST接口路由
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/status/mqtt", get(mqtt_status_handler))
+        .route("/api/status/clients", get(live_clients_handler))
+        .route("/api/dead_letters", get(list_dead_letters))
+        .route(
+            "/api/dead_letters/{id}",
+            get(get_dead_letter).patch(update_dead_letter).delete(delete_dead_letter),
+        )
+        .route("/api/dead_letters/{id}/replay", post(replay_dead_letter))
+        .route("/api/drones", get(list_drones).post(create_drone))
+        .route(
+            "/api/drones/{serial}",
+            get(get_drone).patch(update_drone).delete(delete_drone),
+        )
+        .route("/api/drones/{serial}/end_flight", post(end_drone_flight))
+        .route("/api/drone_states", get(list_drone_states))
+        .route("/api/drone_states/{serial}", get(get_drone_state))
+        .route("/api/tracks", get(list_tracks).post(create_track))
+        .route("/api/tracks/latest", get(get_latest_track))
+        .route("/api/tracks/{id}", get(get_track).delete(delete_track))
+        .route("/api/tracks/{id}/coordinates", post(append_track_coordinates))
+        .route("/api/flights/{id}/track", get(get_flight_track))
+        .route("/api/flights/{id}/metrics", get(get_flight_metrics))
+        .route("/api/geo/tracks/intersecting", post(intersecting_tracks))
+        .route("/api/geo/tracks/area", get(tracks_in_area))
+        .route("/api/geo/drones/nearby", get(nearby_drones))
+}
@@ -0,0 +1,16 @@
+use std::sync::Arc;
+use axum::{extract::State, Json};
+
+use crate::live::LiveClientDto;
+use crate::mqtt::ConnectionStatus;
+use super::ApiState;
+
+/// 查询当前MQTT连接状态
+pub async fn mqtt_status_handler(State(state): State<Arc<ApiState>>) -> Json<ConnectionStatus> {
+    Json(state.mqtt_status.borrow().clone())
+}
+
+/// 查询当前WebSocket和SSE客户端及其丢失消息的统计
+pub async fn live_clients_handler(State(state): State<Arc<ApiState>>) -> Json<Vec<LiveClientDto>> {
+    Json(state.live_clients.list())
+}
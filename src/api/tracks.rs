@@ -0,0 +1,349 @@
+use std::sync::Arc;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::Deserialize;
+
+use crate::model::geo::validate_position;
+use crate::model::ship_track::{
+    ShipTrackRequestDto, ShipTrackResponseDto, ShipTrackSummaryDto, TrackPoint, TrackPointDto, UpdateShipTrackPayload,
+};
+use crate::service::ship_track_service::{RolloverReason, TrackFilter};
+use super::error::{parse_object_id, ApiError, ApiJson, ApiQuery};
+use super::ApiState;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct ListTracksQuery {
+    pub drone_id: Option<String>,
+    pub flight_id: Option<String>,
+    /// 与 `to` 一起筛选时间范围有交集的航迹
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub skip: Option<u64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LatestTrackQuery {
+    pub drone_id: Option<String>,
+}
+
+/// 分页列出航迹摘要，按最后更新时间倒序，航迹点通过 `GET /api/tracks/{id}` 获取
+pub async fn list_tracks(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(query): ApiQuery<ListTracksQuery>,
+) -> Result<Json<Vec<ShipTrackSummaryDto>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::BadRequest(format!("limit 必须在1到{}之间", MAX_PAGE_SIZE)));
+    }
+    if matches!((query.from, query.to), (Some(from), Some(to)) if from > to) {
+        return Err(ApiError::BadRequest("from 不能晚于 to".to_string()));
+    }
+    let filter = TrackFilter {
+        drone_id: query.drone_id,
+        flight_id: query.flight_id.as_deref().map(parse_object_id).transpose()?,
+        from: query.from.map(Into::into),
+        to: query.to.map(Into::into),
+    };
+    let tracks = state.track_service.list(&filter, query.skip.unwrap_or(0), limit).await?;
+    Ok(Json(tracks.into_iter().map(Into::into).collect()))
+}
+
+/// 创建航迹
+pub async fn create_track(
+    State(state): State<Arc<ApiState>>,
+    ApiJson(body): ApiJson<ShipTrackRequestDto>,
+) -> Result<(StatusCode, Json<ShipTrackResponseDto>), ApiError> {
+    if body.total_points as usize != body.coordinates.len() {
+        return Err(ApiError::BadRequest(format!(
+            "totalPoints 为{}，但 coordinates 中有{}个航迹点",
+            body.total_points,
+            body.coordinates.len()
+        )));
+    }
+    let points = track_points(body.coordinates)?;
+    let track = state.track_service.create(body.drone_id, points).await?;
+    info!("已创建航迹: {}", track.id);
+    Ok((StatusCode::CREATED, Json(track.into())))
+}
+
+/// 按ID查询航迹
+pub async fn get_track(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ShipTrackResponseDto>, ApiError> {
+    let track_id = parse_object_id(&id)?;
+    let track = state
+        .track_service
+        .get(&track_id)
+        .await?
+        .ok_or_else(|| track_not_found(&id))?;
+    Ok(Json(track.into()))
+}
+
+/// 最近更新的航迹，可通过 `drone_id` 限定无人机
+pub async fn get_latest_track(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(query): ApiQuery<LatestTrackQuery>,
+) -> Result<Json<ShipTrackResponseDto>, ApiError> {
+    let track = state
+        .track_service
+        .get_latest(query.drone_id.as_deref())
+        .await?
+        .ok_or_else(|| ApiError::NotFound("没有航迹".to_string()))?;
+    Ok(Json(track.into()))
+}
+
+/// 向航迹追加航迹点，返回更新后的航迹
+pub async fn append_track_coordinates(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+    ApiJson(body): ApiJson<UpdateShipTrackPayload>,
+) -> Result<Json<ShipTrackResponseDto>, ApiError> {
+    let track_id = parse_object_id(&id)?;
+    if body.coordinates_to_add.is_empty() {
+        return Err(ApiError::BadRequest("coordinatesToAdd 不能为空".to_string()));
+    }
+    let points = track_points(body.coordinates_to_add)?;
+    if let Some(reason) = state.track_service.oversized_batch(&points)? {
+        return Err(ApiError::Unprocessable(format!("coordinatesToAdd 超过单个航迹的上限: {}", reason)));
+    }
+    // 与MQTT写入使用相同的上限，REST创建的航迹不按时间间隔分段
+    let now = Utc::now().into();
+    check_capacity(state.track_service.rollover_reason(&track_id, &points, now).await?)?;
+    let result = state.track_service.append_coordinates_and_update(&track_id, points).await?;
+    if result.matched_count == 0 {
+        return Err(track_not_found(&id));
+    }
+    get_track(State(state), Path(id)).await
+}
+
+/// 删除航迹
+pub async fn delete_track(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let track_id = parse_object_id(&id)?;
+    let track = state
+        .track_service
+        .delete(&track_id)
+        .await?
+        .ok_or_else(|| track_not_found(&id))?;
+    // 删除的可能是无人机当前的航迹，下一条遥测重新确认
+    if let Some(drone_id) = &track.drone_id {
+        state.provisioning_service.forget(drone_id);
+    }
+    info!("已删除航迹: {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 转换并校验请求中的航迹点，航迹点按时间排序存储，没有时间的以请求时间为准
+fn track_points(points: Vec<TrackPointDto>) -> Result<Vec<TrackPoint>, ApiError> {
+    let now = Utc::now();
+    points
+        .into_iter()
+        .map(|mut point| {
+            validate_position(&[point.longitude, point.latitude]).map_err(ApiError::BadRequest)?;
+            point.timestamp.get_or_insert(now);
+            Ok(point.into())
+        })
+        .collect()
+}
+
+/// 航迹放不下新的航迹点时返回冲突，时间间隔过长不影响REST追加
+fn check_capacity(rollover: Option<RolloverReason>) -> Result<(), ApiError> {
+    match rollover {
+        Some(reason @ (RolloverReason::MaxPoints | RolloverReason::MaxBytes)) => {
+            Err(ApiError::Conflict(format!("航迹已满（{}），请创建新的航迹", reason)))
+        }
+        Some(RolloverReason::Gap) | None => Ok(()),
+    }
+}
+
+fn track_not_found(id: &str) -> ApiError {
+    ApiError::NotFound(format!("航迹不存在: {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::extract::{FromRequest, Request};
+    use axum::response::IntoResponse;
+    use crate::config::SegmentsConfig;
+    use crate::live::{LastKnownState, LiveClients, LocationHistory};
+    use crate::mqtt::{ConnectionMonitor, Dispatcher, MessageRouter};
+    use crate::service::dead_letter_service::DeadLetterService;
+    use crate::service::drone_service::DroneService;
+    use crate::service::flight_service::FlightService;
+    use crate::service::provisioning_service::ProvisioningService;
+    use crate::service::ship_track_service::ShipTrackService;
+
+    /// 数据库不可达的接口状态，只能走到访问数据库之前的校验
+    async fn state(limits: SegmentsConfig) -> Arc<ApiState> {
+        let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=50")
+            .await
+            .unwrap();
+        let db = client.database("test");
+        let drone_service = Arc::new(DroneService::new(db.collection("drones")));
+        let track_service = Arc::new(ShipTrackService::new(db.collection("tracks"), limits));
+        let flight_service = Arc::new(FlightService::new(db.collection("flights"), db.collection("samples")));
+        let dead_letter_service = Arc::new(DeadLetterService::new(db.collection("dead_letters")));
+        Arc::new(ApiState {
+            mqtt_status: ConnectionMonitor::new().subscribe(),
+            dispatcher: Arc::new(Dispatcher::new(Arc::new(MessageRouter::new()), dead_letter_service.clone(), 1, 1)),
+            dead_letter_service,
+            provisioning_service: Arc::new(ProvisioningService::new(
+                drone_service.clone(),
+                track_service.clone(),
+                flight_service.clone(),
+                false,
+            )),
+            drone_service,
+            track_service,
+            flight_service,
+            live_clients: Arc::new(LiveClients::default()),
+            last_known: Arc::new(LastKnownState::default()),
+            history: Arc::new(LocationHistory::new(1, None)),
+        })
+    }
+
+    fn points(count: usize) -> Vec<TrackPointDto> {
+        (0..count)
+            .map(|i| {
+                let point = serde_json::json!({"longitude": 120.0 + i as f64 * 1e-4, "latitude": 30.0});
+                serde_json::from_value(point).unwrap()
+            })
+            .collect()
+    }
+
+    fn list_query(limit: Option<i64>, from: Option<&str>, to: Option<&str>) -> ListTracksQuery {
+        ListTracksQuery {
+            drone_id: None,
+            flight_id: None,
+            from: from.map(|time| time.parse().unwrap()),
+            to: to.map(|time| time.parse().unwrap()),
+            skip: None,
+            limit,
+        }
+    }
+
+    /// 错误的状态码和 `{"error": ...}` 响应体
+    async fn response(error: ApiError) -> (StatusCode, String) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (status, body["error"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn create_rejects_total_points_mismatch() {
+        let state = state(SegmentsConfig::default()).await;
+        let body = ShipTrackRequestDto { drone_id: None, coordinates: points(2), total_points: 3 };
+        let error = create_track(State(state), ApiJson(body)).await.unwrap_err();
+        let (status, message) = response(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("totalPoints"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn create_rejects_out_of_range_coordinate() {
+        let state = state(SegmentsConfig::default()).await;
+        let mut coordinates = points(2);
+        coordinates[1].latitude = 91.0;
+        let body = ShipTrackRequestDto { drone_id: None, coordinates, total_points: 2 };
+        let error = create_track(State(state), ApiJson(body)).await.unwrap_err();
+        assert_eq!(response(error).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn invalid_id_is_bad_request() {
+        let state = state(SegmentsConfig::default()).await;
+        let error = get_track(State(state.clone()), Path("abc".to_string())).await.unwrap_err();
+        assert_eq!(response(error).await.0, StatusCode::BAD_REQUEST);
+        let error = delete_track(State(state), Path("abc".to_string())).await.unwrap_err();
+        assert_eq!(response(error).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn list_validates_page_and_time_range() {
+        let state = state(SegmentsConfig::default()).await;
+        for query in [
+            list_query(Some(0), None, None),
+            list_query(Some(MAX_PAGE_SIZE + 1), None, None),
+            list_query(None, Some("2026-01-02T00:00:00Z"), Some("2026-01-01T00:00:00Z")),
+        ] {
+            let error = list_tracks(State(state.clone()), ApiQuery(query)).await.unwrap_err();
+            assert_eq!(response(error).await.0, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn append_rejects_oversized_batch() {
+        let limits = SegmentsConfig { max_points: 2, ..SegmentsConfig::default() };
+        let state = state(limits).await;
+        let id = mongodb::bson::oid::ObjectId::new().to_hex();
+        let body = UpdateShipTrackPayload { coordinates_to_add: points(3) };
+        let error = append_track_coordinates(State(state.clone()), Path(id.clone()), ApiJson(body)).await.unwrap_err();
+        assert_eq!(response(error).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = UpdateShipTrackPayload { coordinates_to_add: Vec::new() };
+        let error = append_track_coordinates(State(state), Path(id), ApiJson(body)).await.unwrap_err();
+        assert_eq!(response(error).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn points_without_timestamp_use_request_time() {
+        let mut coordinates = points(2);
+        let sampled = "2026-01-01T00:00:00Z".parse().unwrap();
+        coordinates[0].timestamp = Some(sampled);
+        let converted = track_points(coordinates).unwrap();
+        assert_eq!(converted[0].timestamp, Some(sampled.into()));
+        assert!(converted[1].timestamp.unwrap() > converted[0].timestamp.unwrap());
+    }
+
+    #[test]
+    fn full_track_is_conflict() {
+        assert!(matches!(check_capacity(Some(RolloverReason::MaxPoints)), Err(ApiError::Conflict(_))));
+        assert!(matches!(check_capacity(Some(RolloverReason::MaxBytes)), Err(ApiError::Conflict(_))));
+        assert!(check_capacity(Some(RolloverReason::Gap)).is_ok());
+        assert!(check_capacity(None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn error_status_mapping() {
+        assert_eq!(response(track_not_found("abc")).await, (StatusCode::NOT_FOUND, "航迹不存在: abc".to_string()));
+        let conflict = check_capacity(Some(RolloverReason::MaxPoints)).unwrap_err();
+        assert_eq!(response(conflict).await.0, StatusCode::CONFLICT);
+        assert_eq!(response(ApiError::Unprocessable("x".into())).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response(ApiError::Internal("x".into())).await.0, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn malformed_body_is_rejected_as_json() {
+        let request = |body: &str| {
+            Request::builder()
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        // 字段类型错误为422，JSON语法错误为400
+        let error = ApiJson::<ShipTrackRequestDto>::from_request(request(r#"{"coordinates": [], "totalPoints": "2"}"#), &())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(response(error).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        let error = ApiJson::<ShipTrackRequestDto>::from_request(request("{"), &()).await.err().unwrap();
+        assert_eq!(response(error).await.0, StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,34 +1,712 @@
 use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::mqtt::TopicPattern;
+
+/// 未指定 `CONFIG_PATH` 时使用的配置文件路径
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// 分段字节数上限，低于MongoDB 16MB的文档上限，为更新时的估算偏差留出余量
+const MAX_SEGMENT_BYTES: i64 = 15 * 1024 * 1024;
 
 /// 应用程序配置结构
-#[derive(Debug, Clone)]
+///
+/// 加载顺序：内置默认值 -> TOML配置文件 -> 环境变量覆盖，最后统一校验。
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct AppConfig {
-    pub mongodb_uri: String,
-    pub mqtt_host: String,
-    pub mqtt_port: u16,
-    pub mqtt_username: String,
-    pub mqtt_password: String,
+    pub mqtt: MqttConfig,
+    pub mongo: MongoConfig,
+    pub http: HttpConfig,
+    pub topics: TopicsConfig,
+    pub channels: ChannelsConfig,
+    pub dispatcher: DispatcherConfig,
+    pub cluster: ClusterConfig,
+    pub segments: SegmentsConfig,
+    pub alerts: AlertsConfig,
+}
+
+/// MQTT传输方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttTransport {
+    /// 明文TCP
+    Tcp,
+    /// TLS（可选客户端证书双向认证）
+    Tls,
+    /// 明文WebSocket
+    Ws,
+    /// 基于TLS的WebSocket
+    Wss,
+}
+
+impl MqttTransport {
+    /// 是否需要TLS配置
+    pub fn uses_tls(self) -> bool {
+        matches!(self, MqttTransport::Tls | MqttTransport::Wss)
+    }
+}
+
+impl FromStr for MqttTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(MqttTransport::Tcp),
+            "tls" => Ok(MqttTransport::Tls),
+            "ws" => Ok(MqttTransport::Ws),
+            "wss" => Ok(MqttTransport::Wss),
+            other => Err(format!("未知的传输方式 \"{}\"，可选值: tcp、tls、ws、wss", other)),
+        }
+    }
+}
+
+/// MQTT协议版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum MqttProtocol {
+    /// MQTT 3.1.1
+    #[serde(rename = "3.1.1")]
+    V311,
+    /// MQTT 5，可读取content-type、关联数据、用户属性和消息过期时间
+    #[serde(rename = "5")]
+    V5,
+}
+
+impl FromStr for MqttProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "3.1.1" | "4" | "v4" => Ok(MqttProtocol::V311),
+            "5" | "v5" => Ok(MqttProtocol::V5),
+            other => Err(format!("未知的协议版本 \"{}\"，可选值: 3.1.1、5", other)),
+        }
+    }
+}
+
+/// MQTT连接配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    /// 客户端ID，为空时使用用户名
+    pub client_id: Option<String>,
+    pub protocol: MqttProtocol,
+    pub transport: MqttTransport,
+    /// WebSocket传输时的请求路径
+    pub ws_path: String,
+    /// CA证书路径（tls/wss）
     pub ca_cert_path: String,
+    /// 客户端证书路径（PEM），与 `client_key_path` 同时配置时启用双向TLS
+    pub client_cert_path: Option<String>,
+    /// 客户端私钥路径（PEM）
+    pub client_key_path: Option<String>,
+    pub keep_alive_secs: u64,
+    pub clean_session: bool,
+    /// MQTT 5 断开后服务端保留会话的时间（秒），`clean_session = false` 时生效
+    pub session_expiry_secs: u32,
+    /// 单个主题订阅失败时的最大重试次数
+    pub subscribe_retries: u8,
+    /// 重连退避的初始等待时间（毫秒）
+    pub reconnect_initial_delay_ms: u64,
+    /// 重连退避的最大等待时间（秒）
+    pub reconnect_max_delay_secs: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 8883,
+            username: String::new(),
+            password: String::new(),
+            client_id: None,
+            protocol: MqttProtocol::V311,
+            transport: MqttTransport::Tls,
+            ws_path: "/mqtt".to_string(),
+            ca_cert_path: "ca.crt".to_string(),
+            client_cert_path: None,
+            client_key_path: None,
+            keep_alive_secs: 5,
+            clean_session: false,
+            session_expiry_secs: 3600,
+            subscribe_retries: 3,
+            reconnect_initial_delay_ms: 500,
+            reconnect_max_delay_secs: 60,
+        }
+    }
+}
+
+/// MongoDB配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongoConfig {
+    pub uri: String,
+    pub database: String,
+    pub track_collection: String,
+    pub flight_collection: String,
+    /// flight状态采样的时间序列集合，启动时自动创建
+    pub flight_sample_collection: String,
+    /// 被拒绝消息的死信集合
+    pub dead_letter_collection: String,
+    /// 无人机登记集合
+    pub drone_collection: String,
+}
+
+impl Default for MongoConfig {
+    fn default() -> Self {
+        Self {
+            uri: String::new(),
+            database: "shipTracking".to_string(),
+            track_collection: "trackSegments".to_string(),
+            flight_collection: "flights".to_string(),
+            flight_sample_collection: "flightSamples".to_string(),
+            dead_letter_collection: "deadLetters".to_string(),
+            drone_collection: "drones".to_string(),
+        }
+    }
+}
+
+/// HTTP服务配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind_address: String,
+    /// SSE、WebSocket和REST接口共用的端口
+    pub port: u16,
+    /// 收到SIGTERM/SIGINT后等待连接关闭和消息写入完成的最长时间
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0".to_string(),
+            port: 8080,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+/// 订阅主题配置
+///
+/// 主题使用路由模式语法，`{id}` 捕获无人机序列号，订阅时替换为 `+`；
+/// 可选的 `{*suffix}` 捕获主题后缀，用于声明消息版本和编码
+/// （如 `drone/A1/location/v2`、`drone/A1/state/cbor`，编码可选 json、cbor、msgpack、pb）。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopicsConfig {
+    pub location: String,
+    pub state: String,
+    /// 订阅使用的QoS等级（0、1、2）
+    pub qos: u8,
+}
+
+impl Default for TopicsConfig {
+    fn default() -> Self {
+        Self {
+            location: "drone/{id}/location/{*suffix}".to_string(),
+            state: "drone/{id}/state/{*suffix}".to_string(),
+            qos: 1,
+        }
+    }
+}
+
+/// 内部通道容量配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelsConfig {
+    /// WebSocket推送flight消息的广播通道容量
+    pub flight_broadcast_capacity: usize,
+    /// SSE推送位置消息的广播通道容量
+    pub location_broadcast_capacity: usize,
+    /// WebSocket推送告警的广播通道容量
+    pub alert_broadcast_capacity: usize,
+    /// 每架无人机保留的最近位置事件数，SSE客户端断线重连时据此补发
+    pub location_history: usize,
+    /// MQTT客户端请求队列容量
+    pub mqtt_request_capacity: usize,
+}
+
+impl Default for ChannelsConfig {
+    fn default() -> Self {
+        Self {
+            flight_broadcast_capacity: 100,
+            location_broadcast_capacity: 100,
+            alert_broadcast_capacity: 100,
+            location_history: 256,
+            mqtt_request_capacity: 10,
+        }
+    }
+}
+
+/// MQTT消息分发配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatcherConfig {
+    /// 并发处理消息的工作任务数
+    pub workers: usize,
+    /// 每个工作任务的队列深度，队列满时对MQTT事件循环施加背压
+    pub queue_depth: usize,
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            queue_depth: 64,
+        }
+    }
+}
+
+/// 航迹分段配置
+///
+/// 当前分段的点数或字节数达到上限，或无人机静默超过间隔阈值时，自动开启同一flight的下一个分段。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SegmentsConfig {
+    /// 单个分段的最大航迹点数
+    pub max_points: u32,
+    /// 单个分段文档的最大字节数（航迹点和GeoJSON各算一份），需低于MongoDB 16MB的文档上限
+    pub max_bytes: i64,
+    /// 两次位置消息的最大间隔（秒），超过后开启新分段
+    pub max_gap_secs: u64,
+}
+
+impl Default for SegmentsConfig {
+    fn default() -> Self {
+        Self {
+            max_points: 5000,
+            max_bytes: 4 * 1024 * 1024,
+            max_gap_secs: 300,
+        }
+    }
+}
+
+/// 告警配置，告警通过WebSocket的 `alerts` 频道推送
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// 电量低于此值（百分比）时告警，回升到此值以上后再次低于才会重新告警
+    pub low_battery: f64,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self { low_battery: 20.0 }
+    }
+}
+
+/// 多实例部署配置
+///
+/// 设置 `share_group` 后遥测主题以 `$share/<group>/...` 共享订阅，每条消息只由一个实例处理；
+/// 各实例把实时数据转发到 `<fanout_prefix>/<instance_id>/<kind>/<drone_id>`，
+/// 并订阅其他实例的转发，使每个实例的SSE/WebSocket客户端都能看到整个机队。
+///
+/// broker按消息分配共享订阅，同一无人机相邻的消息可能由不同实例并发处理，不再保证按到达顺序处理：
+/// 航迹点按采样时间排序写入，实时推送和最近状态可能短暂出现较早的消息。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// 共享订阅组名，未配置时单实例运行
+    pub share_group: Option<String>,
+    /// 实例ID，附加到MQTT客户端ID后；未配置时启动时随机生成（持久会话无法跨重启恢复）
+    pub instance_id: Option<String>,
+    /// 实例间转发实时数据的主题前缀
+    pub fanout_prefix: String,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            share_group: None,
+            instance_id: None,
+            fanout_prefix: "mqtt_drone/live".to_string(),
+        }
+    }
+}
+
+impl ClusterConfig {
+    /// 是否以共享订阅方式多实例运行
+    pub fn enabled(&self) -> bool {
+        self.share_group.is_some()
+    }
+
+    /// 本实例ID，`load` 之后多实例模式下一定存在
+    pub fn instance_id(&self) -> &str {
+        self.instance_id.as_deref().unwrap_or_default()
+    }
+}
+
+/// 配置加载错误，错误信息中包含出错的配置项
+#[derive(Debug)]
+pub enum ConfigError {
+    /// 配置文件无法读取
+    Io { path: PathBuf, source: std::io::Error },
+    /// 配置文件格式错误
+    Parse { path: PathBuf, source: toml::de::Error },
+    /// 环境变量的值无法解析
+    Env { var: &'static str, field: &'static str, message: String },
+    /// 配置项取值不合法
+    Invalid { field: &'static str, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "无法读取配置文件 {}: {}", path.display(), source)
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "配置文件 {} 格式错误: {}", path.display(), source)
+            }
+            ConfigError::Env { var, field, message } => {
+                write!(f, "环境变量 {} (对应配置项 {}) 无效: {}", var, field, message)
+            }
+            ConfigError::Invalid { field, message } => {
+                write!(f, "配置项 {} 无效: {}", field, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
 }
 
 impl AppConfig {
-    /// 从环境变量加载配置
-    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
-        let mongodb_uri = env::var("MONGODB_URI")?;
-        let mqtt_host = env::var("MQTT_HOST")?;
-        let mqtt_port_str = env::var("MQTT_PORT")?;
-        let mqtt_port: u16 = mqtt_port_str.parse()?;
-        let mqtt_username = env::var("MQTT_USERNAME")?;
-        let mqtt_password = env::var("MQTT_PASSWORD")?;
-        let ca_cert_path = env::var("CA_CERT_PATH")?;
-
-        Ok(Self {
-            mongodb_uri,
-            mqtt_host,
-            mqtt_port,
-            mqtt_username,
-            mqtt_password,
-            ca_cert_path,
+    /// 加载配置：读取 `CONFIG_PATH` 指定的TOML文件（默认 `config.toml`），
+    /// 再应用环境变量覆盖并校验
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match env::var("CONFIG_PATH") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            // 未显式指定时配置文件是可选的
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => Self::default(),
+        };
+        config.apply_env_overrides()?;
+        if config.cluster.enabled() && config.cluster.instance_id.is_none() {
+            config.cluster.instance_id = Some(format!("{:08x}", rand::random::<u32>()));
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 从TOML文件读取配置，未出现的配置项使用默认值
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
         })
     }
+
+    /// 使用环境变量覆盖配置文件中的值
+    fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        override_string(&mut self.mqtt.host, "MQTT_HOST");
+        override_parsed(&mut self.mqtt.port, "MQTT_PORT", "mqtt.port")?;
+        override_string(&mut self.mqtt.username, "MQTT_USERNAME");
+        override_string(&mut self.mqtt.password, "MQTT_PASSWORD");
+        if let Ok(client_id) = env::var("MQTT_CLIENT_ID") {
+            self.mqtt.client_id = Some(client_id);
+        }
+        override_parsed(&mut self.mqtt.protocol, "MQTT_PROTOCOL", "mqtt.protocol")?;
+        override_parsed(&mut self.mqtt.transport, "MQTT_TRANSPORT", "mqtt.transport")?;
+        override_string(&mut self.mqtt.ws_path, "MQTT_WS_PATH");
+        override_string(&mut self.mqtt.ca_cert_path, "CA_CERT_PATH");
+        if let Ok(path) = env::var("CLIENT_CERT_PATH") {
+            self.mqtt.client_cert_path = Some(path);
+        }
+        if let Ok(path) = env::var("CLIENT_KEY_PATH") {
+            self.mqtt.client_key_path = Some(path);
+        }
+        override_parsed(&mut self.mqtt.keep_alive_secs, "MQTT_KEEP_ALIVE_SECS", "mqtt.keep_alive_secs")?;
+        override_parsed(&mut self.mqtt.clean_session, "MQTT_CLEAN_SESSION", "mqtt.clean_session")?;
+        override_parsed(
+            &mut self.mqtt.session_expiry_secs,
+            "MQTT_SESSION_EXPIRY_SECS",
+            "mqtt.session_expiry_secs",
+        )?;
+        override_parsed(&mut self.mqtt.subscribe_retries, "MQTT_SUBSCRIBE_RETRIES", "mqtt.subscribe_retries")?;
+        override_parsed(
+            &mut self.mqtt.reconnect_initial_delay_ms,
+            "MQTT_RECONNECT_INITIAL_DELAY_MS",
+            "mqtt.reconnect_initial_delay_ms",
+        )?;
+        override_parsed(
+            &mut self.mqtt.reconnect_max_delay_secs,
+            "MQTT_RECONNECT_MAX_DELAY_SECS",
+            "mqtt.reconnect_max_delay_secs",
+        )?;
+
+        override_string(&mut self.mongo.uri, "MONGODB_URI");
+        override_string(&mut self.mongo.database, "MONGODB_DATABASE");
+        override_string(&mut self.mongo.track_collection, "MONGODB_TRACK_COLLECTION");
+        override_string(&mut self.mongo.flight_collection, "MONGODB_FLIGHT_COLLECTION");
+        override_string(&mut self.mongo.dead_letter_collection, "MONGODB_DEAD_LETTER_COLLECTION");
+        override_string(&mut self.mongo.drone_collection, "MONGODB_DRONE_COLLECTION");
+        override_string(&mut self.mongo.flight_sample_collection, "MONGODB_FLIGHT_SAMPLE_COLLECTION");
+
+        override_string(&mut self.http.bind_address, "HTTP_BIND_ADDRESS");
+        override_parsed(&mut self.http.port, "HTTP_PORT", "http.port")?;
+        override_parsed(
+            &mut self.http.shutdown_timeout_secs,
+            "HTTP_SHUTDOWN_TIMEOUT_SECS",
+            "http.shutdown_timeout_secs",
+        )?;
+
+        override_string(&mut self.topics.location, "TOPIC_LOCATION");
+        override_string(&mut self.topics.state, "TOPIC_STATE");
+        override_parsed(&mut self.topics.qos, "TOPIC_QOS", "topics.qos")?;
+        if let Ok(group) = env::var("MQTT_SHARE_GROUP") {
+            self.cluster.share_group = Some(group);
+        }
+        if let Ok(instance_id) = env::var("INSTANCE_ID") {
+            self.cluster.instance_id = Some(instance_id);
+        }
+        override_string(&mut self.cluster.fanout_prefix, "FANOUT_TOPIC_PREFIX");
+        override_parsed(&mut self.segments.max_points, "SEGMENT_MAX_POINTS", "segments.max_points")?;
+        override_parsed(&mut self.segments.max_bytes, "SEGMENT_MAX_BYTES", "segments.max_bytes")?;
+        override_parsed(&mut self.segments.max_gap_secs, "SEGMENT_MAX_GAP_SECS", "segments.max_gap_secs")?;
+
+        override_parsed(
+            &mut self.channels.flight_broadcast_capacity,
+            "FLIGHT_BROADCAST_CAPACITY",
+            "channels.flight_broadcast_capacity",
+        )?;
+        override_parsed(
+            &mut self.channels.location_broadcast_capacity,
+            "LOCATION_BROADCAST_CAPACITY",
+            "channels.location_broadcast_capacity",
+        )?;
+        override_parsed(
+            &mut self.channels.alert_broadcast_capacity,
+            "ALERT_BROADCAST_CAPACITY",
+            "channels.alert_broadcast_capacity",
+        )?;
+        override_parsed(&mut self.channels.location_history, "LOCATION_HISTORY", "channels.location_history")?;
+        override_parsed(&mut self.alerts.low_battery, "ALERT_LOW_BATTERY", "alerts.low_battery")?;
+        override_parsed(
+            &mut self.channels.mqtt_request_capacity,
+            "MQTT_REQUEST_CAPACITY",
+            "channels.mqtt_request_capacity",
+        )?;
+
+        override_parsed(&mut self.dispatcher.workers, "DISPATCHER_WORKERS", "dispatcher.workers")?;
+        override_parsed(&mut self.dispatcher.queue_depth, "DISPATCHER_QUEUE_DEPTH", "dispatcher.queue_depth")?;
+        Ok(())
+    }
+
+    /// 校验配置，返回第一个不合法的配置项
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        require_non_empty("mqtt.host", &self.mqtt.host)?;
+        require_non_zero("mqtt.port", self.mqtt.port as u64)?;
+        require_non_empty("mqtt.username", &self.mqtt.username)?;
+        if let Some(client_id) = &self.mqtt.client_id {
+            require_non_empty("mqtt.client_id", client_id)?;
+        }
+        if matches!(self.mqtt.transport, MqttTransport::Ws | MqttTransport::Wss)
+            && !self.mqtt.ws_path.starts_with('/')
+        {
+            return Err(ConfigError::Invalid {
+                field: "mqtt.ws_path",
+                message: format!("必须以 / 开头，当前为 \"{}\"", self.mqtt.ws_path),
+            });
+        }
+        if self.mqtt.transport.uses_tls() {
+            require_non_empty("mqtt.ca_cert_path", &self.mqtt.ca_cert_path)?;
+            match (&self.mqtt.client_cert_path, &self.mqtt.client_key_path) {
+                (Some(cert), Some(key)) => {
+                    require_non_empty("mqtt.client_cert_path", cert)?;
+                    require_non_empty("mqtt.client_key_path", key)?;
+                }
+                (Some(_), None) => {
+                    return Err(ConfigError::Invalid {
+                        field: "mqtt.client_key_path",
+                        message: "配置了客户端证书但缺少私钥".to_string(),
+                    });
+                }
+                (None, Some(_)) => {
+                    return Err(ConfigError::Invalid {
+                        field: "mqtt.client_cert_path",
+                        message: "配置了客户端私钥但缺少证书".to_string(),
+                    });
+                }
+                (None, None) => {}
+            }
+        } else if self.mqtt.client_cert_path.is_some() || self.mqtt.client_key_path.is_some() {
+            return Err(ConfigError::Invalid {
+                field: "mqtt.transport",
+                message: "客户端证书仅在 tls 或 wss 传输下可用".to_string(),
+            });
+        }
+        require_non_zero("mqtt.keep_alive_secs", self.mqtt.keep_alive_secs)?;
+        require_non_zero("mqtt.subscribe_retries", self.mqtt.subscribe_retries as u64)?;
+        require_non_zero("mqtt.reconnect_initial_delay_ms", self.mqtt.reconnect_initial_delay_ms)?;
+        require_non_zero("mqtt.reconnect_max_delay_secs", self.mqtt.reconnect_max_delay_secs)?;
+        if self.mqtt.reconnect_initial_delay_ms > self.mqtt.reconnect_max_delay_secs * 1000 {
+            return Err(ConfigError::Invalid {
+                field: "mqtt.reconnect_initial_delay_ms",
+                message: "不能大于 mqtt.reconnect_max_delay_secs".to_string(),
+            });
+        }
+
+        require_non_empty("mongo.uri", &self.mongo.uri)?;
+        require_non_empty("mongo.database", &self.mongo.database)?;
+        require_non_empty("mongo.track_collection", &self.mongo.track_collection)?;
+        require_non_empty("mongo.flight_collection", &self.mongo.flight_collection)?;
+        require_non_empty("mongo.dead_letter_collection", &self.mongo.dead_letter_collection)?;
+        require_non_empty("mongo.drone_collection", &self.mongo.drone_collection)?;
+        require_non_empty("mongo.flight_sample_collection", &self.mongo.flight_sample_collection)?;
+
+        require_non_empty("http.bind_address", &self.http.bind_address)?;
+        require_non_zero("http.port", self.http.port as u64)?;
+        require_non_zero("http.shutdown_timeout_secs", self.http.shutdown_timeout_secs)?;
+
+        require_topic_pattern("topics.location", &self.topics.location)?;
+        require_topic_pattern("topics.state", &self.topics.state)?;
+        if self.topics.qos > 2 {
+            return Err(ConfigError::Invalid {
+                field: "topics.qos",
+                message: format!("必须是0、1或2，当前为{}", self.topics.qos),
+            });
+        }
+
+        require_non_zero("channels.flight_broadcast_capacity", self.channels.flight_broadcast_capacity as u64)?;
+        require_non_zero("channels.location_broadcast_capacity", self.channels.location_broadcast_capacity as u64)?;
+        require_non_zero("channels.alert_broadcast_capacity", self.channels.alert_broadcast_capacity as u64)?;
+        require_non_zero("channels.location_history", self.channels.location_history as u64)?;
+        if !(0.0..=100.0).contains(&self.alerts.low_battery) {
+            return Err(ConfigError::Invalid {
+                field: "alerts.low_battery",
+                message: format!("必须在0到100之间，当前为{}", self.alerts.low_battery),
+            });
+        }
+        require_non_zero("channels.mqtt_request_capacity", self.channels.mqtt_request_capacity as u64)?;
+
+        require_non_zero("dispatcher.workers", self.dispatcher.workers as u64)?;
+        require_non_zero("dispatcher.queue_depth", self.dispatcher.queue_depth as u64)?;
+
+        if let Some(group) = &self.cluster.share_group {
+            require_topic_level("cluster.share_group", group)?;
+        }
+        if let Some(instance_id) = &self.cluster.instance_id {
+            require_topic_level("cluster.instance_id", instance_id)?;
+        }
+        require_non_empty("cluster.fanout_prefix", &self.cluster.fanout_prefix)?;
+        if self.cluster.fanout_prefix.starts_with('$')
+            || self.cluster.fanout_prefix.contains(['+', '#', '{', '}'])
+        {
+            return Err(ConfigError::Invalid {
+                field: "cluster.fanout_prefix",
+                message: format!("不能包含通配符或以 $ 开头，当前为 \"{}\"", self.cluster.fanout_prefix),
+            });
+        }
+
+        require_non_zero("segments.max_points", self.segments.max_points as u64)?;
+        require_non_zero("segments.max_gap_secs", self.segments.max_gap_secs)?;
+        if !(1..=MAX_SEGMENT_BYTES).contains(&self.segments.max_bytes) {
+            return Err(ConfigError::Invalid {
+                field: "segments.max_bytes",
+                message: format!("必须在1到{}之间，当前为{}", MAX_SEGMENT_BYTES, self.segments.max_bytes),
+            });
+        }
+        Ok(())
+    }
+
+    /// MQTT客户端ID，未配置时使用用户名；多实例模式下附加实例ID，避免互相踢下线
+    pub fn mqtt_client_id(&self) -> String {
+        let base = self.mqtt.client_id.as_deref().unwrap_or(&self.mqtt.username);
+        if self.cluster.enabled() {
+            format!("{}-{}", base, self.cluster.instance_id())
+        } else {
+            base.to_string()
+        }
+    }
+}
+
+fn override_string(target: &mut String, var: &'static str) {
+    if let Ok(value) = env::var(var) {
+        *target = value;
+    }
+}
+
+fn override_parsed<T>(target: &mut T, var: &'static str, field: &'static str) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Ok(value) = env::var(var) {
+        *target = value.trim().parse().map_err(|e: T::Err| ConfigError::Env {
+            var,
+            field,
+            message: format!("无法解析 \"{}\": {}", value, e),
+        })?;
+    }
+    Ok(())
+}
+
+fn require_non_empty(field: &'static str, value: &str) -> Result<(), ConfigError> {
+    if value.trim().is_empty() {
+        return Err(ConfigError::Invalid {
+            field,
+            message: "不能为空".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn require_non_zero(field: &'static str, value: u64) -> Result<(), ConfigError> {
+    if value == 0 {
+        return Err(ConfigError::Invalid {
+            field,
+            message: "必须大于0".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// 校验只能作为单个主题层级使用的名称
+fn require_topic_level(field: &'static str, value: &str) -> Result<(), ConfigError> {
+    require_non_empty(field, value)?;
+    if value.contains(['/', '+', '#', '{', '}']) {
+        return Err(ConfigError::Invalid {
+            field,
+            message: format!("不能包含 / + # {{ }}，当前为 \"{}\"", value),
+        });
+    }
+    Ok(())
+}
+
+/// 校验主题路由模式，处理器依赖其中的 `{id}` 捕获
+fn require_topic_pattern(field: &'static str, pattern: &str) -> Result<(), ConfigError> {
+    let parsed = TopicPattern::parse(pattern).map_err(|e| ConfigError::Invalid {
+        field,
+        message: e.message,
+    })?;
+    if !parsed.has_capture("id") {
+        return Err(ConfigError::Invalid {
+            field,
+            message: format!("主题模式 \"{}\" 缺少 {{id}} 捕获", pattern),
+        });
+    }
+    Ok(())
 }
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use log::warn;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::model::drone::DroneSnapshotDto;
+use crate::mqtt::LiveKind;
+
+/// 一架无人机最近的实时事件，内容与推送给客户端的JSON一致
+#[derive(Debug, Clone, Default)]
+struct CachedDrone {
+    location: Option<Arc<str>>,
+    state: Option<Arc<str>>,
+}
+
+/// 无人机最近的位置和flight状态，格式与实时事件一致
+#[derive(Debug, Serialize)]
+pub struct DroneStateDto {
+    #[serde(rename = "droneId")]
+    pub drone_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<Value>,
+}
+
+/// 新连接建立后首先收到的事件，包含其关注的无人机最近的状态
+#[derive(Debug, Serialize)]
+pub struct SnapshotEventDto {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub drones: Vec<DroneStateDto>,
+}
+
+impl SnapshotEventDto {
+    pub fn new(drones: Vec<DroneStateDto>) -> Self {
+        SnapshotEventDto { kind: "snapshot", drones }
+    }
+}
+
+/// 每架无人机最近一次的位置和flight状态事件
+///
+/// 由 [`LiveFanout`](crate::mqtt::LiveFanout) 在推送时更新，多实例部署时也包含其他实例转发的事件。
+/// 启动时从数据库预热，之后只保存在内存中。
+#[derive(Default)]
+pub struct LastKnownState {
+    drones: RwLock<HashMap<String, CachedDrone>>,
+}
+
+impl LastKnownState {
+    /// 记录一条实时事件，告警不缓存
+    pub fn record(&self, kind: LiveKind, drone_id: &str, payload: &Arc<str>) {
+        let mut drones = self.drones.write().unwrap();
+        let drone = drones.entry(drone_id.to_string()).or_default();
+        match kind {
+            LiveKind::Location => drone.location = Some(payload.clone()),
+            LiveKind::Flight => drone.state = Some(payload.clone()),
+            LiveKind::Alert => {}
+        }
+    }
+
+    /// 用数据库中的最新状态填充缓存，已经收到实时事件的部分不覆盖
+    pub fn warm(&self, snapshots: Vec<DroneSnapshotDto>) -> usize {
+        let mut drones = self.drones.write().unwrap();
+        let mut warmed = 0;
+        for snapshot in snapshots {
+            let drone = drones.entry(snapshot.drone_id).or_default();
+            if drone.location.is_none() {
+                drone.location = snapshot.location.as_ref().and_then(to_payload);
+            }
+            if drone.state.is_none() {
+                drone.state = snapshot.state.as_ref().and_then(to_payload);
+            }
+            warmed += 1;
+        }
+        warmed
+    }
+
+    /// 删除无人机后清除其缓存，之后不再出现在快照中；多实例部署时只清除本实例的缓存
+    pub fn remove(&self, drone_id: &str) {
+        self.drones.write().unwrap().remove(drone_id);
+    }
+
+    /// 一架无人机最近的状态
+    pub fn get(&self, drone_id: &str) -> Option<DroneStateDto> {
+        let drones = self.drones.read().unwrap();
+        drones.get(drone_id).map(|drone| to_dto(drone_id, drone, |_| true))
+    }
+
+    /// 按序列号排序的最近状态，`include` 决定每架无人机包含哪些事件，
+    /// 没有包含任何事件的无人机不出现在结果中
+    pub fn snapshot(&self, include: impl Fn(LiveKind, &str) -> bool) -> Vec<DroneStateDto> {
+        let drones = self.drones.read().unwrap();
+        let mut states: Vec<DroneStateDto> = drones
+            .iter()
+            .map(|(drone_id, drone)| to_dto(drone_id, drone, |kind| include(kind, drone_id)))
+            .filter(|state| state.location.is_some() || state.state.is_some())
+            .collect();
+        states.sort_by(|a, b| a.drone_id.cmp(&b.drone_id));
+        states
+    }
+}
+
+fn to_payload<T: Serialize>(event: &T) -> Option<Arc<str>> {
+    serde_json::to_string(event)
+        .inspect_err(|e| warn!("缓存无人机状态失败: {}", e))
+        .ok()
+        .map(Into::into)
+}
+
+fn to_dto(drone_id: &str, drone: &CachedDrone, include: impl Fn(LiveKind) -> bool) -> DroneStateDto {
+    let parse = |kind: LiveKind, payload: &Option<Arc<str>>| {
+        payload
+            .as_deref()
+            .filter(|_| include(kind))
+            .and_then(|payload| serde_json::from_str(payload).ok())
+    };
+    DroneStateDto {
+        drone_id: drone_id.to_string(),
+        location: parse(LiveKind::Location, &drone.location),
+        state: parse(LiveKind::Flight, &drone.state),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_drone_leaves_snapshot() {
+        let cache = LastKnownState::default();
+        cache.record(LiveKind::Location, "A1", &Arc::from(r#"{"longitude":120.0}"#));
+        cache.record(LiveKind::Flight, "B2", &Arc::from(r#"{"battery":80}"#));
+        cache.remove("A1");
+        assert!(cache.get("A1").is_none());
+        let drones: Vec<String> = cache.snapshot(|_, _| true).into_iter().map(|state| state.drone_id).collect();
+        assert_eq!(drones, ["B2"]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// 实时推送客户端的连接方式
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientKind {
+    WebSocket,
+    Sse,
+}
+
+/// 一个实时推送客户端的处理延迟统计
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveClientDto {
+    pub id: u64,
+    pub kind: ClientKind,
+    #[serde(rename = "connectedAt")]
+    pub connected_at: DateTime<Utc>,
+    /// 因处理过慢而丢失消息的次数
+    #[serde(rename = "lagCount")]
+    pub lag_count: u64,
+    /// 累计丢失的消息数
+    #[serde(rename = "skippedMessages")]
+    pub skipped_messages: u64,
+    #[serde(rename = "lastLagAt")]
+    pub last_lag_at: Option<DateTime<Utc>>,
+}
+
+/// 当前连接的WebSocket和SSE客户端
+#[derive(Default)]
+pub struct LiveClients {
+    next_id: AtomicU64,
+    clients: Mutex<HashMap<u64, LiveClientDto>>,
+}
+
+impl LiveClients {
+    /// 登记新连接，返回的句柄释放时移除
+    pub fn register(self: &Arc<Self>, kind: ClientKind) -> ClientHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let client = LiveClientDto {
+            id,
+            kind,
+            connected_at: Utc::now(),
+            lag_count: 0,
+            skipped_messages: 0,
+            last_lag_at: None,
+        };
+        self.clients.lock().unwrap().insert(id, client);
+        ClientHandle {
+            id,
+            clients: self.clone(),
+        }
+    }
+
+    /// 按连接顺序列出当前客户端
+    pub fn list(&self) -> Vec<LiveClientDto> {
+        let mut clients: Vec<LiveClientDto> = self.clients.lock().unwrap().values().cloned().collect();
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
+}
+
+/// 已登记的客户端
+pub struct ClientHandle {
+    id: u64,
+    clients: Arc<LiveClients>,
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// 记录一次丢失消息，返回累计丢失的次数
+    pub fn record_lag(&self, skipped: u64) -> u64 {
+        let mut clients = self.clients.clients.lock().unwrap();
+        let Some(client) = clients.get_mut(&self.id) else {
+            return 0;
+        };
+        client.lag_count += 1;
+        client.skipped_messages += skipped;
+        client.last_lag_at = Some(Utc::now());
+        client.lag_count
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.clients.clients.lock().unwrap().remove(&self.id);
+    }
+}
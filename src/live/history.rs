@@ -0,0 +1,299 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use tokio::sync::broadcast;
+
+use crate::mqtt::LiveEvent;
+
+/// 断线重连时的补发结果
+pub enum Resume {
+    /// 客户端没有提供 `Last-Event-ID`
+    Fresh,
+    /// 该ID之后的事件，按ID排序
+    Replay(Vec<LiveEvent>),
+    /// 该ID之后的事件已被淘汰，或ID不是本实例的本进程分配的，无法补发
+    Gap,
+}
+
+/// [`LocationHistory::subscribe`] 的结果
+pub struct HistorySubscription {
+    pub rx: broadcast::Receiver<LiveEvent>,
+    /// 订阅时最后分配的ID，之后从 `rx` 收到的事件ID都比它大
+    pub latest_id: u64,
+    pub resume: Resume,
+}
+
+struct DroneHistory {
+    events: VecDeque<LiveEvent>,
+    /// 已淘汰的最大事件ID
+    evicted_up_to: u64,
+}
+
+struct HistoryInner {
+    next_id: u64,
+    drones: HashMap<String, DroneHistory>,
+}
+
+/// 实时事件ID分配，以及每架无人机最近位置事件的环形缓冲区
+///
+/// 位置事件在同一把锁内分配ID、写入缓冲区并广播，订阅广播和读取缓冲区也在这把锁内完成，
+/// 因此补发的事件与之后广播的事件之间既不重复也不遗漏。
+/// ID从进程启动时的微秒时间戳开始递增，重启后分配的ID仍大于之前的ID。
+/// 多实例部署时各实例独立分配ID，发送给客户端的ID带有实例ID前缀，见 [`LocationHistory::event_id`]。
+pub struct LocationHistory {
+    capacity: usize,
+    /// 多实例部署时本实例的ID
+    instance: Option<String>,
+    /// 本进程分配的第一个ID
+    first_id: u64,
+    inner: Mutex<HistoryInner>,
+}
+
+impl LocationHistory {
+    /// `capacity` 为每架无人机保留的事件数，`instance` 为多实例部署时本实例的ID
+    pub fn new(capacity: usize, instance: Option<String>) -> Self {
+        let first_id = Utc::now().timestamp_micros().max(1) as u64;
+        Self {
+            capacity,
+            instance,
+            first_id,
+            inner: Mutex::new(HistoryInner {
+                next_id: first_id,
+                drones: HashMap::new(),
+            }),
+        }
+    }
+
+    /// 为不保存历史的事件分配ID
+    pub fn next_id(&self) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        id
+    }
+
+    /// 最后分配的ID
+    pub fn latest_id(&self) -> u64 {
+        self.inner.lock().unwrap().next_id - 1
+    }
+
+    /// 发送给客户端的事件ID，多实例部署时为 `<实例ID>-<ID>`
+    ///
+    /// 客户端重连到其他实例时，带回的ID前缀不同，按无法补发处理，而不会与该实例分配的ID混淆。
+    pub fn event_id(&self, id: u64) -> String {
+        match &self.instance {
+            Some(instance) => format!("{}-{}", instance, id),
+            None => id.to_string(),
+        }
+    }
+
+    /// 解析客户端带回的事件ID，其他实例分配的或无法解析的ID返回 `None`
+    fn parse_event_id(&self, event_id: &str) -> Option<u64> {
+        let id = match &self.instance {
+            Some(instance) => event_id.strip_prefix(instance.as_str())?.strip_prefix('-')?,
+            None => event_id,
+        };
+        id.parse().ok()
+    }
+
+    /// 删除无人机后丢弃其事件
+    pub fn remove(&self, drone_id: &str) {
+        self.inner.lock().unwrap().drones.remove(drone_id);
+    }
+
+    /// 分配ID并保存位置事件，然后广播给已订阅的客户端
+    pub fn publish(
+        &self,
+        tx: &broadcast::Sender<LiveEvent>,
+        drone_id: &str,
+        payload: Arc<str>,
+    ) -> Result<usize, broadcast::error::SendError<LiveEvent>> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let event = LiveEvent {
+            id,
+            drone_id: drone_id.into(),
+            payload,
+        };
+
+        let history = inner.drones.entry(drone_id.to_string()).or_insert_with(|| DroneHistory {
+            events: VecDeque::with_capacity(self.capacity),
+            evicted_up_to: 0,
+        });
+        if history.events.len() == self.capacity
+            && let Some(evicted) = history.events.pop_front()
+        {
+            history.evicted_up_to = evicted.id;
+        }
+        history.events.push_back(event.clone());
+        tx.send(event)
+    }
+
+    /// 订阅位置广播，并找出事件ID `last_event_id` 之后 `include` 中的无人机的事件
+    pub fn subscribe(
+        &self,
+        tx: &broadcast::Sender<LiveEvent>,
+        last_event_id: Option<&str>,
+        include: impl Fn(&str) -> bool,
+    ) -> HistorySubscription {
+        let inner = self.inner.lock().unwrap();
+        let rx = tx.subscribe();
+        let latest_id = inner.next_id - 1;
+        let resume = match last_event_id.map(|event_id| self.parse_event_id(event_id)) {
+            None => Resume::Fresh,
+            // 其他实例分配的或无法解析的ID
+            Some(None) => Resume::Gap,
+            // 之前的进程分配的ID
+            Some(Some(last_id)) if last_id.saturating_add(1) < self.first_id || last_id > latest_id => Resume::Gap,
+            Some(Some(last_id)) => {
+                let histories: Vec<&DroneHistory> = inner
+                    .drones
+                    .iter()
+                    .filter(|(drone_id, _)| include(drone_id))
+                    .map(|(_, history)| history)
+                    .collect();
+                if histories.iter().any(|history| history.evicted_up_to > last_id) {
+                    Resume::Gap
+                } else {
+                    let mut events: Vec<LiveEvent> = histories
+                        .iter()
+                        .flat_map(|history| history.events.iter().filter(|event| event.id > last_id).cloned())
+                        .collect();
+                    events.sort_by_key(|event| event.id);
+                    Resume::Replay(events)
+                }
+            }
+        };
+        HistorySubscription { rx, latest_id, resume }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publish(history: &LocationHistory, tx: &broadcast::Sender<LiveEvent>, drone_id: &str) -> u64 {
+        history.publish(tx, drone_id, Arc::from("{}")).unwrap();
+        history.latest_id()
+    }
+
+    /// 带着本实例分配的事件ID `last_id` 重连
+    fn resume_after(
+        history: &LocationHistory,
+        tx: &broadcast::Sender<LiveEvent>,
+        last_id: u64,
+        include: impl Fn(&str) -> bool,
+    ) -> Resume {
+        history.subscribe(tx, Some(&history.event_id(last_id)), include).resume
+    }
+
+    fn replayed_ids(resume: Resume) -> Vec<u64> {
+        match resume {
+            Resume::Replay(events) => events.iter().map(|event| event.id).collect(),
+            Resume::Fresh => panic!("应为补发，实际为 Fresh"),
+            Resume::Gap => panic!("应为补发，实际为 Gap"),
+        }
+    }
+
+    #[test]
+    fn ids_increase_across_event_kinds() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(4, None);
+        let first = publish(&history, &tx, "A1");
+        let flight = history.next_id();
+        let second = publish(&history, &tx, "A1");
+        assert!(first < flight && flight < second);
+    }
+
+    #[test]
+    fn without_last_event_id_is_fresh() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(4, None);
+        let latest = publish(&history, &tx, "A1");
+        let subscription = history.subscribe(&tx, None, |_| true);
+        assert!(matches!(subscription.resume, Resume::Fresh));
+        assert_eq!(subscription.latest_id, latest);
+    }
+
+    #[test]
+    fn replays_events_after_last_event_id_in_order() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(4, None);
+        let seen = publish(&history, &tx, "A1");
+        let b = publish(&history, &tx, "B2");
+        let a = publish(&history, &tx, "A1");
+        assert_eq!(replayed_ids(resume_after(&history, &tx, seen, |_| true)), vec![b, a]);
+        assert_eq!(replayed_ids(resume_after(&history, &tx, seen, |drone| drone == "B2")), vec![b]);
+        assert!(replayed_ids(resume_after(&history, &tx, a, |_| true)).is_empty());
+    }
+
+    #[test]
+    fn replay_and_broadcast_do_not_overlap() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(4, None);
+        let seen = publish(&history, &tx, "A1");
+        let replayed = publish(&history, &tx, "A1");
+        let mut subscription = history.subscribe(&tx, Some(&history.event_id(seen)), |_| true);
+        let live = publish(&history, &tx, "A1");
+        assert_eq!(replayed_ids(subscription.resume), vec![replayed]);
+        assert_eq!(subscription.rx.try_recv().unwrap().id, live);
+        assert!(subscription.rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn evicted_events_are_a_gap() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(2, None);
+        let seen = publish(&history, &tx, "A1");
+        publish(&history, &tx, "B2");
+        publish(&history, &tx, "A1");
+        // 淘汰的正是客户端已收到的事件，仍可补发
+        publish(&history, &tx, "A1");
+        assert_eq!(replayed_ids(resume_after(&history, &tx, seen, |_| true)).len(), 3);
+
+        publish(&history, &tx, "A1");
+        assert!(matches!(resume_after(&history, &tx, seen, |_| true), Resume::Gap));
+        // 其他无人机的淘汰不影响只关注B2的客户端
+        assert_eq!(replayed_ids(resume_after(&history, &tx, seen, |drone| drone == "B2")).len(), 1);
+    }
+
+    #[test]
+    fn unknown_ids_are_a_gap() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(4, None);
+        let latest = publish(&history, &tx, "A1");
+        // 之前的进程分配的ID，以及无法解析的 Last-Event-ID
+        assert!(matches!(history.subscribe(&tx, Some("0"), |_| true).resume, Resume::Gap));
+        assert!(matches!(resume_after(&history, &tx, latest + 1, |_| true), Resume::Gap));
+        assert!(matches!(history.subscribe(&tx, Some(&u64::MAX.to_string()), |_| true).resume, Resume::Gap));
+        assert!(matches!(history.subscribe(&tx, Some("abc"), |_| true).resume, Resume::Gap));
+        assert!(matches!(history.subscribe(&tx, Some(""), |_| true).resume, Resume::Gap));
+    }
+
+    #[test]
+    fn ids_from_other_instances_are_a_gap() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(4, Some("ingest-1".to_string()));
+        let other = LocationHistory::new(4, Some("ingest-2".to_string()));
+        let seen = publish(&history, &tx, "A1");
+        let next = publish(&history, &tx, "A1");
+        assert_eq!(history.event_id(seen), format!("ingest-1-{}", seen));
+        assert_eq!(replayed_ids(resume_after(&history, &tx, seen, |_| true)), vec![next]);
+        // 另一个实例分配的相同ID，以及不带前缀的ID
+        assert!(matches!(history.subscribe(&tx, Some(&other.event_id(seen)), |_| true).resume, Resume::Gap));
+        assert!(matches!(history.subscribe(&tx, Some(&seen.to_string()), |_| true).resume, Resume::Gap));
+        assert!(matches!(history.subscribe(&tx, Some("ingest-1"), |_| true).resume, Resume::Gap));
+    }
+
+    #[test]
+    fn removed_drone_is_not_replayed() {
+        let (tx, _rx) = broadcast::channel(16);
+        let history = LocationHistory::new(4, None);
+        let seen = publish(&history, &tx, "A1");
+        publish(&history, &tx, "A1");
+        let b = publish(&history, &tx, "B2");
+        history.remove("A1");
+        assert_eq!(replayed_ids(resume_after(&history, &tx, seen, |_| true)), vec![b]);
+    }
+}
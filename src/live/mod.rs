@@ -0,0 +1,9 @@
+pub mod cache;
+pub mod clients;
+pub mod history;
+pub mod resync;
+
+pub use cache::*;
+pub use clients::*;
+pub use history::*;
+pub use resync::*;
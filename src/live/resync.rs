@@ -0,0 +1,55 @@
+use serde::Serialize;
+
+use super::DroneStateDto;
+
+/// 需要重新同步的原因
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResyncReason {
+    /// 客户端处理过慢，广播通道丢弃了消息
+    Lagged,
+    /// 重连时 `Last-Event-ID` 之后的事件已不在缓冲区中
+    Evicted,
+}
+
+/// 客户端丢失消息后收到的重新同步事件，包含其关注的每架无人机最近的状态，
+/// 客户端用它替换本地状态，连接保持不变
+#[derive(Debug, Serialize)]
+pub struct ResyncEventDto {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub reason: ResyncReason,
+    /// 丢失消息的频道
+    pub channel: &'static str,
+    /// 本次丢失的消息数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<u64>,
+    /// 该客户端累计丢失消息的次数
+    #[serde(rename = "lagCount", skip_serializing_if = "Option::is_none")]
+    pub lag_count: Option<u64>,
+    pub drones: Vec<DroneStateDto>,
+}
+
+impl ResyncEventDto {
+    pub fn lagged(channel: &'static str, skipped: u64, lag_count: u64, drones: Vec<DroneStateDto>) -> Self {
+        ResyncEventDto {
+            kind: "resync",
+            reason: ResyncReason::Lagged,
+            channel,
+            skipped: Some(skipped),
+            lag_count: Some(lag_count),
+            drones,
+        }
+    }
+
+    pub fn evicted(channel: &'static str, drones: Vec<DroneStateDto>) -> Self {
+        ResyncEventDto {
+            kind: "resync",
+            reason: ResyncReason::Evicted,
+            channel,
+            skipped: None,
+            lag_count: None,
+            drones,
+        }
+    }
+}
@@ -4,24 +4,43 @@ mod config;
 mod mqtt;
 mod websocket;
 mod sse;
+mod api;
+mod telemetry;
+mod migrate;
+mod live;
+mod server;
+mod shutdown;
 
 use std::sync::Arc;
 use std::time::Duration;
 use dotenv::dotenv;
-use log::{error, info};
+use log::{error, info, warn};
 use mongodb::options::ClientOptions;
 use mongodb::Client;
 use pretty_env_logger::env_logger::Env;
-use rumqttc::{Event, QoS};
+use tokio::net::TcpListener;
 use tokio::sync::broadcast;
-use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
 use crate::config::AppConfig;
 use crate::service::ship_track_service::ShipTrackService;
 use crate::service::flight_service::FlightService;
-use crate::mqtt::{create_mqtt_client, subscribe_with_retry, handle_mqtt_message, run_mqtt_loop};
-use crate::websocket::start_websocket_server;
-use crate::sse::start_sse_server;
+use crate::service::dead_letter_service::DeadLetterService;
+use crate::service::drone_service::DroneService;
+use crate::service::provisioning_service::ProvisioningService;
+use crate::service::latest_state_service::LatestStateService;
+use crate::live::{LastKnownState, LiveClients, LocationHistory};
+use crate::mqtt::{
+    qos_from_level, run_mqtt_loop, ConnectionMonitor, Dispatcher, FanoutHandler, LiveEvent, LiveFanout,
+    LocationHandler, LowBatteryMonitor, MessageRouter, StateHandler,
+};
+use rumqttc::QoS;
+use crate::websocket::WsState;
+use crate::sse::replay::ReplayRegistry;
+use crate::sse::SseState;
+use crate::api::ApiState;
+use crate::server::AppState;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -33,47 +52,198 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
     
     // 加载配置
-    let config = AppConfig::from_env()?;
+    let config = AppConfig::load().inspect_err(|e| error!("配置加载失败: {}", e))?;
     info!("配置加载成功");
     
     // 创建广播通道用于WebSocket推送flight消息
-    let (flight_tx, _) = broadcast::channel::<String>(100);
+    let (flight_tx, _) = broadcast::channel::<LiveEvent>(config.channels.flight_broadcast_capacity);
     let flight_broadcaster = Arc::new(flight_tx);
     
     // 创建广播通道用于SSE推送位置消息
-    let (location_tx, _) = broadcast::channel::<String>(100);
+    let (location_tx, _) = broadcast::channel::<LiveEvent>(config.channels.location_broadcast_capacity);
     let location_broadcaster = Arc::new(location_tx);
 
+    // 创建广播通道用于WebSocket推送告警
+    let (alert_tx, _) = broadcast::channel::<LiveEvent>(config.channels.alert_broadcast_capacity);
+    let alert_broadcaster = Arc::new(alert_tx);
+
     // 配置MongoDB连接
-    let client_options = ClientOptions::parse(&config.mongodb_uri).await?;
+    let client_options = ClientOptions::parse(&config.mongo.uri).await?;
     let client = Client::with_options(client_options)?;
-    let db = client.database("shipTracking");
+    let db = client.database(&config.mongo.database);
+
+    // `mqtt migrate <名称>` 只执行数据迁移，不启动服务
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        return migrate::run_migration(args.get(2).map(String::as_str), &config, &db).await;
+    }
     
     // 创建服务实例
-    let track_collection = db.collection::<model::ship_track::ShipTrack>("trackSegments");
-    let track_service = Arc::new(ShipTrackService::new(track_collection));
+    let track_collection = db.collection::<model::ship_track::ShipTrack>(&config.mongo.track_collection);
+    let track_service = Arc::new(ShipTrackService::new(track_collection, config.segments.clone()));
+    track_service.ensure_indexes().await?;
     
-    let flight_collection = db.collection::<model::flight::Flight>("flights");
-    let flight_service = Arc::new(FlightService::new(flight_collection));
+    let flight_collection = db.collection::<model::flight::Flight>(&config.mongo.flight_collection);
+    let flight_sample_collection = db.collection::<model::flight::FlightSample>(&config.mongo.flight_sample_collection);
+    let flight_service = Arc::new(FlightService::new(flight_collection, flight_sample_collection));
+    flight_service.ensure_sample_collection(&db).await?;
+
+    let drone_collection = db.collection::<model::drone::Drone>(&config.mongo.drone_collection);
+    let drone_service = Arc::new(DroneService::new(drone_collection));
+    drone_service.ensure_indexes().await?;
+
+    let provisioning_service = Arc::new(ProvisioningService::new(
+        drone_service.clone(),
+        track_service.clone(),
+        flight_service.clone(),
+        config.cluster.enabled(),
+    ));
+
+    let dead_letter_collection = db.collection::<model::dead_letter::DeadLetter>(&config.mongo.dead_letter_collection);
+    let dead_letter_service = Arc::new(DeadLetterService::new(dead_letter_collection));
     
-    // 启动WebSocket服务器
-    let flight_broadcaster_clone = flight_broadcaster.clone();
-    tokio::spawn(async move {
-        if let Err(e) = start_websocket_server(flight_broadcaster_clone).await {
-            error!("WebSocket服务器启动失败: {}", e);
+    // 收到SIGTERM/SIGINT后各组件依次停止
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::wait_for_signal().await;
+            shutdown.cancel();
         }
     });
-    
-    // 启动SSE服务器
-    let location_broadcaster_clone = location_broadcaster.clone();
-    tokio::spawn(async move {
-        if let Err(e) = start_sse_server(location_broadcaster_clone).await {
-            error!("SSE服务器启动失败: {}", e);
+
+    // SSE、WebSocket和REST接口共用一个监听端口，绑定失败时直接退出
+    let http_addr = format!("{}:{}", config.http.bind_address, config.http.port);
+    let listener = TcpListener::bind(&http_addr)
+        .await
+        .inspect_err(|e| error!("HTTP服务器无法绑定 {}: {}", http_addr, e))?;
+
+    // 无人机最近的状态，新连接的客户端首先收到；启动时从数据库预热
+    let last_known = Arc::new(LastKnownState::default());
+    let latest_state_service = LatestStateService::new(
+        drone_service.clone(),
+        track_service.clone(),
+        flight_service.clone(),
+    );
+    match latest_state_service.snapshot(None).await {
+        Ok(snapshots) => info!("已加载{}架无人机的最近状态", last_known.warm(snapshots)),
+        Err(e) => warn!("加载无人机最近状态失败，等待实时数据: {}", e),
+    }
+    let live_clients = Arc::new(LiveClients::default());
+    let location_history = Arc::new(LocationHistory::new(
+        config.channels.location_history,
+        config.cluster.enabled().then(|| config.cluster.instance_id().to_string()),
+    ));
+
+    let ws_state = WsState {
+        flight_tx: flight_broadcaster.clone(),
+        location_tx: location_broadcaster.clone(),
+        alert_tx: alert_broadcaster.clone(),
+        drone_service: drone_service.clone(),
+        last_known: last_known.clone(),
+        clients: live_clients.clone(),
+        shutdown: shutdown.clone(),
+        connections: TaskTracker::new(),
+    };
+    let sse_state = SseState {
+        location_tx: location_broadcaster.clone(),
+        track_service: track_service.clone(),
+        flight_service: flight_service.clone(),
+        replays: ReplayRegistry::default(),
+        last_known: last_known.clone(),
+        history: location_history.clone(),
+        clients: live_clients.clone(),
+        shutdown: shutdown.clone(),
+    };
+
+    // 实时数据发布者，多实例部署时在实例间转发
+    let live = Arc::new(LiveFanout::new(
+        &config.cluster,
+        location_broadcaster,
+        flight_broadcaster,
+        alert_broadcaster,
+        last_known.clone(),
+        location_history.clone(),
+    ));
+
+    // 注册MQTT主题路由
+    let qos = qos_from_level(config.topics.qos);
+    let mut router = MessageRouter::new()
+        .route(&config.topics.location, qos, Arc::new(LocationHandler {
+            track_service: track_service.clone(),
+            provisioning: provisioning_service.clone(),
+            live: live.clone(),
+        }))?
+        .route(&config.topics.state, qos, Arc::new(StateHandler {
+            flight_service: flight_service.clone(),
+            provisioning: provisioning_service.clone(),
+            live: live.clone(),
+            low_battery: Arc::new(LowBatteryMonitor::new(drone_service.clone(), config.alerts.low_battery)),
+        }))?;
+    if let Some(pattern) = live.route_pattern() {
+        info!(
+            "多实例模式: 共享订阅组 {:?}，实例ID {}",
+            config.cluster.share_group,
+            config.cluster.instance_id()
+        );
+        router = router.route_per_instance(&pattern, QoS::AtMostOnce, Arc::new(FanoutHandler { fanout: live.clone() }))?;
+    }
+    let router = Arc::new(router);
+
+    let dispatcher = Arc::new(Dispatcher::new(
+        router.clone(),
+        dead_letter_service.clone(),
+        config.dispatcher.workers,
+        config.dispatcher.queue_depth,
+    ));
+
+    let connection_monitor = Arc::new(ConnectionMonitor::new());
+    let api_state = ApiState {
+        mqtt_status: connection_monitor.subscribe(),
+        dead_letter_service,
+        dispatcher: dispatcher.clone(),
+        drone_service,
+        provisioning_service,
+        track_service,
+        flight_service,
+        live_clients,
+        last_known,
+        history: location_history,
+    };
+
+    // 启动HTTP服务器，异常退出时关闭整个进程
+    let app_state = AppState {
+        api: Arc::new(api_state),
+        sse: Arc::new(sse_state),
+        ws: Arc::new(ws_state),
+    };
+    let http_server = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            if let Err(e) = server::serve(listener, app_state, shutdown.clone()).await {
+                error!("HTTP服务器异常退出: {}", e);
+                shutdown.cancel();
+            }
         }
     });
 
-    // 创建MQTT客户端并开始主循环
-    run_mqtt_loop(config, track_service, flight_service, flight_broadcaster, location_broadcaster).await?;
+    // 创建MQTT客户端并开始主循环，收到关闭信号后断开连接并返回
+    let shutdown_timeout = Duration::from_secs(config.http.shutdown_timeout_secs);
+    let result = run_mqtt_loop(config, dispatcher.clone(), connection_monitor, live, shutdown.clone()).await;
+    shutdown.cancel();
+
+    // 等待HTTP连接关闭和已收到的消息写入完成
+    let drained = tokio::time::timeout(shutdown_timeout, async {
+        if let Err(e) = http_server.await {
+            error!("HTTP服务器任务异常: {}", e);
+        }
+        dispatcher.shutdown().await;
+    })
+    .await;
+    if drained.is_err() {
+        warn!("等待关闭超时（{}秒），强制退出", shutdown_timeout.as_secs());
+    }
+    info!("服务已关闭");
 
-    Ok(())
+    result
 }
@@ -0,0 +1,194 @@
+use bson::{doc, Bson, DateTime, Document};
+use futures::TryStreamExt;
+use log::{info, warn};
+use mongodb::Database;
+
+use crate::config::AppConfig;
+use crate::model::flight::{FlightDto, FlightSample, FlightSampleMeta};
+use crate::service::flight_service::FlightService;
+
+/// 可用的数据迁移
+pub const MIGRATIONS: &[&str] = &["track-points", "flight-samples", "track-geojson"];
+
+/// 旧flight文档中按指标分开存放的数组字段
+const FLIGHT_SERIES: [&str; 6] = [
+    "batteryCapacity",
+    "estimatedRemainingUsageTime",
+    "cabinTemperature",
+    "aircraftAltitude",
+    "distanceToFan",
+    "airPressure",
+];
+
+/// 执行 `mqtt migrate <名称>` 子命令，迁移均可重复执行
+pub async fn run_migration(
+    name: Option<&str>,
+    config: &AppConfig,
+    db: &Database,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match name {
+        Some("track-points") => migrate_track_points(config, db).await,
+        Some("flight-samples") => migrate_flight_samples(config, db).await,
+        Some("track-geojson") => migrate_track_geojson(config, db).await,
+        Some(other) => Err(format!("未知的迁移 \"{}\"，可用的迁移: {}", other, MIGRATIONS.join(", ")).into()),
+        None => Err(format!("用法: mqtt migrate <名称>，可用的迁移: {}", MIGRATIONS.join(", ")).into()),
+    }
+}
+
+/// 把航迹中 `[经度, 纬度]` 数组格式的航迹点转换为对象格式
+///
+/// 旧航迹点没有设备时间、海拔等信息，转换后这些字段为空。已是对象的航迹点保持不变。
+async fn migrate_track_points(config: &AppConfig, db: &Database) -> Result<(), Box<dyn std::error::Error>> {
+    let collection = db.collection::<Document>(&config.mongo.track_collection);
+    let filter = doc! { "coordinates": { "$elemMatch": { "$type": "array" } } };
+    let pipeline = vec![doc! {
+        "$set": {
+            "coordinates": {
+                "$map": {
+                    "input": "$coordinates",
+                    "as": "point",
+                    "in": {
+                        "$cond": [
+                            { "$isArray": "$$point" },
+                            {
+                                "longitude": { "$arrayElemAt": ["$$point", 0] },
+                                "latitude": { "$arrayElemAt": ["$$point", 1] },
+                            },
+                            "$$point",
+                        ]
+                    }
+                }
+            }
+        }
+    }];
+    let result = collection.update_many(filter, pipeline).await?;
+    info!(
+        "航迹点迁移完成: {} 个航迹匹配, {} 个已更新",
+        result.matched_count, result.modified_count
+    );
+    Ok(())
+}
+
+/// 按航迹点重建GeoJSON字段 `path` 和 `lastPosition`，使已有航迹可以被空间查询
+///
+/// 依赖对象格式的航迹点，先执行 `track-points` 迁移。
+async fn migrate_track_geojson(config: &AppConfig, db: &Database) -> Result<(), Box<dyn std::error::Error>> {
+    migrate_track_points(config, db).await?;
+    let collection = db.collection::<Document>(&config.mongo.track_collection);
+    let filter = doc! { "coordinates.0": { "$exists": true } };
+    let pipeline = vec![doc! {
+        "$set": {
+            "path": {
+                "type": "MultiPoint",
+                "coordinates": {
+                    "$map": {
+                        "input": "$coordinates",
+                        "as": "point",
+                        "in": ["$$point.longitude", "$$point.latitude"],
+                    }
+                }
+            },
+            "lastPosition": {
+                "type": "Point",
+                "coordinates": [
+                    { "$getField": { "field": "longitude", "input": { "$last": "$coordinates" } } },
+                    { "$getField": { "field": "latitude", "input": { "$last": "$coordinates" } } },
+                ]
+            }
+        }
+    }];
+    let result = collection.update_many(filter, pipeline).await?;
+    info!(
+        "航迹GeoJSON迁移完成: {} 个航迹匹配, {} 个已更新",
+        result.matched_count, result.modified_count
+    );
+    Ok(())
+}
+
+/// 把旧flight文档中的指标数组转换为时间序列集合中的采样，并删除这些数组
+///
+/// 旧数据没有采样时间，按关联航迹的 `startTime` 到 `lastUpdate` 均匀插值，采样标记为 `meta.migrated`。
+/// 各数组长度不一致时只迁移对齐的部分。重复执行时先删除该flight已迁移的采样。
+async fn migrate_flight_samples(config: &AppConfig, db: &Database) -> Result<(), Box<dyn std::error::Error>> {
+    let flight_service = FlightService::new(
+        db.collection(&config.mongo.flight_collection),
+        db.collection(&config.mongo.flight_sample_collection),
+    );
+    flight_service.ensure_sample_collection(db).await?;
+    let flights = db.collection::<Document>(&config.mongo.flight_collection);
+    let tracks = db.collection::<Document>(&config.mongo.track_collection);
+
+    let mut cursor = flights.find(doc! { "batteryCapacity": { "$exists": true } }).await?;
+    let (mut migrated_flights, mut migrated_samples) = (0u64, 0u64);
+    while let Some(flight) = cursor.try_next().await? {
+        let id = flight.get_object_id("_id")?;
+        let series: Vec<Vec<f64>> = FLIGHT_SERIES.iter().map(|field| number_array(&flight, field)).collect();
+        let count = series.iter().map(Vec::len).min().unwrap_or(0);
+        let longest = series.iter().map(Vec::len).max().unwrap_or(0);
+        if count != longest {
+            warn!("flight {} 的指标数组长度不一致，丢弃 {} 条未对齐的数据", id, longest - count);
+        }
+
+        // 用关联航迹的起止时间估计采样时间，找不到航迹时使用flight ID中的创建时间
+        let track = match flight.get_object_id("trackId") {
+            Ok(track_id) => tracks.find_one(doc! { "_id": track_id }).await?,
+            Err(_) => None,
+        };
+        let created = id.timestamp();
+        let start = track.as_ref().and_then(|t| t.get_datetime("startTime").ok().copied()).unwrap_or(created);
+        let end = track.as_ref().and_then(|t| t.get_datetime("lastUpdate").ok().copied()).unwrap_or(start);
+        let span = (end.timestamp_millis() - start.timestamp_millis()).max(0);
+
+        let meta = FlightSampleMeta {
+            flight_id: id,
+            drone_id: flight.get_str("droneId").ok().map(str::to_string),
+            migrated: true,
+        };
+        let samples: Vec<FlightSample> = (0..count)
+            .map(|i| {
+                let offset = if count > 1 { span * i as i64 / (count as i64 - 1) } else { 0 };
+                let state = FlightDto {
+                    battery_capacity: series[0][i],
+                    estimated_remaining_usage_time: series[1][i],
+                    cabin_temperature: series[2][i],
+                    aircraft_altitude: series[3][i],
+                    distance_to_fan: series[4][i],
+                    air_pressure: series[5][i],
+                };
+                FlightSample::new(meta.clone(), DateTime::from_millis(start.timestamp_millis() + offset), state)
+            })
+            .collect();
+
+        flight_service
+            .samples
+            .delete_many(doc! { "meta.flightId": id, "meta.migrated": true })
+            .await?;
+        if !samples.is_empty() {
+            flight_service.samples.insert_many(samples).await?;
+        }
+        let unset: Document = FLIGHT_SERIES.iter().map(|field| (field.to_string(), Bson::String(String::new()))).collect();
+        flights.update_one(doc! { "_id": id }, doc! { "$unset": unset }).await?;
+        migrated_flights += 1;
+        migrated_samples += count as u64;
+    }
+    info!("flight采样迁移完成: {} 个flight, {} 条采样", migrated_flights, migrated_samples);
+    Ok(())
+}
+
+/// 读取数值数组，忽略非数值元素
+fn number_array(document: &Document, field: &str) -> Vec<f64> {
+    document
+        .get_array(field)
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|value| match value {
+                    Bson::Double(v) => Some(*v),
+                    Bson::Int32(v) => Some(*v as f64),
+                    Bson::Int64(v) => Some(*v as f64),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
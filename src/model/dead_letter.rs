@@ -0,0 +1,147 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bson::serde_helpers::serialize_bson_datetime_as_rfc3339_string;
+use bson::spec::BinarySubtype;
+use bson::Binary;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::serde_helpers::serialize_object_id_as_hex_string;
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::mqtt::MessageMetadata;
+
+/// 死信状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadLetterStatus {
+    /// 等待处理
+    Pending,
+    /// 已成功重放
+    Replayed,
+}
+
+/// 被处理器拒绝的MQTT消息
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub topic: String,
+    /// 原始消息内容
+    pub payload: Binary,
+    /// MQTT 5 content-type，重放时用于选择解码器
+    #[serde(rename = "contentType", default)]
+    pub content_type: Option<String>,
+    /// MQTT 5 关联数据
+    #[serde(rename = "correlationData", default)]
+    pub correlation_data: Option<Binary>,
+    /// MQTT 5 用户属性，按原顺序保存为 `[名称, 值]`
+    #[serde(rename = "userProperties", default)]
+    pub user_properties: Vec<(String, String)>,
+    #[serde(rename = "receivedAt")]
+    pub received_at: DateTime,
+    /// 最近一次被拒绝的原因
+    pub error: String,
+    pub status: DeadLetterStatus,
+    #[serde(rename = "replayCount")]
+    pub replay_count: u32,
+    #[serde(rename = "lastReplayAt")]
+    pub last_replay_at: Option<DateTime>,
+}
+
+impl DeadLetter {
+    /// 重放时还原的消息属性，不包含有效期
+    pub fn metadata(&self) -> MessageMetadata {
+        MessageMetadata {
+            content_type: self.content_type.clone(),
+            correlation_data: self.correlation_data.as_ref().map(|data| data.bytes.clone()),
+            user_properties: self.user_properties.clone(),
+            message_expiry: None,
+        }
+    }
+
+    pub fn new(
+        topic: String,
+        payload: Vec<u8>,
+        metadata: MessageMetadata,
+        received_at: DateTime,
+        error: String,
+    ) -> Self {
+        Self {
+            id: ObjectId::new(),
+            topic,
+            payload: binary(payload),
+            content_type: metadata.content_type,
+            correlation_data: metadata.correlation_data.map(binary),
+            user_properties: metadata.user_properties,
+            received_at,
+            error,
+            status: DeadLetterStatus::Pending,
+            replay_count: 0,
+            last_replay_at: None,
+        }
+    }
+}
+
+pub fn binary(bytes: Vec<u8>) -> Binary {
+    Binary {
+        subtype: BinarySubtype::Generic,
+        bytes,
+    }
+}
+
+/// 修正死信的请求体，`payload` 与 `payloadBase64` 二选一
+#[derive(Debug, Deserialize)]
+pub struct UpdateDeadLetterPayload {
+    pub topic: Option<String>,
+    /// 文本形式的新消息内容
+    pub payload: Option<String>,
+    /// Base64编码的新消息内容，用于二进制消息
+    #[serde(rename = "payloadBase64")]
+    pub payload_base64: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadLetterResponseDto {
+    #[serde(rename = "_id", serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    pub topic: String,
+    /// 消息内容是合法UTF-8时的文本形式
+    pub payload: Option<String>,
+    #[serde(rename = "payloadBase64")]
+    pub payload_base64: String,
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    #[serde(rename = "correlationDataBase64")]
+    pub correlation_data_base64: Option<String>,
+    #[serde(rename = "userProperties")]
+    pub user_properties: Vec<(String, String)>,
+    #[serde(rename = "receivedAt", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub received_at: DateTime,
+    pub error: String,
+    pub status: DeadLetterStatus,
+    #[serde(rename = "replayCount")]
+    pub replay_count: u32,
+    #[serde(rename = "lastReplayAt")]
+    pub last_replay_at: Option<String>,
+}
+
+impl From<DeadLetter> for DeadLetterResponseDto {
+    fn from(dead_letter: DeadLetter) -> Self {
+        DeadLetterResponseDto {
+            id: dead_letter.id,
+            topic: dead_letter.topic,
+            payload: String::from_utf8(dead_letter.payload.bytes.clone()).ok(),
+            payload_base64: BASE64.encode(&dead_letter.payload.bytes),
+            content_type: dead_letter.content_type,
+            correlation_data_base64: dead_letter.correlation_data.map(|data| BASE64.encode(data.bytes)),
+            user_properties: dead_letter.user_properties,
+            received_at: dead_letter.received_at,
+            error: dead_letter.error,
+            status: dead_letter.status,
+            replay_count: dead_letter.replay_count,
+            last_replay_at: dead_letter
+                .last_replay_at
+                .and_then(|t| t.try_to_rfc3339_string().ok()),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use bson::serde_helpers::serialize_bson_datetime_as_rfc3339_string;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::serde_helpers::serialize_object_id_as_hex_string;
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use crate::model::flight::FlightEventDto;
+use crate::model::ship_track::LocationEventDto;
+
+/// 起降点
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HomePoint {
+    pub longitude: f64,
+    pub latitude: f64,
+    pub altitude: Option<f64>,
+}
+
+/// 无人机登记信息，通过序列号关联当前的航迹和flight文档
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Drone {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// 无人机序列号，即MQTT主题中的ID
+    pub serial: String,
+    pub model: Option<String>,
+    pub owner: Option<String>,
+    #[serde(rename = "homePoint")]
+    pub home_point: Option<HomePoint>,
+    #[serde(rename = "activeTrackId")]
+    pub active_track_id: Option<ObjectId>,
+    #[serde(rename = "activeFlightId")]
+    pub active_flight_id: Option<ObjectId>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    /// 最近一次状态的电量是否低于告警阈值，见 [`LowBatteryMonitor`](crate::mqtt::LowBatteryMonitor)
+    #[serde(rename = "lowBattery", default)]
+    pub low_battery: bool,
+}
+
+/// 无人机当前使用的航迹和flight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveDocuments {
+    pub track_id: ObjectId,
+    pub flight_id: ObjectId,
+}
+
+// 用于登记无人机的请求体结构体
+#[derive(Debug, Deserialize)]
+pub struct CreateDroneRequestDto {
+    pub serial: String,
+    pub model: Option<String>,
+    pub owner: Option<String>,
+    #[serde(rename = "homePoint")]
+    pub home_point: Option<HomePoint>,
+}
+
+// 用于更新无人机信息的请求体结构体，未提供的字段保持不变
+#[derive(Debug, Deserialize)]
+pub struct UpdateDroneRequestDto {
+    pub model: Option<String>,
+    pub owner: Option<String>,
+    #[serde(rename = "homePoint")]
+    pub home_point: Option<HomePoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DroneResponseDto {
+    #[serde(rename = "_id", serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    pub serial: String,
+    pub model: Option<String>,
+    pub owner: Option<String>,
+    #[serde(rename = "homePoint")]
+    pub home_point: Option<HomePoint>,
+    #[serde(rename = "activeTrackId")]
+    pub active_track_id: Option<String>,
+    #[serde(rename = "activeFlightId")]
+    pub active_flight_id: Option<String>,
+    #[serde(rename = "createdAt", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+}
+
+impl From<Drone> for DroneResponseDto {
+    fn from(drone: Drone) -> Self {
+        DroneResponseDto {
+            id: drone.id,
+            serial: drone.serial,
+            model: drone.model,
+            owner: drone.owner,
+            home_point: drone.home_point,
+            active_track_id: drone.active_track_id.map(|id| id.to_hex()),
+            active_flight_id: drone.active_flight_id.map(|id| id.to_hex()),
+            created_at: drone.created_at,
+        }
+    }
+}
+
+/// 无人机的最新位置和flight状态，格式与实时事件一致
+#[derive(Debug, Serialize)]
+pub struct DroneSnapshotDto {
+    #[serde(rename = "droneId")]
+    pub drone_id: String,
+    pub location: Option<LocationEventDto>,
+    pub state: Option<FlightEventDto>,
+}
@@ -1,25 +1,187 @@
 use bson::oid::ObjectId;
+use bson::DateTime;
+use bson::serde_helpers::serialize_bson_datetime_as_rfc3339_string;
+use bson::serde_helpers::serialize_object_id_as_hex_string;
 use serde::{Deserialize, Serialize};
-use mongodb::bson::serde_helpers::serialize_object_id_as_hex_string;
+use crate::service::downsample::DownsampleMethod;
+/// flight文档，状态采样存放在时间序列集合中（见 [`FlightSample`]）
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Flight {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     #[serde(rename = "trackId")]
     pub track_id: ObjectId,// 关联的航迹ID
+    /// 无人机序列号，迁移前创建的flight可能没有
+    #[serde(rename = "droneId", default, skip_serializing_if = "Option::is_none")]
+    pub drone_id: Option<String>,
+}
+
+/// 时间序列集合中的一条状态采样
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlightSample {
+    /// 时间字段：服务端收到状态消息的时间
+    pub timestamp: DateTime,
+    /// 元数据字段，同一flight的采样存放在同一组桶中
+    pub meta: FlightSampleMeta,
     #[serde(rename = "batteryCapacity")]
-    pub battery_capacity: Vec<f64>, // 电池容量
+    pub battery_capacity: f64, // 电池容量
     #[serde(rename = "estimatedRemainingUsageTime")]
-    pub estimated_remaining_usage_time: Vec<f64>,
+    pub estimated_remaining_usage_time: f64,
     #[serde(rename = "cabinTemperature")]
-    pub cabin_temperature: Vec<f64>,
+    pub cabin_temperature: f64,
     #[serde(rename = "aircraftAltitude")]
-    pub aircraft_altitude: Vec<f64>,
+    pub aircraft_altitude: f64,
     #[serde(rename = "distanceToFan")]
-    pub distance_to_fan: Vec<f64>,
+    pub distance_to_fan: f64,
     #[serde(rename = "airPressure")]
-    pub air_pressure: Vec<f64>,
+    pub air_pressure: f64,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlightSampleMeta {
+    #[serde(rename = "flightId")]
+    pub flight_id: ObjectId,
+    #[serde(rename = "droneId", default, skip_serializing_if = "Option::is_none")]
+    pub drone_id: Option<String>,
+    /// 由旧flight文档迁移而来，时间为按航迹起止时间插值的估计值
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub migrated: bool,
+}
+
+impl FlightSample {
+    pub fn new(meta: FlightSampleMeta, timestamp: DateTime, state: FlightDto) -> Self {
+        FlightSample {
+            timestamp,
+            meta,
+            battery_capacity: state.battery_capacity,
+            estimated_remaining_usage_time: state.estimated_remaining_usage_time,
+            cabin_temperature: state.cabin_temperature,
+            aircraft_altitude: state.aircraft_altitude,
+            distance_to_fan: state.distance_to_fan,
+            air_pressure: state.air_pressure,
+        }
+    }
+
+    /// 采样中的状态，与实时推送的格式一致
+    pub fn state(&self) -> FlightDto {
+        FlightDto {
+            battery_capacity: self.battery_capacity,
+            estimated_remaining_usage_time: self.estimated_remaining_usage_time,
+            cabin_temperature: self.cabin_temperature,
+            aircraft_altitude: self.aircraft_altitude,
+            distance_to_fan: self.distance_to_fan,
+            air_pressure: self.air_pressure,
+        }
+    }
+}
+
+/// flight状态指标，名称与采样文档中的字段一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightMetric {
+    BatteryCapacity,
+    EstimatedRemainingUsageTime,
+    CabinTemperature,
+    AircraftAltitude,
+    DistanceToFan,
+    AirPressure,
+}
+
+impl FlightMetric {
+    pub const ALL: [FlightMetric; 6] = [
+        FlightMetric::BatteryCapacity,
+        FlightMetric::EstimatedRemainingUsageTime,
+        FlightMetric::CabinTemperature,
+        FlightMetric::AircraftAltitude,
+        FlightMetric::DistanceToFan,
+        FlightMetric::AirPressure,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FlightMetric::BatteryCapacity => "batteryCapacity",
+            FlightMetric::EstimatedRemainingUsageTime => "estimatedRemainingUsageTime",
+            FlightMetric::CabinTemperature => "cabinTemperature",
+            FlightMetric::AircraftAltitude => "aircraftAltitude",
+            FlightMetric::DistanceToFan => "distanceToFan",
+            FlightMetric::AirPressure => "airPressure",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|metric| metric.as_str() == name)
+    }
+
+    /// 读取采样中的指标值
+    pub fn value(self, sample: &FlightSample) -> f64 {
+        match self {
+            FlightMetric::BatteryCapacity => sample.battery_capacity,
+            FlightMetric::EstimatedRemainingUsageTime => sample.estimated_remaining_usage_time,
+            FlightMetric::CabinTemperature => sample.cabin_temperature,
+            FlightMetric::AircraftAltitude => sample.aircraft_altitude,
+            FlightMetric::DistanceToFan => sample.distance_to_fan,
+            FlightMetric::AirPressure => sample.air_pressure,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetricPointDto {
+    #[serde(serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub timestamp: DateTime,
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetricSeriesDto {
+    pub metric: &'static str,
+    pub points: Vec<MetricPointDto>,
+}
+
+/// flight指标历史，各指标分别降采样
+#[derive(Debug, Serialize)]
+pub struct FlightMetricsResponseDto {
+    #[serde(rename = "flightId", serialize_with = "serialize_object_id_as_hex_string")]
+    pub flight_id: ObjectId,
+    pub method: DownsampleMethod,
+    /// 降采样前时间范围内的采样数
+    #[serde(rename = "sourcePoints")]
+    pub source_points: usize,
+    pub series: Vec<MetricSeriesDto>,
+}
+
+/// 实时flight状态事件，`data` 为状态内容
+#[derive(Debug, Serialize)]
+pub struct FlightEventDto {
+    #[serde(rename = "droneId")]
+    pub drone_id: Option<String>,
+    #[serde(rename = "flightId", serialize_with = "serialize_object_id_as_hex_string")]
+    pub flight_id: ObjectId,
+    /// 服务端收到消息的时间，回放时为采样时间
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub data: FlightDto,
+}
+
+/// 告警类型
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AlertKind {
+    LowBattery,
+}
+
+/// 实时告警事件
+#[derive(Debug, Serialize)]
+pub struct AlertEventDto {
+    #[serde(rename = "droneId")]
+    pub drone_id: String,
+    #[serde(rename = "flightId", serialize_with = "serialize_object_id_as_hex_string")]
+    pub flight_id: ObjectId,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub kind: AlertKind,
+    pub message: String,
+    /// 触发告警的指标值
+    pub value: f64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FlightDto {
     // #[serde(serialize_with = "serialize_object_id_as_hex_string")]
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+/// GeoJSON几何对象，坐标为 `[经度, 纬度]`
+///
+/// 只包含航迹存储和查询用到的类型，序列化格式可直接用于MongoDB的 `2dsphere` 索引和 `$geometry` 查询。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point { coordinates: [f64; 2] },
+    MultiPoint { coordinates: Vec<[f64; 2]> },
+    Polygon { coordinates: Vec<Vec<[f64; 2]>> },
+}
+
+impl Geometry {
+    pub fn point(longitude: f64, latitude: f64) -> Self {
+        Geometry::Point { coordinates: [longitude, latitude] }
+    }
+
+    /// 经纬度范围构成的矩形，`min` 和 `max` 为 `[经度, 纬度]`
+    pub fn bbox(min: [f64; 2], max: [f64; 2]) -> Self {
+        Geometry::Polygon {
+            coordinates: vec![vec![
+                [min[0], min[1]],
+                [max[0], min[1]],
+                [max[0], max[1]],
+                [min[0], max[1]],
+                [min[0], min[1]],
+            ]],
+        }
+    }
+
+    /// 检查坐标范围，多边形的每个环需闭合且至少有4个位置
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Geometry::Point { coordinates } => validate_position(coordinates),
+            Geometry::MultiPoint { coordinates } => {
+                if coordinates.is_empty() {
+                    return Err("MultiPoint 至少需要1个位置".to_string());
+                }
+                coordinates.iter().try_for_each(validate_position)
+            }
+            Geometry::Polygon { coordinates } => {
+                if coordinates.is_empty() {
+                    return Err("Polygon 至少需要1个环".to_string());
+                }
+                for ring in coordinates {
+                    if ring.len() < 4 {
+                        return Err("Polygon 的环至少需要4个位置".to_string());
+                    }
+                    if ring.first() != ring.last() {
+                        return Err("Polygon 的环必须闭合（首尾位置相同）".to_string());
+                    }
+                    ring.iter().try_for_each(validate_position)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// 检查经纬度是否在WGS84范围内
+pub fn validate_position([longitude, latitude]: &[f64; 2]) -> Result<(), String> {
+    if !(-180.0..=180.0).contains(longitude) || !(-90.0..=90.0).contains(latitude) {
+        return Err(format!("坐标超出范围: [{}, {}]", longitude, latitude));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(ring: Vec<[f64; 2]>) -> Geometry {
+        Geometry::Polygon { coordinates: vec![ring] }
+    }
+
+    #[test]
+    fn bbox_is_valid_polygon() {
+        assert_eq!(Geometry::bbox([120.0, 30.0], [121.0, 31.0]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unclosed_ring() {
+        let ring = vec![[120.0, 30.0], [121.0, 30.0], [121.0, 31.0], [120.0, 31.0]];
+        assert!(polygon(ring).validate().unwrap_err().contains("闭合"));
+    }
+
+    #[test]
+    fn rejects_ring_with_fewer_than_four_positions() {
+        let ring = vec![[120.0, 30.0], [121.0, 30.0], [120.0, 30.0]];
+        assert!(polygon(ring).validate().unwrap_err().contains("4个位置"));
+        assert!(Geometry::Polygon { coordinates: Vec::new() }.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_positions() {
+        assert!(Geometry::point(120.0, 90.5).validate().is_err());
+        assert!(Geometry::point(180.5, 30.0).validate().is_err());
+        assert!(Geometry::point(-180.5, -30.0).validate().is_err());
+        assert!(Geometry::bbox([120.0, 30.0], [181.0, 31.0]).validate().is_err());
+        assert!(Geometry::MultiPoint { coordinates: vec![[120.0, 30.0], [0.0, -91.0]] }.validate().is_err());
+    }
+
+    #[test]
+    fn accepts_range_boundaries() {
+        assert_eq!(validate_position(&[180.0, 90.0]), Ok(()));
+        assert_eq!(validate_position(&[-180.0, -90.0]), Ok(()));
+    }
+
+    #[test]
+    fn rejects_empty_multipoint() {
+        assert!(Geometry::MultiPoint { coordinates: Vec::new() }.validate().is_err());
+    }
+}
@@ -1,2 +1,5 @@
 pub(crate) mod ship_track;
-pub mod flight;
\ No newline at end of file
+pub mod flight;
+pub mod dead_letter;
+pub mod drone;
+pub mod geo;
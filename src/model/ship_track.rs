@@ -2,29 +2,172 @@ use bson::serde_helpers::serialize_bson_datetime_as_rfc3339_string;
 use mongodb::bson::oid::ObjectId;
 use mongodb::bson::serde_helpers::serialize_object_id_as_hex_string;
 use mongodb::bson::DateTime;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use crate::model::geo::Geometry;
+/// 航迹分段，同一flight的分段按 `segmentIndex` 顺序组成完整航迹
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ShipTrack {
     #[serde(rename = "_id")]
     pub id: ObjectId,
+    /// 无人机序列号，分段功能之前创建的航迹没有
+    #[serde(rename = "droneId", default, skip_serializing_if = "Option::is_none")]
+    pub drone_id: Option<String>,
+    #[serde(rename = "flightId", default, skip_serializing_if = "Option::is_none")]
+    pub flight_id: Option<ObjectId>,
+    /// 分段序号，从0开始
+    #[serde(rename = "segmentIndex", default)]
+    pub segment_index: u32,
+    /// 上一个分段
+    #[serde(rename = "prevSegmentId", default, skip_serializing_if = "Option::is_none")]
+    pub prev_segment_id: Option<ObjectId>,
+    /// 分段文档序列化后的字节数（估算上限），用于分段
+    #[serde(rename = "sizeBytes", default)]
+    pub size_bytes: i64,
     #[serde(rename = "startTime")]
     pub start_time: DateTime,
    #[serde(rename = "lastUpdate")]
     pub last_update: DateTime,
     #[serde(rename = "totalPoints")]
     pub total_points: u32,
-    pub coordinates: Vec<[f64; 2]>,
+    /// 查询时可能不返回航迹点
+    #[serde(default)]
+    pub coordinates: Vec<TrackPoint>,
+    /// 航迹点的GeoJSON `MultiPoint`，建有 `2dsphere` 索引，写入第一个航迹点时创建
+    ///
+    /// 不使用 `LineString`：它至少需要两个不同的位置，刚开始的分段和悬停时的航迹无法写入索引。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<Geometry>,
+    /// 最新位置的GeoJSON `Point`，建有 `2dsphere` 索引
+    #[serde(rename = "lastPosition", default, skip_serializing_if = "Option::is_none")]
+    pub last_position: Option<Geometry>,
 }
+
+/// 航迹点
+///
+/// 早期文档中的航迹点是 `[经度, 纬度]` 数组，读取时仍然兼容；新写入的航迹点为对象。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredTrackPoint")]
+pub struct TrackPoint {
+    pub longitude: f64,
+    pub latitude: f64,
+    /// 海拔高度（米）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    /// 设备采样时间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime>,
+    /// 地速（米/秒）
+    #[serde(rename = "groundSpeed", skip_serializing_if = "Option::is_none")]
+    pub ground_speed: Option<f64>,
+    /// 航向（度，正北为0，顺时针）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heading: Option<f64>,
+    /// GNSS水平定位精度（米）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<f64>,
+}
+
+impl From<[f64; 2]> for TrackPoint {
+    fn from([longitude, latitude]: [f64; 2]) -> Self {
+        TrackPoint {
+            longitude,
+            latitude,
+            altitude: None,
+            timestamp: None,
+            ground_speed: None,
+            heading: None,
+            accuracy: None,
+        }
+    }
+}
+
+/// 数据库中航迹点的两种存储格式
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredTrackPoint {
+    Legacy([f64; 2]),
+    Point {
+        longitude: f64,
+        latitude: f64,
+        altitude: Option<f64>,
+        timestamp: Option<DateTime>,
+        #[serde(rename = "groundSpeed")]
+        ground_speed: Option<f64>,
+        heading: Option<f64>,
+        accuracy: Option<f64>,
+    },
+}
+
+impl From<StoredTrackPoint> for TrackPoint {
+    fn from(stored: StoredTrackPoint) -> Self {
+        match stored {
+            StoredTrackPoint::Legacy(pair) => pair.into(),
+            StoredTrackPoint::Point { longitude, latitude, altitude, timestamp, ground_speed, heading, accuracy } => {
+                TrackPoint { longitude, latitude, altitude, timestamp, ground_speed, heading, accuracy }
+            }
+        }
+    }
+}
+
+/// 接口中的航迹点，时间为RFC 3339字符串
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackPointDto {
+    pub longitude: f64,
+    pub latitude: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<chrono::DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ground_speed: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<f64>,
+}
+
+impl From<TrackPoint> for TrackPointDto {
+    fn from(point: TrackPoint) -> Self {
+        TrackPointDto {
+            longitude: point.longitude,
+            latitude: point.latitude,
+            altitude: point.altitude,
+            timestamp: point.timestamp.map(DateTime::to_chrono),
+            ground_speed: point.ground_speed,
+            heading: point.heading,
+            accuracy: point.accuracy,
+        }
+    }
+}
+
+impl From<TrackPointDto> for TrackPoint {
+    fn from(dto: TrackPointDto) -> Self {
+        TrackPoint {
+            longitude: dto.longitude,
+            latitude: dto.latitude,
+            altitude: dto.altitude,
+            timestamp: dto.timestamp.map(DateTime::from_chrono),
+            ground_speed: dto.ground_speed,
+            heading: dto.heading,
+            accuracy: dto.accuracy,
+        }
+    }
+}
+
 // 新增：用于更新操作的请求体结构体
 #[derive(Debug, Deserialize)]
 pub struct UpdateShipTrackPayload {
     #[serde(rename = "coordinatesToAdd")]
-    pub coordinates_to_add: Vec<[f64; 2]>,
+    pub coordinates_to_add: Vec<TrackPointDto>,
 }
 // 新增：用于创建操作的请求体结构体
 #[derive(Debug, Deserialize)] // 只需要 Deserialize，因为这是输入载荷
 pub struct ShipTrackRequestDto {
-    pub coordinates: Vec<[f64; 2]>,
+    #[serde(rename = "droneId", default)]
+    pub drone_id: Option<String>,
+    pub coordinates: Vec<TrackPointDto>,
     #[serde(rename = "totalPoints")]
     pub total_points: u32, // 客户端提供 total_points
 }
@@ -40,10 +183,22 @@ pub struct ShipTrackResponseDto {
     #[serde(rename = "lastUpdate", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
     pub last_update: DateTime, // mongodb::bson::DateTime
 
-    pub coordinates: Vec<[f64; 2]>,
+    pub coordinates: Vec<TrackPointDto>,
 
     #[serde(rename = "totalPoints")]
     pub total_points: u32,
+
+    #[serde(rename = "droneId")]
+    pub drone_id: Option<String>,
+
+    #[serde(rename = "flightId")]
+    pub flight_id: Option<String>,
+
+    #[serde(rename = "segmentIndex")]
+    pub segment_index: u32,
+
+    #[serde(rename = "prevSegmentId")]
+    pub prev_segment_id: Option<String>,
 }
 
 // Implement From trait for easy conversion from ShipTrack model to ShipTrackResponseDto
@@ -53,8 +208,172 @@ impl From<ShipTrack> for ShipTrackResponseDto {
             id: track_model.id,
             start_time: track_model.start_time,
             last_update: track_model.last_update,
-            coordinates: track_model.coordinates,
+            coordinates: track_model.coordinates.into_iter().map(Into::into).collect(),
             total_points: track_model.total_points,
+            drone_id: track_model.drone_id,
+            flight_id: track_model.flight_id.map(|id| id.to_hex()),
+            segment_index: track_model.segment_index,
+            prev_segment_id: track_model.prev_segment_id.map(|id| id.to_hex()),
+        }
+    }
+}
+
+/// 不含航迹点的航迹摘要，用于航迹列表和空间查询结果
+#[derive(Debug, Serialize)]
+pub struct ShipTrackSummaryDto {
+    #[serde(rename = "_id", serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    #[serde(rename = "droneId")]
+    pub drone_id: Option<String>,
+    #[serde(rename = "flightId")]
+    pub flight_id: Option<String>,
+    #[serde(rename = "segmentIndex")]
+    pub segment_index: u32,
+    #[serde(rename = "startTime", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub start_time: DateTime,
+    #[serde(rename = "lastUpdate", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub last_update: DateTime,
+    #[serde(rename = "totalPoints")]
+    pub total_points: u32,
+}
+
+impl From<ShipTrack> for ShipTrackSummaryDto {
+    fn from(track: ShipTrack) -> Self {
+        ShipTrackSummaryDto {
+            id: track.id,
+            drone_id: track.drone_id,
+            flight_id: track.flight_id.map(|id| id.to_hex()),
+            segment_index: track.segment_index,
+            start_time: track.start_time,
+            last_update: track.last_update,
+            total_points: track.total_points,
+        }
+    }
+}
+
+/// 经过矩形区域的航迹，`points` 为区域和时间窗口内的航迹点
+#[derive(Debug, Serialize)]
+pub struct TrackAreaMatchDto {
+    #[serde(flatten)]
+    pub track: ShipTrackSummaryDto,
+    pub points: Vec<TrackPointDto>,
+}
+
+impl From<ShipTrack> for TrackAreaMatchDto {
+    fn from(mut track: ShipTrack) -> Self {
+        let points = std::mem::take(&mut track.coordinates).into_iter().map(Into::into).collect();
+        TrackAreaMatchDto { track: track.into(), points }
+    }
+}
+
+/// 某点附近的无人机及其最新位置，由空间查询的聚合管道直接生成
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NearbyDroneDto {
+    #[serde(rename = "droneId")]
+    pub drone_id: String,
+    #[serde(rename = "trackId", serialize_with = "serialize_object_id_as_hex_string")]
+    pub track_id: ObjectId,
+    pub longitude: f64,
+    pub latitude: f64,
+    /// 到查询点的距离（米）
+    pub distance: f64,
+    #[serde(rename = "lastUpdate", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub last_update: DateTime,
+}
+
+/// 实时位置事件（SSE `location` 事件），包含同一条消息中的全部航迹点
+#[derive(Debug, Serialize)]
+pub struct LocationEventDto {
+    #[serde(rename = "droneId")]
+    pub drone_id: Option<String>,
+    #[serde(rename = "trackId", serialize_with = "serialize_object_id_as_hex_string")]
+    pub track_id: ObjectId,
+    /// 服务端收到消息的时间，回放时为航迹点的记录时间
+    pub timestamp: chrono::DateTime<Utc>,
+    /// 最新航迹点的经纬度，与早期的 `{longitude, latitude}` 格式兼容
+    pub longitude: f64,
+    pub latitude: f64,
+    pub points: Vec<TrackPointDto>,
+}
+
+impl LocationEventDto {
+    /// `points` 不能为空
+    pub fn new(
+        drone_id: Option<String>,
+        track_id: ObjectId,
+        timestamp: chrono::DateTime<Utc>,
+        points: Vec<TrackPoint>,
+    ) -> Self {
+        let (longitude, latitude) = points.last().map(|p| (p.longitude, p.latitude)).unwrap_or_default();
+        LocationEventDto {
+            drone_id,
+            track_id,
+            timestamp,
+            longitude,
+            latitude,
+            points: points.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// 拼接后航迹中的分段信息
+#[derive(Debug, Serialize)]
+pub struct TrackSegmentSummaryDto {
+    #[serde(rename = "_id", serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    #[serde(rename = "segmentIndex")]
+    pub segment_index: u32,
+    #[serde(rename = "startTime", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub start_time: DateTime,
+    #[serde(rename = "lastUpdate", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub last_update: DateTime,
+    #[serde(rename = "totalPoints")]
+    pub total_points: u32,
+}
+
+/// 按分段顺序拼接的完整航迹
+#[derive(Debug, Serialize)]
+pub struct StitchedTrackResponseDto {
+    #[serde(rename = "flightId", serialize_with = "serialize_object_id_as_hex_string")]
+    pub flight_id: ObjectId,
+    #[serde(rename = "droneId")]
+    pub drone_id: Option<String>,
+    #[serde(rename = "startTime", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub start_time: DateTime,
+    #[serde(rename = "lastUpdate", serialize_with = "serialize_bson_datetime_as_rfc3339_string")]
+    pub last_update: DateTime,
+    #[serde(rename = "totalPoints")]
+    pub total_points: u32,
+    pub segments: Vec<TrackSegmentSummaryDto>,
+    pub coordinates: Vec<TrackPointDto>,
+}
+
+impl StitchedTrackResponseDto {
+    /// 拼接同一flight的分段，`segments` 需按 `segmentIndex` 排序且不能为空
+    pub fn stitch(flight_id: ObjectId, segments: Vec<ShipTrack>) -> Self {
+        let start_time = segments.first().map(|s| s.start_time).unwrap_or_else(DateTime::now);
+        let last_update = segments.iter().map(|s| s.last_update).max().unwrap_or(start_time);
+        let drone_id = segments.iter().find_map(|s| s.drone_id.clone());
+        let mut summaries = Vec::with_capacity(segments.len());
+        let mut coordinates = Vec::new();
+        for segment in segments {
+            summaries.push(TrackSegmentSummaryDto {
+                id: segment.id,
+                segment_index: segment.segment_index,
+                start_time: segment.start_time,
+                last_update: segment.last_update,
+                total_points: segment.coordinates.len() as u32,
+            });
+            coordinates.extend(segment.coordinates.into_iter().map(TrackPointDto::from));
+        }
+        StitchedTrackResponseDto {
+            flight_id,
+            drone_id,
+            start_time,
+            last_update,
+            total_points: coordinates.len() as u32,
+            segments: summaries,
+            coordinates,
         }
     }
 }
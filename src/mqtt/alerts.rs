@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use crate::service::drone_service::DroneService;
+
+/// 低电量告警检测
+///
+/// 只在电量从阈值以上降到阈值以下时告警一次，电量回升后重新计算，避免每条状态消息都告警。
+/// 状态保存在无人机文档的 `lowBattery` 字段中，共享订阅下同一无人机的消息由不同实例处理时也只告警一次。
+pub struct LowBatteryMonitor {
+    drone_service: Arc<DroneService>,
+    threshold: f64,
+}
+
+impl LowBatteryMonitor {
+    pub fn new(drone_service: Arc<DroneService>, threshold: f64) -> Self {
+        Self { drone_service, threshold }
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// 记录无人机的最新电量，刚降到阈值以下时返回 `true`
+    pub async fn check(&self, drone_id: &str, battery: f64) -> mongodb::error::Result<bool> {
+        let low = battery < self.threshold;
+        let changed = self.drone_service.set_low_battery(drone_id, low).await?;
+        Ok(low && changed)
+    }
+}
@@ -1,40 +1,215 @@
-use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use tokio::time::sleep;
-use rumqttc::{AsyncClient, MqttOptions, QoS, TlsConfiguration, Transport};
+use rumqttc::v5;
+use rumqttc::v5::mqttbytes::v5::{ConnectProperties, Packet as V5Packet, PublishProperties};
+use rumqttc::v5::mqttbytes::QoS as V5QoS;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS, TlsConfiguration, Transport};
 use log::{error, info, warn};
+use crate::config::{AppConfig, MqttConfig, MqttProtocol, MqttTransport};
+use crate::mqtt::MessageMetadata;
+
+/// MQTT客户端，按配置的协议版本包装 3.1.1 或 5 的 `AsyncClient`
+#[derive(Clone)]
+pub enum MqttClient {
+    V311(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+impl MqttClient {
+    pub async fn subscribe(&self, topic: &str, qos: QoS) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            MqttClient::V311(client) => client.subscribe(topic, qos).await?,
+            MqttClient::V5(client) => client.subscribe(topic, v5_qos(qos)).await?,
+        }
+        Ok(())
+    }
+
+    /// 不等待地发布消息，请求队列已满时返回错误
+    ///
+    /// 工作任务中不能等待请求队列：事件循环可能正因工作队列已满而阻塞，等待会形成死锁。
+    pub fn try_publish(
+        &self,
+        topic: &str,
+        qos: QoS,
+        payload: Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            MqttClient::V311(client) => client.try_publish(topic, qos, false, payload)?,
+            MqttClient::V5(client) => client.try_publish(topic, v5_qos(qos), false, payload)?,
+        }
+        Ok(())
+    }
+
+    /// 请求断开连接，DISCONNECT报文由事件循环发出（见 [`MqttEvent::DisconnectSent`]）
+    pub async fn disconnect(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            MqttClient::V311(client) => client.disconnect().await?,
+            MqttClient::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+/// 与协议版本无关的MQTT事件
+pub enum MqttEvent {
+    ConnAck { session_present: bool },
+    Publish(IncomingPublish),
+    /// 其他收到的报文，仅用于日志
+    Incoming(String),
+    /// DISCONNECT报文已发出
+    DisconnectSent,
+    Outgoing,
+}
+
+/// 收到的PUBLISH报文
+pub struct IncomingPublish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub metadata: MessageMetadata,
+}
+
+/// MQTT事件循环，与 [`MqttClient`] 成对创建
+// 整个进程只有一个事件循环，不需要为缩小枚举而装箱
+#[allow(clippy::large_enum_variant)]
+pub enum MqttEventLoop {
+    V311(EventLoop),
+    V5(v5::EventLoop),
+}
+
+impl MqttEventLoop {
+    pub async fn poll(&mut self) -> Result<MqttEvent, Box<dyn std::error::Error + Send + Sync>> {
+        let event = match self {
+            MqttEventLoop::V311(eventloop) => match eventloop.poll().await? {
+                Event::Incoming(Packet::ConnAck(ack)) => MqttEvent::ConnAck {
+                    session_present: ack.session_present,
+                },
+                Event::Incoming(Packet::Publish(publish)) => MqttEvent::Publish(IncomingPublish {
+                    topic: publish.topic,
+                    payload: publish.payload.to_vec(),
+                    metadata: MessageMetadata::default(),
+                }),
+                Event::Incoming(packet) => MqttEvent::Incoming(format!("{:?}", packet)),
+                Event::Outgoing(Outgoing::Disconnect) => MqttEvent::DisconnectSent,
+                Event::Outgoing(_) => MqttEvent::Outgoing,
+            },
+            MqttEventLoop::V5(eventloop) => match eventloop.poll().await? {
+                v5::Event::Incoming(V5Packet::ConnAck(ack)) => MqttEvent::ConnAck {
+                    session_present: ack.session_present,
+                },
+                v5::Event::Incoming(V5Packet::Publish(publish)) => MqttEvent::Publish(IncomingPublish {
+                    topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+                    payload: publish.payload.to_vec(),
+                    metadata: publish.properties.map(metadata_from_properties).unwrap_or_default(),
+                }),
+                v5::Event::Incoming(packet) => MqttEvent::Incoming(format!("{:?}", packet)),
+                v5::Event::Outgoing(Outgoing::Disconnect) => MqttEvent::DisconnectSent,
+                v5::Event::Outgoing(_) => MqttEvent::Outgoing,
+            },
+        };
+        Ok(event)
+    }
+}
+
+fn metadata_from_properties(properties: PublishProperties) -> MessageMetadata {
+    MessageMetadata {
+        content_type: properties.content_type,
+        correlation_data: properties.correlation_data.map(|data| data.to_vec()),
+        user_properties: properties.user_properties,
+        message_expiry: properties
+            .message_expiry_interval
+            .map(|secs| Duration::from_secs(secs as u64)),
+    }
+}
+
+fn v5_qos(qos: QoS) -> V5QoS {
+    match qos {
+        QoS::AtMostOnce => V5QoS::AtMostOnce,
+        QoS::AtLeastOnce => V5QoS::AtLeastOnce,
+        QoS::ExactlyOnce => V5QoS::ExactlyOnce,
+    }
+}
 
 /// 创建MQTT客户端配置
 pub async fn create_mqtt_client(
-    host: &str,
-    port: u16,
-    username: &str,
-    password: &str,
-    ca_cert_path: &str,
-) -> Result<(AsyncClient, rumqttc::EventLoop), Box<dyn std::error::Error>> {
-    let mut mqttoptions = MqttOptions::new(username, host, port);
-    mqttoptions.set_credentials(username.to_string(), password.to_string());
-    
-    // 配置TLS
-    let mut ca_file = File::open(ca_cert_path).await?;
-    let mut ca = Vec::new();
-    ca_file.read_to_end(&mut ca).await?;
-    mqttoptions.set_transport(Transport::Tls(TlsConfiguration::Simple {
+    config: &AppConfig,
+) -> Result<(MqttClient, MqttEventLoop), Box<dyn std::error::Error>> {
+    let mqtt = &config.mqtt;
+    // WebSocket传输时rumqttc从URL中解析地址和端口
+    let broker_addr = match mqtt.transport {
+        MqttTransport::Ws => format!("ws://{}:{}{}", mqtt.host, mqtt.port, mqtt.ws_path),
+        MqttTransport::Wss => format!("wss://{}:{}{}", mqtt.host, mqtt.port, mqtt.ws_path),
+        MqttTransport::Tcp | MqttTransport::Tls => mqtt.host.clone(),
+    };
+
+    // 配置传输层
+    let transport = match mqtt.transport {
+        MqttTransport::Tcp => Transport::Tcp,
+        MqttTransport::Ws => Transport::Ws,
+        MqttTransport::Tls => Transport::Tls(load_tls_configuration(mqtt).await?),
+        MqttTransport::Wss => Transport::Wss(load_tls_configuration(mqtt).await?),
+    };
+    info!("MQTT传输方式: {:?}，协议版本: {:?}", mqtt.transport, mqtt.protocol);
+    let keep_alive = Duration::from_secs(mqtt.keep_alive_secs);
+    let capacity = config.channels.mqtt_request_capacity;
+
+    match mqtt.protocol {
+        MqttProtocol::V311 => {
+            let mut mqttoptions = MqttOptions::new(config.mqtt_client_id(), broker_addr, mqtt.port);
+            mqttoptions.set_credentials(mqtt.username.clone(), mqtt.password.clone());
+            mqttoptions.set_transport(transport);
+            mqttoptions.set_keep_alive(keep_alive);
+            mqttoptions.set_clean_session(mqtt.clean_session);
+            let (client, eventloop) = AsyncClient::new(mqttoptions, capacity);
+            Ok((MqttClient::V311(client), MqttEventLoop::V311(eventloop)))
+        }
+        MqttProtocol::V5 => {
+            let mut mqttoptions = v5::MqttOptions::new(config.mqtt_client_id(), broker_addr, mqtt.port);
+            mqttoptions.set_credentials(mqtt.username.clone(), mqtt.password.clone());
+            mqttoptions.set_transport(transport);
+            mqttoptions.set_keep_alive(keep_alive);
+            mqttoptions.set_clean_start(mqtt.clean_session);
+            // MQTT 5 的会话默认在断开时结束，持久会话需要显式设置过期时间
+            if !mqtt.clean_session {
+                let mut properties = ConnectProperties::new();
+                properties.session_expiry_interval = Some(mqtt.session_expiry_secs);
+                mqttoptions.set_connect_properties(properties);
+            }
+            let (client, eventloop) = v5::AsyncClient::new(mqttoptions, capacity);
+            Ok((MqttClient::V5(client), MqttEventLoop::V5(eventloop)))
+        }
+    }
+}
+
+/// 读取CA证书，以及可选的客户端证书和私钥（双向TLS）
+async fn load_tls_configuration(mqtt: &MqttConfig) -> Result<TlsConfiguration, Box<dyn std::error::Error>> {
+    let ca = read_file(&mqtt.ca_cert_path).await?;
+    let client_auth = match (&mqtt.client_cert_path, &mqtt.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            info!("启用客户端证书认证: {}", cert_path);
+            Some((read_file(cert_path).await?, read_file(key_path).await?))
+        }
+        _ => None,
+    };
+    Ok(TlsConfiguration::Simple {
         ca,
         alpn: None,
-        client_auth: None,
-    }));
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
-    mqttoptions.set_clean_session(false);
+        client_auth,
+    })
+}
 
-    let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
-    Ok((client, eventloop))
+async fn read_file(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|e| format!("无法打开文件 {}: {}", path, e))?;
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).await?;
+    Ok(content)
 }
 
 /// 带重试的订阅函数
-pub async fn subscribe_with_retry(client: &mut AsyncClient, topic: &str, qos: QoS, max_retries: u8) {
+pub async fn subscribe_with_retry(client: &MqttClient, topic: &str, qos: QoS, max_retries: u8) {
     for attempt in 1..=max_retries {
         match client.subscribe(topic, qos).await {
             Ok(_) => {
@@ -49,3 +224,12 @@ pub async fn subscribe_with_retry(client: &mut AsyncClient, topic: &str, qos: Qo
     }
     error!("多次尝试后仍无法订阅: {}", topic);
 }
+
+/// 将配置中的QoS等级转换为rumqttc的QoS
+pub fn qos_from_level(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtLeastOnce,
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use log::info;
+use rand::Rng;
+use serde::Serialize;
+use tokio::sync::watch;
+
+/// MQTT连接阶段
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum ConnectionPhase {
+    /// 正在建立连接
+    Connecting,
+    /// 已连接（收到ConnAck）
+    Connected,
+    /// 连接失败，等待下一次重连
+    BackingOff {
+        #[serde(rename = "retryInMs")]
+        retry_in_ms: u64,
+    },
+}
+
+/// 对外发布的MQTT连接状态
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatus {
+    #[serde(flatten)]
+    pub phase: ConnectionPhase,
+    /// 进入当前阶段的时间
+    pub since: DateTime<Utc>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    #[serde(rename = "reconnectCount")]
+    pub reconnect_count: u64,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        Self {
+            phase: ConnectionPhase::Connecting,
+            since: Utc::now(),
+            last_error: None,
+            reconnect_count: 0,
+        }
+    }
+}
+
+/// 连接状态发布者，通过watch通道广播最新状态
+pub struct ConnectionMonitor {
+    tx: watch::Sender<ConnectionStatus>,
+}
+
+impl ConnectionMonitor {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(ConnectionStatus::default());
+        Self { tx }
+    }
+
+    /// 订阅连接状态变化
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionStatus> {
+        self.tx.subscribe()
+    }
+
+    pub fn connecting(&self) {
+        self.set_phase(ConnectionPhase::Connecting);
+    }
+
+    pub fn connected(&self) {
+        self.set_phase(ConnectionPhase::Connected);
+    }
+
+    /// 记录连接错误并进入退避阶段
+    pub fn backing_off(&self, error: String, delay: Duration) {
+        self.tx.send_modify(|status| {
+            status.phase = ConnectionPhase::BackingOff {
+                retry_in_ms: delay.as_millis() as u64,
+            };
+            status.since = Utc::now();
+            status.last_error = Some(error);
+            status.reconnect_count += 1;
+        });
+    }
+
+    fn set_phase(&self, phase: ConnectionPhase) {
+        self.tx.send_if_modified(|status| {
+            if status.phase == phase {
+                return false;
+            }
+            info!("MQTT连接状态: {:?} -> {:?}", status.phase, phase);
+            status.phase = phase;
+            status.since = Utc::now();
+            true
+        });
+    }
+}
+
+/// 带抖动的指数退避
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// 下一次重连前的等待时间：基准值按2的幂增长并封顶，
+    /// 实际等待在基准值的一半到全部之间随机，避免多个实例同时重连
+    pub fn next_delay(&mut self) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempt);
+        let base = self.initial.saturating_mul(factor).min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        let half = base / 2;
+        let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter_ms)
+    }
+
+    /// 连接成功后重置
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 第 `attempt` 次重连的基准等待时间
+    fn base(attempt: u32) -> Duration {
+        Duration::from_millis((500u64 << attempt).min(30_000))
+    }
+
+    #[test]
+    fn delay_stays_between_half_and_full_base() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(30));
+        for attempt in 0..12 {
+            let delay = backoff.next_delay();
+            let base = base(attempt);
+            assert!(delay >= base / 2 && delay <= base, "第{}次: {:?} 不在 {:?} 的一半到全部之间", attempt, delay, base);
+        }
+    }
+
+    #[test]
+    fn delay_is_capped_without_overflow() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+        for _ in 0..100 {
+            assert!(backoff.next_delay() <= Duration::from_secs(60));
+        }
+        assert!(backoff.next_delay() >= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(60));
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use chrono::Utc;
+use futures::FutureExt;
+use log::{error, info, warn};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::mqtt::{HandlerError, MessageMetadata, MessageRouter, RoutedMessage};
+use crate::service::dead_letter_service::DeadLetterService;
+
+/// 并发消息分发器
+///
+/// 消息按保序键（无人机ID）哈希到固定的工作任务，保证本实例收到的同一无人机的消息按到达顺序处理，
+/// 不同无人机之间并发处理。多实例共享订阅时broker按消息而不是按无人机分配，同一无人机的消息
+/// 可能由不同实例并发处理，跨实例没有顺序保证，写入需与顺序无关（航迹点按时间排序插入）。每个工作任务的队列有界，队列满时 [`Dispatcher::dispatch`]
+/// 会等待，从而对MQTT事件循环形成背压。被处理器拒绝的消息存入死信集合。
+pub struct Dispatcher {
+    router: Arc<MessageRouter>,
+    workers: Vec<mpsc::Sender<Job>>,
+    tasks: TaskTracker,
+    closing: CancellationToken,
+}
+
+impl Dispatcher {
+    /// 创建分发器并启动 `worker_count` 个工作任务
+    pub fn new(
+        router: Arc<MessageRouter>,
+        dead_letters: Arc<DeadLetterService>,
+        worker_count: usize,
+        queue_depth: usize,
+    ) -> Self {
+        let tasks = TaskTracker::new();
+        let closing = CancellationToken::new();
+        let workers = (0..worker_count)
+            .map(|index| {
+                let (tx, rx) = mpsc::channel(queue_depth);
+                tasks.spawn(run_worker(index, rx, dead_letters.clone(), closing.clone()));
+                tx
+            })
+            .collect();
+        tasks.close();
+        info!("消息分发器已启动: {}个工作任务, 队列深度{}", worker_count, queue_depth);
+        Self { router, workers, tasks, closing }
+    }
+
+    pub fn router(&self) -> &Arc<MessageRouter> {
+        &self.router
+    }
+
+    /// 将消息投递到对应的工作任务，队列已满时等待
+    pub async fn dispatch(&self, topic: &str, payload: Vec<u8>, metadata: MessageMetadata) {
+        let Some(routed) = self.router.resolve(topic, payload, metadata) else {
+            return;
+        };
+        let worker = &self.workers[self.worker_index(routed.message.ordering_key())];
+        if worker.capacity() == 0 {
+            info!("工作队列已满，等待处理: {}", topic);
+        }
+        if worker.send(Job { routed, done: None }).await.is_err() {
+            error!("工作任务已退出，丢弃消息: {}", topic);
+        }
+    }
+
+    /// 通过同一无人机的工作任务重放消息并等待结果，与实时消息按到达顺序处理
+    ///
+    /// 处理结果直接返回给调用方，被拒绝的消息不会再存入死信集合。
+    pub async fn replay(&self, routed: RoutedMessage) -> Result<(), HandlerError> {
+        let worker = &self.workers[self.worker_index(routed.message.ordering_key())];
+        let (tx, rx) = oneshot::channel();
+        let stopped = || HandlerError::Failed("消息分发器已停止".to_string());
+        worker.send(Job { routed, done: Some(tx) }).await.map_err(|_| stopped())?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    /// 停止接收新消息，等待工作任务处理完队列中已有的消息
+    pub async fn shutdown(&self) {
+        self.closing.cancel();
+        self.tasks.wait().await;
+        info!("消息分发器已停止");
+    }
+
+    fn worker_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.workers.len() as u64) as usize
+    }
+}
+
+/// 工作队列中的消息，重放的消息附带返回结果的通道
+struct Job {
+    routed: RoutedMessage,
+    done: Option<oneshot::Sender<Result<(), HandlerError>>>,
+}
+
+async fn run_worker(
+    index: usize,
+    mut rx: mpsc::Receiver<Job>,
+    dead_letters: Arc<DeadLetterService>,
+    closing: CancellationToken,
+) {
+    loop {
+        let routed = tokio::select! {
+            routed = rx.recv() => routed,
+            // 关闭队列后继续处理已排队的消息，recv返回None时退出
+            _ = closing.cancelled(), if !rx.is_closed() => {
+                rx.close();
+                continue;
+            }
+        };
+        let Some(Job { routed, done }) = routed else {
+            break;
+        };
+        if let Some(done) = done {
+            let _ = done.send(handle_caught(&routed).await);
+            continue;
+        }
+        // 排队期间已过期的消息直接丢弃，不写入也不进入死信
+        if routed.message.is_expired(Utc::now()) {
+            warn!("消息已过期，丢弃: {} (过期时间 {:?})", routed.message.topic, routed.message.expires_at());
+            continue;
+        }
+        process_message(&routed, &dead_letters).await;
+    }
+    info!("工作任务{}已退出", index);
+}
+
+/// 调用处理器，处理器panic时视为消息被拒绝，工作任务继续运行
+async fn handle_caught(routed: &RoutedMessage) -> Result<(), HandlerError> {
+    match AssertUnwindSafe(routed.handle()).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "未知错误".to_string());
+            error!("处理消息时发生panic: {} - {}", routed.message.topic, message);
+            Err(HandlerError::Rejected(format!("处理器panic: {}", message)))
+        }
+    }
+}
+
+/// 处理消息，被拒绝和导致处理器panic的消息存入死信集合
+pub async fn process_message(routed: &RoutedMessage, dead_letters: &DeadLetterService) {
+    let message = &routed.message;
+    match handle_caught(routed).await {
+        Ok(()) => {}
+        Err(HandlerError::Rejected(reason)) => {
+            warn!("消息被拒绝: {} - {}", message.topic, reason);
+            match dead_letters
+                .record(
+                    &message.topic,
+                    message.payload.clone(),
+                    message.metadata.clone(),
+                    message.received_at,
+                    &reason,
+                )
+                .await
+            {
+                Ok(id) => info!("已存入死信: {}", id),
+                Err(e) => error!("保存死信失败: {}", e),
+            }
+        }
+        Err(HandlerError::Failed(reason)) => {
+            error!("{}: {}", message.topic, reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use futures::future::BoxFuture;
+    use rumqttc::QoS;
+    use crate::mqtt::{MessageHandler, MqttMessage};
+
+    /// `panic` 主题的消息使处理器panic，其余消息计数
+    struct PanicOnce {
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl MessageHandler for PanicOnce {
+        fn handle<'a>(&'a self, message: &'a MqttMessage) -> BoxFuture<'a, Result<(), HandlerError>> {
+            async move {
+                if message.param("id") == Some("panic") {
+                    panic!("处理器错误");
+                }
+                self.handled.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn worker_survives_handler_panic() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let router = MessageRouter::new()
+            .route("drone/{id}/state", QoS::AtLeastOnce, Arc::new(PanicOnce { handled: handled.clone() }))
+            .unwrap();
+        // 死信无法写入时只记录错误
+        let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=50")
+            .await
+            .unwrap();
+        let dead_letters = Arc::new(DeadLetterService::new(client.database("test").collection("dead_letters")));
+        let dispatcher = Dispatcher::new(Arc::new(router), dead_letters, 1, 4);
+
+        let routed = dispatcher.router().resolve("drone/panic/state", Vec::new(), MessageMetadata::default());
+        assert!(matches!(handle_caught(&routed.unwrap()).await, Err(HandlerError::Rejected(_))));
+
+        dispatcher.dispatch("drone/panic/state", Vec::new(), MessageMetadata::default()).await;
+        dispatcher.dispatch("drone/A1/state", Vec::new(), MessageMetadata::default()).await;
+        dispatcher.shutdown().await;
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn replay_returns_handler_result() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let router = MessageRouter::new()
+            .route("drone/{id}/state", QoS::AtLeastOnce, Arc::new(PanicOnce { handled: handled.clone() }))
+            .unwrap();
+        let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=50")
+            .await
+            .unwrap();
+        let dead_letters = Arc::new(DeadLetterService::new(client.database("test").collection("dead_letters")));
+        let dispatcher = Dispatcher::new(Arc::new(router), dead_letters, 2, 4);
+
+        let resolve = |topic| dispatcher.router().resolve(topic, Vec::new(), MessageMetadata::default()).unwrap();
+        assert!(dispatcher.replay(resolve("drone/A1/state")).await.is_ok());
+        assert!(matches!(dispatcher.replay(resolve("drone/panic/state")).await, Err(HandlerError::Rejected(_))));
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+
+        dispatcher.shutdown().await;
+        assert!(matches!(dispatcher.replay(resolve("drone/A1/state")).await, Err(HandlerError::Failed(_))));
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::{Arc, OnceLock};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{info, warn};
+use rumqttc::QoS;
+use tokio::sync::broadcast;
+
+use crate::config::ClusterConfig;
+use crate::live::{LastKnownState, LocationHistory};
+use crate::mqtt::{HandlerError, MessageHandler, MqttClient, MqttMessage};
+
+/// 实时数据类型，对应转发主题的最后一层
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveKind {
+    /// 位置更新，推送给SSE客户端
+    Location,
+    /// flight状态，推送给WebSocket客户端
+    Flight,
+    /// 告警，推送给WebSocket客户端
+    Alert,
+}
+
+impl LiveKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            LiveKind::Location => "location",
+            LiveKind::Flight => "flight",
+            LiveKind::Alert => "alert",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "location" => Some(LiveKind::Location),
+            "flight" => Some(LiveKind::Flight),
+            "alert" => Some(LiveKind::Alert),
+            _ => None,
+        }
+    }
+}
+
+/// 推送给SSE/WebSocket客户端的一条实时数据
+#[derive(Debug, Clone)]
+pub struct LiveEvent {
+    /// 事件ID，单调递增，见 [`LocationHistory`]
+    pub id: u64,
+    pub drone_id: Arc<str>,
+    /// JSON格式的事件内容
+    pub payload: Arc<str>,
+}
+
+/// 实时数据发布者
+///
+/// 处理器通过它把实时数据推送给本实例的SSE/WebSocket客户端。多实例部署时同时转发到
+/// `<prefix>/<instance_id>/<kind>/<drone_id>`，其他实例的 [`FanoutHandler`] 收到后推送给各自的客户端。
+pub struct LiveFanout {
+    /// 单实例运行时为 `None`，不转发
+    instance_id: Option<String>,
+    prefix: String,
+    client: OnceLock<MqttClient>,
+    location_broadcaster: Arc<broadcast::Sender<LiveEvent>>,
+    flight_broadcaster: Arc<broadcast::Sender<LiveEvent>>,
+    alert_broadcaster: Arc<broadcast::Sender<LiveEvent>>,
+    /// 新连接的客户端首先收到的最近状态
+    last_known: Arc<LastKnownState>,
+    /// 事件ID和SSE断线重连时补发的位置事件
+    history: Arc<LocationHistory>,
+}
+
+impl LiveFanout {
+    pub fn new(
+        cluster: &ClusterConfig,
+        location_broadcaster: Arc<broadcast::Sender<LiveEvent>>,
+        flight_broadcaster: Arc<broadcast::Sender<LiveEvent>>,
+        alert_broadcaster: Arc<broadcast::Sender<LiveEvent>>,
+        last_known: Arc<LastKnownState>,
+        history: Arc<LocationHistory>,
+    ) -> Self {
+        Self {
+            instance_id: cluster.enabled().then(|| cluster.instance_id().to_string()),
+            prefix: cluster.fanout_prefix.trim_end_matches('/').to_string(),
+            client: OnceLock::new(),
+            location_broadcaster,
+            flight_broadcaster,
+            alert_broadcaster,
+            last_known,
+            history,
+        }
+    }
+
+    /// 接收其他实例转发的路由模式，单实例运行时为 `None`
+    pub fn route_pattern(&self) -> Option<String> {
+        self.instance_id
+            .as_ref()
+            .map(|_| format!("{}/{{instance}}/{{kind}}/{{drone}}", self.prefix))
+    }
+
+    /// 绑定用于转发的MQTT客户端，客户端在重连时复用，只需绑定一次
+    pub fn attach(&self, client: MqttClient) {
+        if self.instance_id.is_some() && self.client.set(client).is_err() {
+            warn!("实时数据转发已绑定MQTT客户端");
+        }
+    }
+
+    /// 推送给本实例的客户端，并转发给其他实例
+    pub fn publish(&self, kind: LiveKind, drone_id: &str, payload: String) {
+        if let (Some(instance_id), Some(client)) = (&self.instance_id, self.client.get()) {
+            let topic = format!("{}/{}/{}/{}", self.prefix, instance_id, kind.as_str(), drone_id);
+            // 实时数据丢失可以接受，不能因为请求队列已满阻塞工作任务
+            if let Err(e) = client.try_publish(&topic, QoS::AtMostOnce, payload.clone().into_bytes()) {
+                warn!("转发实时数据失败: {} - {}", topic, e);
+            }
+        }
+        self.deliver_local(kind, drone_id, payload);
+    }
+
+    fn deliver_local(&self, kind: LiveKind, drone_id: &str, payload: String) {
+        let payload: Arc<str> = payload.into();
+        self.last_known.record(kind, drone_id, &payload);
+        match kind {
+            // 位置事件同时保存到历史中，供SSE客户端重连时补发
+            LiveKind::Location => match self.history.publish(&self.location_broadcaster, drone_id, payload) {
+                Ok(_) => info!("已广播位置消息到SSE客户端"),
+                Err(e) => warn!("广播位置消息失败: {}", e),
+            },
+            LiveKind::Flight => match self.flight_broadcaster.send(self.event(drone_id, payload)) {
+                Ok(_) => info!("已广播flight消息到WebSocket客户端"),
+                Err(e) => warn!("广播flight消息失败: {}", e),
+            },
+            LiveKind::Alert => match self.alert_broadcaster.send(self.event(drone_id, payload)) {
+                Ok(_) => info!("已广播告警到WebSocket客户端"),
+                Err(e) => warn!("广播告警失败: {}", e),
+            },
+        }
+    }
+
+    fn event(&self, drone_id: &str, payload: Arc<str>) -> LiveEvent {
+        LiveEvent {
+            id: self.history.next_id(),
+            drone_id: drone_id.into(),
+            payload,
+        }
+    }
+}
+
+/// 接收其他实例转发的实时数据，主题模式见 [`LiveFanout::route_pattern`]
+pub struct FanoutHandler {
+    pub fanout: Arc<LiveFanout>,
+}
+
+impl MessageHandler for FanoutHandler {
+    fn handle<'a>(&'a self, message: &'a MqttMessage) -> BoxFuture<'a, Result<(), HandlerError>> {
+        async move {
+            // 本实例发出的消息已经推送过
+            if message.param("instance") == self.fanout.instance_id.as_deref() {
+                return Ok(());
+            }
+            // 转发消息不进入死信，无法识别时只记录日志
+            let (Some(kind), Some(drone_id)) = (message.param("kind").and_then(LiveKind::parse), message.param("drone"))
+            else {
+                warn!("无法识别的转发主题: {}", message.topic);
+                return Ok(());
+            };
+            match String::from_utf8(message.payload.clone()) {
+                Ok(payload) => self.fanout.deliver_local(kind, drone_id, payload),
+                Err(e) => warn!("转发消息不是UTF-8文本: {} - {}", message.topic, e),
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
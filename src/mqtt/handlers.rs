@@ -1,197 +1,325 @@
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::broadcast;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use log::{error, info, warn};
-use rumqttc::{Event, QoS};
-use serde_json;
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 use crate::config::AppConfig;
-use crate::model::flight::FlightDto;
-use crate::mqtt::{create_mqtt_client, subscribe_with_retry};
+use crate::model::drone::ActiveDocuments;
+use crate::model::flight::{AlertEventDto, AlertKind, FlightEventDto};
+use crate::model::ship_track::LocationEventDto;
+use crate::mqtt::{
+    create_mqtt_client, subscribe_with_retry, Backoff, ConnectionMonitor, Dispatcher, HandlerError,
+    IncomingPublish, LiveFanout, LiveKind, LowBatteryMonitor, MessageHandler, MqttClient, MqttEvent, MqttEventLoop,
+    MqttMessage,
+};
 use crate::service::flight_service::FlightService;
+use crate::service::provisioning_service::ProvisioningService;
 use crate::service::ship_track_service::ShipTrackService;
+use crate::telemetry::{decode_location_payload, decode_state_payload};
 
+/// 关闭时等待DISCONNECT报文发出的最长时间
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 // 运行MQTT事件循环
+//
+// 连接出错后复用同一个EventLoop重连，保留未确认的消息和持久会话；
+// 重连间隔按带抖动的指数退避增长，连接状态通过 `monitor` 对外发布。
+// `shutdown` 取消后发送DISCONNECT并返回，已投递给工作任务的消息由 [`Dispatcher::shutdown`] 等待处理完。
 pub async fn run_mqtt_loop(
     config: AppConfig,
-    track_service: Arc<ShipTrackService>,
-    flight_service: Arc<FlightService>,
-    flight_broadcaster: Arc<broadcast::Sender<String>>,
-    location_broadcaster: Arc<broadcast::Sender<String>>,
+    dispatcher: Arc<Dispatcher>,
+    monitor: Arc<ConnectionMonitor>,
+    fanout: Arc<LiveFanout>,
+    shutdown: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let mut backoff = Backoff::new(
+        Duration::from_millis(config.mqtt.reconnect_initial_delay_ms),
+        Duration::from_secs(config.mqtt.reconnect_max_delay_secs),
+    );
+
+    // 只有客户端本身创建失败（如证书无法读取）时才重新创建
+    let (client, mut eventloop) = loop {
+        monitor.connecting();
+        match create_mqtt_client(&config).await {
+            Ok(pair) => break pair,
+            Err(e) => {
+                let delay = backoff.next_delay();
+                error!("创建MQTT客户端失败: {}，{}ms后重试", e, delay.as_millis());
+                monitor.backing_off(e.to_string(), delay);
+                if sleep_or_shutdown(delay, &shutdown).await {
+                    return Ok(());
+                }
+            }
+        }
+    };
+    info!("MQTT客户端创建成功: {}", config.mqtt_client_id());
+    fanout.attach(client.clone());
+
+    // 事件循环处理
+    let mut connected = false;
     loop {
-        match create_mqtt_client(
-            &config.mqtt_host,
-            config.mqtt_port,
-            &config.mqtt_username,
-            &config.mqtt_password,
-            &config.ca_cert_path,
-        ).await {
-            Ok((mut client, mut eventloop)) => {
-                info!("MQTT客户端创建成功");
-
-                // 订阅主题
-                subscribe_with_retry(&mut client, "drone/+/location", QoS::AtLeastOnce, 3).await;
-                subscribe_with_retry(&mut client, "drone/+/state", QoS::AtLeastOnce, 3).await;
-
-                // 事件循环处理
-                loop {
-                    match eventloop.poll().await {
-                        Ok(event) => {
-                            match event {
-                                Event::Incoming(packet) => {
-                                    info!("收到消息: {:?}", packet);
-                                    handle_mqtt_message(
-                                        track_service.clone(),
-                                        flight_service.clone(),
-                                        flight_broadcaster.clone(),
-                                        location_broadcaster.clone(),
-                                        packet
-                                    ).await;
-                                }
-                                Event::Outgoing(_) => {}
-                            }
-                        }
-                        Err(e) => {
-                            error!("事件循环错误: {}", e);
-                            break; // 跳出内层循环，重新创建客户端
+        let event = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            event = eventloop.poll() => event,
+        };
+        match event {
+            Ok(MqttEvent::ConnAck { session_present }) => {
+                info!("MQTT连接成功, session_present: {}", session_present);
+                connected = true;
+                backoff.reset();
+                monitor.connected();
+                // 服务端没有保留会话时重新订阅路由表中注册的主题
+                if !session_present {
+                    let client = client.clone();
+                    let subscriptions = dispatcher.router().subscriptions();
+                    let share_group = config.cluster.share_group.clone();
+                    let retries = config.mqtt.subscribe_retries;
+                    tokio::spawn(async move {
+                        for subscription in subscriptions {
+                            let topic = subscription.topic(share_group.as_deref());
+                            subscribe_with_retry(&client, &topic, subscription.qos, retries).await;
                         }
-                    }
+                    });
                 }
             }
+            Ok(MqttEvent::Publish(publish)) => {
+                info!("收到消息: {} ({}字节, {:?})", publish.topic, publish.payload.len(), publish.metadata);
+                handle_mqtt_message(&dispatcher, publish).await;
+            }
+            Ok(MqttEvent::Incoming(packet)) => {
+                info!("收到消息: {}", packet);
+            }
+            Ok(MqttEvent::DisconnectSent) | Ok(MqttEvent::Outgoing) => {}
             Err(e) => {
-                error!("创建MQTT客户端失败: {}", e);
+                connected = false;
+                let delay = backoff.next_delay();
+                error!("事件循环错误: {}，{}ms后重连", e, delay.as_millis());
+                monitor.backing_off(e.to_string(), delay);
+                if sleep_or_shutdown(delay, &shutdown).await {
+                    break;
+                }
+                monitor.connecting();
             }
         }
+    }
 
-        error!("尝试重新连接...");
-        sleep(Duration::from_secs(5)).await;
+    if connected {
+        disconnect(&client, &mut eventloop).await;
     }
+    Ok(())
 }
 
-/// 处理MQTT消息的主要分发函数
-pub async fn handle_mqtt_message(
-    track_service: Arc<ShipTrackService>,
-    flight_service: Arc<FlightService>,
-    flight_broadcaster: Arc<broadcast::Sender<String>>,
-    location_broadcaster: Arc<broadcast::Sender<String>>,
-    packet: rumqttc::Packet,
-) {
-    match packet {
-        rumqttc::Packet::Publish(publish) => {
-            let topic = publish.topic.clone();
-            let payload = publish.payload.to_vec();
-
-            // 从主题中提取任务ID
-            let parts: Vec<&str> = topic.split('/').collect();
-            if parts.len() < 3 {
-                error!("无效的主题格式: {}", topic);
-                return;
-            }
-            let task_type = parts[2];
-            let task_id = parts[1].to_string();
+/// 等待 `delay`，期间收到关闭信号时返回 `true`
+async fn sleep_or_shutdown(delay: Duration, shutdown: &CancellationToken) -> bool {
+    tokio::select! {
+        _ = shutdown.cancelled() => true,
+        _ = sleep(delay) => false,
+    }
+}
 
-            match task_type {
-                "location" => {
-                    info!("接收到位置更新任务: {}", task_id);
-                    handle_location_message(track_service, task_id, payload, location_broadcaster).await;
-                }
-                "state" => {
-                    info!("接收到状态更新任务: {}", task_id);
-                    handle_state_message(flight_service, task_id, payload, flight_broadcaster).await;
-                }
-                _ => {
-                    warn!("未知任务类型: {}", task_type);
+/// 发送DISCONNECT，让服务端正常结束会话而不是等待keep alive超时
+async fn disconnect(client: &MqttClient, eventloop: &mut MqttEventLoop) {
+    info!("正在断开MQTT连接");
+    if let Err(e) = client.disconnect().await {
+        warn!("MQTT断开请求失败: {}", e);
+        return;
+    }
+    let sent = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(MqttEvent::DisconnectSent) => return true,
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("断开MQTT连接时出错: {}", e);
+                    return false;
                 }
             }
         }
-        _ => {}
+    };
+    match tokio::time::timeout(DISCONNECT_TIMEOUT, sent).await {
+        Ok(true) => info!("MQTT连接已断开"),
+        Ok(false) => {}
+        Err(_) => warn!("等待MQTT断开超时"),
+    }
+}
+
+/// 处理MQTT消息的主要分发函数，消息交给工作任务处理
+pub async fn handle_mqtt_message(dispatcher: &Dispatcher, publish: IncomingPublish) {
+    dispatcher.dispatch(&publish.topic, publish.payload, publish.metadata).await;
+}
+
+/// 位置消息处理器，主题模式需包含 `{id}` 捕获（无人机序列号）
+pub struct LocationHandler {
+    pub track_service: Arc<ShipTrackService>,
+    pub provisioning: Arc<ProvisioningService>,
+    pub live: Arc<LiveFanout>,
+}
+
+impl MessageHandler for LocationHandler {
+    fn handle<'a>(&'a self, message: &'a MqttMessage) -> BoxFuture<'a, Result<(), HandlerError>> {
+        async move {
+            let drone_id = message
+                .param("id")
+                .map(str::to_string)
+                .ok_or_else(|| HandlerError::Rejected(format!("主题缺少无人机ID: {}", message.topic)))?;
+            info!("接收到位置更新: {}", drone_id);
+            handle_location_message(
+                self.track_service.clone(),
+                &self.provisioning,
+                drone_id,
+                message,
+                &self.live,
+            ).await
+        }
+        .boxed()
+    }
+}
+
+/// flight状态消息处理器，主题模式需包含 `{id}` 捕获（无人机序列号）
+pub struct StateHandler {
+    pub flight_service: Arc<FlightService>,
+    pub provisioning: Arc<ProvisioningService>,
+    pub live: Arc<LiveFanout>,
+    pub low_battery: Arc<LowBatteryMonitor>,
+}
+
+impl MessageHandler for StateHandler {
+    fn handle<'a>(&'a self, message: &'a MqttMessage) -> BoxFuture<'a, Result<(), HandlerError>> {
+        async move {
+            let drone_id = message
+                .param("id")
+                .map(str::to_string)
+                .ok_or_else(|| HandlerError::Rejected(format!("主题缺少无人机ID: {}", message.topic)))?;
+            info!("接收到状态更新: {}", drone_id);
+            handle_state_message(
+                self.flight_service.clone(),
+                &self.provisioning,
+                drone_id,
+                message,
+                &self.live,
+                &self.low_battery,
+            ).await
+        }
+        .boxed()
     }
 }
 
 /// 处理flight状态消息并广播到WebSocket
 pub async fn handle_state_message(
     flight_service: Arc<FlightService>,
-    task_id: String,
-    payload: Vec<u8>,
-    flight_broadcaster: Arc<broadcast::Sender<String>>,
-) {
+    provisioning: &ProvisioningService,
+    drone_id: String,
+    message: &MqttMessage,
+    live: &LiveFanout,
+    low_battery: &LowBatteryMonitor,
+) -> Result<(), HandlerError> {
     // 解析消息内容
-    match serde_json::from_slice::<FlightDto>(&payload) {
-        Ok(state) => {
-            info!("handle_state_message:{:?}", state);
-            if let Err(e) = flight_service.get(&task_id).await {
-                error!("获取航行报告任务失败: {:?}", e);
-                return;
-            }
-            match flight_service.append_data_and_update(&task_id, state.clone()).await {
-                Ok(_) => {
-                    info!("航行报告消息处理成功: {}", task_id);
-                    
-                    // 创建包含task_id的完整消息结构
-                    let flight_message = serde_json::json!({
-                        // "task_id": task_id,
-                        "data": state
-                    });
-                    
-                    // 将消息广播到所有WebSocket连接
-                    if let Ok(json_str) = serde_json::to_string(&flight_message) {
-                        if let Err(e) = flight_broadcaster.send(json_str) {
-                            warn!("广播flight消息失败: {}", e);
-                        } else {
-                            info!("已广播flight消息到WebSocket客户端");
-                        }
-                    }
-                }
-                Err(e) => warn!("航行报告消息处理失败: {}", e),
-            }
-        }
-        Err(e) => {
-            error!("解析任务消息失败: {}", e);
+    let state = decode_state_payload(&message.payload, message.param("suffix"), message.metadata.content_type.as_deref())
+        .map_err(|e| HandlerError::Rejected(e.to_string()))?;
+    info!("handle_state_message:{:?}", state);
+    let active = provision(provisioning, &drone_id, message.received_at).await?;
+    flight_service
+        .record_sample(&active.flight_id, &drone_id, message.received_at.into(), state.clone())
+        .await
+        .map_err(|e| HandlerError::Failed(format!("航行报告消息处理失败: {}", e)))?;
+    info!("航行报告消息处理成功: {} (flight {})", drone_id, active.flight_id);
+
+    let alert_triggered = low_battery
+        .check(&drone_id, state.battery_capacity)
+        .await
+        .inspect_err(|e| warn!("更新无人机 {} 的低电量状态失败: {}", drone_id, e))
+        .unwrap_or(false);
+    if alert_triggered {
+        let alert = AlertEventDto {
+            drone_id: drone_id.clone(),
+            flight_id: active.flight_id,
+            timestamp: message.received_at,
+            kind: AlertKind::LowBattery,
+            message: format!("电量低于{}%", low_battery.threshold()),
+            value: state.battery_capacity,
+        };
+        info!("无人机 {} 低电量告警: {}%", drone_id, state.battery_capacity);
+        if let Ok(json_str) = serde_json::to_string(&alert) {
+            live.publish(LiveKind::Alert, &drone_id, json_str);
         }
     }
+
+    // 推送包含无人机和flight的状态事件
+    let flight_event = FlightEventDto {
+        drone_id: Some(drone_id.clone()),
+        flight_id: active.flight_id,
+        timestamp: message.received_at,
+        data: state,
+    };
+
+    // 将消息广播到所有WebSocket连接
+    if let Ok(json_str) = serde_json::to_string(&flight_event) {
+        live.publish(LiveKind::Flight, &drone_id, json_str);
+    }
+    Ok(())
 }
 
 /// 处理位置消息并广播到SSE
 pub async fn handle_location_message(
     db_service: Arc<ShipTrackService>,
-    task_id: String,
-    payload: Vec<u8>,
-    location_broadcaster: Arc<broadcast::Sender<String>>,
-) {
+    provisioning: &ProvisioningService,
+    drone_id: String,
+    message: &MqttMessage,
+    live: &LiveFanout,
+) -> Result<(), HandlerError> {
     // 解析消息内容
-    match serde_json::from_slice::<Vec<[f64;2]>>(&payload) {
-        Ok(task) => {
-            info!("taskid: {} ,longitude: {},and latitude: {}", task_id, task[0][0], task[0][1]);
-            if let Err(e) = db_service.get(&task_id).await {
-                error!("获取位置任务失败: {:?}", e);
-                return;
-            }
-            match db_service.append_coordinates_and_update(&task_id, task.clone()).await {
-                Ok(_) => {
-                    info!("任务消息处理成功: {}", task_id);
-                    
-                    // 创建包含task_id和位置信息的完整消息结构
-                    let location_message = serde_json::json!({
-                        "longitude": task[0][0],
-                        "latitude": task[0][1],
-                    });
-                    
-                    // 将位置消息广播到所有SSE连接
-                    if let Ok(json_str) = serde_json::to_string(&location_message) {
-                        if let Err(e) = location_broadcaster.send(json_str) {
-                            warn!("广播位置消息失败: {}", e);
-                        } else {
-                            info!("已广播位置消息到SSE客户端");
-                        }
-                    }
-                }
-                Err(e) => warn!("任务消息处理失败: {}", e),
-            }
-        }
-        Err(e) => {
-            error!("解析任务消息失败: {}", e);
-        }
+    let mut task = decode_location_payload(&message.payload, message.param("suffix"), message.metadata.content_type.as_deref())
+        .map_err(|e| HandlerError::Rejected(e.to_string()))?;
+    // 航迹点按时间排序写入，没有采样时间的以收到时间为准
+    for point in &mut task {
+        point.timestamp.get_or_insert(message.received_at.into());
+    }
+    let Some(first) = task.first().copied() else {
+        return Err(HandlerError::Rejected("位置消息中没有坐标".to_string()));
+    };
+    info!("drone: {} ,longitude: {},and latitude: {}", drone_id, first.longitude, first.latitude);
+    let mut active = provision(provisioning, &drone_id, message.received_at).await?;
+    let rollover = db_service
+        .rollover_reason(&active.track_id, &task, message.received_at.into())
+        .await
+        .map_err(|e| HandlerError::Failed(format!("检查航迹分段失败: {}", e)))?;
+    if let Some(reason) = rollover {
+        active = provisioning
+            .roll_segment(&drone_id, active, message.received_at)
+            .await
+            .map_err(|e| HandlerError::Failed(format!("开启新航迹分段失败: {}", e)))?;
+        info!("无人机 {} {}，开启新航迹分段: {}", drone_id, reason, active.track_id);
+    }
+    db_service
+        .append_coordinates_and_update(&active.track_id, task.clone())
+        .await
+        .map_err(|e| HandlerError::Failed(format!("任务消息处理失败: {}", e)))?;
+    info!("任务消息处理成功: {} (航迹 {})", drone_id, active.track_id);
+
+    // 推送包含无人机、航迹和本条消息全部航迹点的位置事件
+    let location_event = LocationEventDto::new(Some(drone_id.clone()), active.track_id, message.received_at, task);
+
+    // 将位置消息广播到所有SSE连接
+    if let Ok(json_str) = serde_json::to_string(&location_event) {
+        live.publish(LiveKind::Location, &drone_id, json_str);
+    }
+    Ok(())
+}
+
+/// 获取无人机当前的航迹和flight，首次收到遥测时自动创建
+async fn provision(
+    provisioning: &ProvisioningService,
+    drone_id: &str,
+    received_at: DateTime<Utc>,
+) -> Result<ActiveDocuments, HandlerError> {
+    if drone_id.is_empty() {
+        return Err(HandlerError::Rejected("无人机ID为空".to_string()));
     }
+    provisioning
+        .ensure_provisioned(drone_id, received_at)
+        .await
+        .map_err(|e| HandlerError::Failed(format!("获取无人机当前航迹失败: {}", e)))
 }
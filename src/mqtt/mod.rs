@@ -1,5 +1,15 @@
+pub mod alerts;
 pub mod client;
+pub mod connection;
+pub mod dispatcher;
+pub mod fanout;
 pub mod handlers;
+pub mod router;
 
+pub use alerts::*;
 pub use client::*;
+pub use connection::*;
+pub use dispatcher::*;
+pub use fanout::*;
 pub use handlers::*;
+pub use router::*;
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use log::warn;
+use rumqttc::QoS;
+
+/// 路由到处理器的MQTT消息
+#[derive(Debug, Clone)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// 主题模式中命名捕获的取值，例如 `drone/{id}/location` 中的 `id`
+    pub params: HashMap<String, String>,
+    /// 服务端收到消息的时间
+    pub received_at: DateTime<Utc>,
+    /// MQTT 5 消息属性
+    pub metadata: MessageMetadata,
+}
+
+/// MQTT 5 PUBLISH报文携带的属性，3.1.1连接下均为空
+#[derive(Debug, Clone, Default)]
+pub struct MessageMetadata {
+    /// 消息编码，用于选择解码器
+    pub content_type: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+    /// 用户属性（如固件版本、序号），同名属性可以出现多次
+    pub user_properties: Vec<(String, String)>,
+    /// 服务端转发时剩余的消息有效期
+    pub message_expiry: Option<Duration>,
+}
+
+impl MqttMessage {
+    /// 获取命名捕获的值
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// 消息过期时间：收到时间加上剩余有效期
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let expiry = chrono::Duration::from_std(self.metadata.message_expiry?).ok()?;
+        self.received_at.checked_add_signed(expiry)
+    }
+
+    /// 消息是否已过期，过期的遥测不再写入
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at().is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// 保序键：本实例收到的同一无人机（`id` 捕获）的消息按到达顺序处理，没有 `id` 时按主题；
+    /// 共享订阅时其他实例收到的消息不在此列
+    pub fn ordering_key(&self) -> &str {
+        self.param("id").unwrap_or(&self.topic)
+    }
+}
+
+/// 已匹配到路由、等待处理的消息
+pub struct RoutedMessage {
+    pub message: MqttMessage,
+    pub handler: Arc<dyn MessageHandler>,
+}
+
+impl RoutedMessage {
+    pub async fn handle(&self) -> Result<(), HandlerError> {
+        self.handler.handle(&self.message).await
+    }
+}
+
+/// 消息处理失败的原因
+#[derive(Debug, Clone)]
+pub enum HandlerError {
+    /// 消息本身无法处理（格式错误、ID无效等），会被存入死信集合
+    Rejected(String),
+    /// 处理过程中出错（如数据库写入失败），消息本身没有问题
+    Failed(String),
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandlerError::Rejected(reason) => write!(f, "消息被拒绝: {}", reason),
+            HandlerError::Failed(reason) => write!(f, "消息处理失败: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for HandlerError {}
+
+/// MQTT消息处理器，按主题模式注册到 [`MessageRouter`]
+pub trait MessageHandler: Send + Sync {
+    fn handle<'a>(&'a self, message: &'a MqttMessage) -> BoxFuture<'a, Result<(), HandlerError>>;
+}
+
+/// 主题模式解析错误
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteError {
+    pub pattern: String,
+    pub message: String,
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "主题模式 \"{}\" 无效: {}", self.pattern, self.message)
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// 单层通配：`+` 或 `{name}`
+    Single(Option<String>),
+    /// 多层通配：`#` 或 `{*name}`，只能位于末尾
+    Multi(Option<String>),
+}
+
+/// 带命名捕获的主题模式
+///
+/// 支持标准MQTT通配符 `+`、`#`，以及命名捕获 `{name}`（单层）和 `{*name}`（多层，
+/// 只能位于末尾）。订阅时命名捕获会被替换为对应的通配符。
+#[derive(Debug, Clone)]
+pub struct TopicPattern {
+    raw: String,
+    segments: Vec<Segment>,
+}
+
+impl TopicPattern {
+    pub fn parse(pattern: &str) -> Result<Self, RouteError> {
+        let error = |message: String| RouteError {
+            pattern: pattern.to_string(),
+            message,
+        };
+        if pattern.is_empty() {
+            return Err(error("不能为空".to_string()));
+        }
+
+        let levels: Vec<&str> = pattern.split('/').collect();
+        let mut segments = Vec::with_capacity(levels.len());
+        let mut names: Vec<&str> = Vec::new();
+        for (i, level) in levels.iter().enumerate() {
+            let segment = match *level {
+                "+" => Segment::Single(None),
+                "#" => Segment::Multi(None),
+                _ if level.starts_with('{') && level.ends_with('}') => {
+                    let inner = &level[1..level.len() - 1];
+                    let (multi, name) = match inner.strip_prefix('*') {
+                        Some(name) => (true, name),
+                        None => (false, inner),
+                    };
+                    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                        return Err(error(format!("捕获名 \"{}\" 不合法", name)));
+                    }
+                    if names.contains(&name) {
+                        return Err(error(format!("捕获名 \"{}\" 重复", name)));
+                    }
+                    names.push(name);
+                    if multi {
+                        Segment::Multi(Some(name.to_string()))
+                    } else {
+                        Segment::Single(Some(name.to_string()))
+                    }
+                }
+                _ if level.contains(['+', '#', '{', '}']) => {
+                    return Err(error(format!("第{}层 \"{}\" 中的通配符位置不合法", i + 1, level)));
+                }
+                _ => Segment::Literal(level.to_string()),
+            };
+            if matches!(segment, Segment::Multi(_)) && i != levels.len() - 1 {
+                return Err(error("多层通配符只能位于末尾".to_string()));
+            }
+            segments.push(segment);
+        }
+
+        Ok(Self {
+            raw: pattern.to_string(),
+            segments,
+        })
+    }
+
+    /// 原始模式字符串
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// 是否包含指定名称的捕获
+    pub fn has_capture(&self, name: &str) -> bool {
+        self.segments.iter().any(|segment| match segment {
+            Segment::Single(Some(n)) | Segment::Multi(Some(n)) => n == name,
+            _ => false,
+        })
+    }
+
+    /// 用于订阅的MQTT主题过滤器
+    pub fn filter(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(s) => s.as_str(),
+                Segment::Single(_) => "+",
+                Segment::Multi(_) => "#",
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// 匹配主题，成功时返回命名捕获
+    pub fn matches(&self, topic: &str) -> Option<HashMap<String, String>> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut params = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(s) => {
+                    if levels.get(i) != Some(&s.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Single(name) => {
+                    let level = levels.get(i)?;
+                    if let Some(name) = name {
+                        params.insert(name.clone(), level.to_string());
+                    }
+                }
+                Segment::Multi(name) => {
+                    // 与MQTT语义一致：`a/#` 同时匹配 `a`
+                    if let Some(name) = name {
+                        let rest = levels.get(i..).map(|rest| rest.join("/")).unwrap_or_default();
+                        params.insert(name.clone(), rest);
+                    }
+                    return Some(params);
+                }
+            }
+        }
+        if levels.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
+
+struct Route {
+    pattern: TopicPattern,
+    qos: QoS,
+    /// 多实例部署时是否使用共享订阅
+    shared: bool,
+    handler: Arc<dyn MessageHandler>,
+}
+
+/// 由路由生成的订阅
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub filter: String,
+    pub qos: QoS,
+    pub shared: bool,
+}
+
+impl Subscription {
+    /// 实际订阅的主题，配置了共享订阅组时加上 `$share/<group>/` 前缀
+    pub fn topic(&self, share_group: Option<&str>) -> String {
+        match share_group {
+            Some(group) if self.shared => format!("$share/{}/{}", group, self.filter),
+            _ => self.filter.clone(),
+        }
+    }
+}
+
+/// 主题到处理器的路由表
+///
+/// 按注册顺序匹配，第一条匹配的路由处理消息。订阅列表由已注册的路由生成。
+#[derive(Default)]
+pub struct MessageRouter {
+    routes: Vec<Route>,
+}
+
+impl MessageRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册路由，多实例部署时以共享订阅方式订阅，每条消息只由一个实例处理
+    pub fn route(self, pattern: &str, qos: QoS, handler: Arc<dyn MessageHandler>) -> Result<Self, RouteError> {
+        self.push(pattern, qos, true, handler)
+    }
+
+    /// 注册每个实例都需要收到的路由，始终使用普通订阅
+    pub fn route_per_instance(
+        self,
+        pattern: &str,
+        qos: QoS,
+        handler: Arc<dyn MessageHandler>,
+    ) -> Result<Self, RouteError> {
+        self.push(pattern, qos, false, handler)
+    }
+
+    fn push(
+        mut self,
+        pattern: &str,
+        qos: QoS,
+        shared: bool,
+        handler: Arc<dyn MessageHandler>,
+    ) -> Result<Self, RouteError> {
+        let pattern = TopicPattern::parse(pattern)?;
+        self.routes.push(Route { pattern, qos, shared, handler });
+        Ok(self)
+    }
+
+    /// 需要订阅的主题过滤器（已去重）
+    pub fn subscriptions(&self) -> Vec<Subscription> {
+        let mut subscriptions: Vec<Subscription> = Vec::new();
+        for route in &self.routes {
+            let filter = route.pattern.filter();
+            if !subscriptions.iter().any(|s| s.filter == filter) {
+                subscriptions.push(Subscription {
+                    filter,
+                    qos: route.qos,
+                    shared: route.shared,
+                });
+            }
+        }
+        subscriptions
+    }
+
+    /// 查找第一条匹配的路由
+    pub fn resolve(&self, topic: &str, payload: Vec<u8>, metadata: MessageMetadata) -> Option<RoutedMessage> {
+        for route in &self.routes {
+            if let Some(params) = route.pattern.matches(topic) {
+                let message = MqttMessage {
+                    topic: topic.to_string(),
+                    payload,
+                    params,
+                    received_at: Utc::now(),
+                    metadata,
+                };
+                return Some(RoutedMessage {
+                    message,
+                    handler: route.handler.clone(),
+                });
+            }
+        }
+        warn!("没有匹配的路由: {} (已注册: {:?})", topic, self.patterns());
+        None
+    }
+
+    fn patterns(&self) -> Vec<&str> {
+        self.routes.iter().map(|route| route.pattern.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(raw: &str) -> TopicPattern {
+        TopicPattern::parse(raw).unwrap()
+    }
+
+    struct Noop;
+
+    impl MessageHandler for Noop {
+        fn handle<'a>(&'a self, _message: &'a MqttMessage) -> BoxFuture<'a, Result<(), HandlerError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn filter_replaces_captures_with_wildcards() {
+        assert_eq!(pattern("drone/{id}/location/{*suffix}").filter(), "drone/+/location/#");
+        assert_eq!(pattern("drone/+/state").filter(), "drone/+/state");
+        assert_eq!(pattern("status/#").filter(), "status/#");
+    }
+
+    #[test]
+    fn single_level_capture() {
+        let params = pattern("drone/{id}/state").matches("drone/A1/state").unwrap();
+        assert_eq!(params.get("id").map(String::as_str), Some("A1"));
+        assert!(pattern("drone/{id}/state").matches("drone/A1/location").is_none());
+        assert!(pattern("drone/{id}/state").matches("drone/A1/state/v2").is_none());
+        assert!(pattern("drone/{id}/state").matches("drone/A1").is_none());
+        assert!(pattern("drone/+/state").matches("drone/A1/state").unwrap().is_empty());
+    }
+
+    #[test]
+    fn multi_level_capture() {
+        let location = pattern("drone/{id}/location/{*suffix}");
+        let params = location.matches("drone/A1/location/v2/cbor").unwrap();
+        assert_eq!(params.get("id").map(String::as_str), Some("A1"));
+        assert_eq!(params.get("suffix").map(String::as_str), Some("v2/cbor"));
+        // 与MQTT语义一致，`#` 也匹配父级主题
+        let params = location.matches("drone/A1/location").unwrap();
+        assert_eq!(params.get("suffix").map(String::as_str), Some(""));
+        assert!(pattern("status/#").matches("status/a/b/c").is_some());
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        for raw in ["", "a/#/b", "a/{*rest}/b", "a/{}/b", "a/{id}/{id}", "a/b+/c", "a/{bad-name}"] {
+            assert!(TopicPattern::parse(raw).is_err(), "{}", raw);
+        }
+        assert!(pattern("drone/{id}/state").has_capture("id"));
+        assert!(!pattern("drone/+/state").has_capture("id"));
+    }
+
+    #[test]
+    fn first_matching_route_wins_and_subscriptions_are_deduplicated() {
+        let router = MessageRouter::new()
+            .route("drone/{id}/state", QoS::AtLeastOnce, Arc::new(Noop))
+            .unwrap()
+            .route("drone/+/state", QoS::AtMostOnce, Arc::new(Noop))
+            .unwrap()
+            .route_per_instance("live/{*rest}", QoS::AtMostOnce, Arc::new(Noop))
+            .unwrap();
+        let subscriptions = router.subscriptions();
+        assert_eq!(subscriptions.len(), 2);
+        assert_eq!(subscriptions[0].qos, QoS::AtLeastOnce);
+        assert_eq!(subscriptions[0].topic(Some("workers")), "$share/workers/drone/+/state");
+        assert_eq!(subscriptions[1].topic(Some("workers")), "live/#");
+
+        let routed = router.resolve("drone/A1/state", Vec::new(), MessageMetadata::default()).unwrap();
+        assert_eq!(routed.message.ordering_key(), "A1");
+        assert!(router.resolve("other/topic", Vec::new(), MessageMetadata::default()).is_none());
+    }
+}
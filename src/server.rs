@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use axum::extract::FromRef;
+use axum::Router;
+use log::info;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::CorsLayer;
+
+use crate::api::{self, ApiState};
+use crate::sse::{self, SseState};
+use crate::websocket::{self, WsState};
+
+/// HTTP服务共享状态，各模块的处理器通过 [`FromRef`] 取出自己需要的部分
+#[derive(Clone)]
+pub struct AppState {
+    pub api: Arc<ApiState>,
+    pub sse: Arc<SseState>,
+    pub ws: Arc<WsState>,
+}
+
+impl FromRef<AppState> for Arc<ApiState> {
+    fn from_ref(state: &AppState) -> Self {
+        state.api.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<SseState> {
+    fn from_ref(state: &AppState) -> Self {
+        state.sse.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<WsState> {
+    fn from_ref(state: &AppState) -> Self {
+        state.ws.clone()
+    }
+}
+
+/// SSE、WebSocket和REST接口合并后的路由
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .merge(api::routes())
+        .merge(sse::routes())
+        .merge(websocket::routes())
+        .layer(CorsLayer::permissive())
+        .with_state(state)
+}
+
+/// 在 `listener` 上运行HTTP服务，直到 `shutdown` 取消
+///
+/// 取消后停止接受新连接，等待进行中的请求完成（SSE事件流随之结束），
+/// 再等待WebSocket连接发送关闭帧退出。
+pub async fn serve(listener: TcpListener, state: AppState, shutdown: CancellationToken) -> std::io::Result<()> {
+    let addr = listener.local_addr()?;
+    info!("HTTP服务器启动在 {}", addr);
+    info!("WebSocket端点: ws://{}/flight_ws", addr);
+    info!("位置更新SSE端点: http://{}/sse/location", addr);
+    info!("航迹回放SSE端点: http://{}/sse/replay/{{track_id}}", addr);
+    info!("REST接口: http://{}/api", addr);
+
+    let connections = state.ws.connections.clone();
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+
+    connections.close();
+    if !connections.is_empty() {
+        info!("等待{}个WebSocket连接关闭", connections.len());
+    }
+    connections.wait().await;
+    info!("HTTP服务器已停止");
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use bson::doc;
+use chrono::{DateTime as ChronoDateTime, Utc};
+use futures::TryStreamExt;
+use log::error;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{DateTime, Document};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+use crate::model::dead_letter::{binary, DeadLetter, DeadLetterStatus};
+use crate::mqtt::MessageMetadata;
+
+/// 重放标记的有效期，超过后视为上次重放已中断
+pub const REPLAY_CLAIM_TIMEOUT: chrono::Duration = chrono::Duration::minutes(5);
+
+pub struct DeadLetterService {
+    pub collection: Collection<DeadLetter>,
+}
+
+impl DeadLetterService {
+    pub fn new(collection: Collection<DeadLetter>) -> Self {
+        Self { collection }
+    }
+
+    /// 保存被拒绝的消息
+    pub async fn record(
+        &self,
+        topic: &str,
+        payload: Vec<u8>,
+        metadata: MessageMetadata,
+        received_at: ChronoDateTime<Utc>,
+        reason: &str,
+    ) -> mongodb::error::Result<ObjectId> {
+        let dead_letter = DeadLetter::new(
+            topic.to_string(),
+            payload,
+            metadata,
+            received_at.into(),
+            reason.to_string(),
+        );
+        let id = dead_letter.id;
+        self.collection.insert_one(dead_letter).await?;
+        Ok(id)
+    }
+
+    /// 按接收时间倒序分页查询
+    pub async fn list(
+        &self,
+        status: Option<DeadLetterStatus>,
+        topic: Option<&str>,
+        skip: u64,
+        limit: i64,
+    ) -> mongodb::error::Result<Vec<DeadLetter>> {
+        let mut filter = Document::new();
+        if let Some(status) = status {
+            filter.insert("status", bson::to_bson(&status)?);
+        }
+        if let Some(topic) = topic {
+            filter.insert("topic", topic);
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"receivedAt": -1})
+            .skip(skip)
+            .limit(limit)
+            .build();
+        self.collection.find(filter).with_options(options).await?.try_collect().await
+    }
+
+    pub async fn get(&self, id: &str) -> mongodb::error::Result<Option<DeadLetter>> {
+        let obj_id = parse_id(id)?;
+        self.collection.find_one(doc! {"_id": obj_id}).await
+    }
+
+    /// 修正死信的主题或消息内容，返回是否找到该死信
+    pub async fn update(
+        &self,
+        id: &str,
+        topic: Option<String>,
+        payload: Option<Vec<u8>>,
+    ) -> mongodb::error::Result<bool> {
+        let obj_id = parse_id(id)?;
+        let mut set = Document::new();
+        if let Some(topic) = topic {
+            set.insert("topic", topic);
+        }
+        if let Some(payload) = payload {
+            set.insert("payload", binary(payload));
+        }
+        if set.is_empty() {
+            return Ok(self.collection.count_documents(doc! {"_id": obj_id}).await? > 0);
+        }
+        let result = self.collection.update_one(doc! {"_id": obj_id}, doc! {"$set": set}).await?;
+        Ok(result.matched_count > 0)
+    }
+
+    /// 标记死信正在重放，返回标记前的死信
+    ///
+    /// 已重放或正在重放的死信不会被再次标记，并发的重放请求只有一个能继续。
+    /// 重放中途进程退出时标记不会被清除，超过 [`REPLAY_CLAIM_TIMEOUT`] 后可以重新重放。
+    pub async fn claim_replay(&self, id: &ObjectId) -> mongodb::error::Result<Option<DeadLetter>> {
+        let now = Utc::now();
+        let stale: DateTime = (now - REPLAY_CLAIM_TIMEOUT).into();
+        let filter = doc! {
+            "_id": id,
+            "status": {"$ne": bson::to_bson(&DeadLetterStatus::Replayed)?},
+            "$or": [
+                {"replayStartedAt": {"$exists": false}},
+                {"replayStartedAt": {"$lt": stale}},
+            ],
+        };
+        let now: DateTime = now.into();
+        self.collection
+            .find_one_and_update(filter, doc! {"$set": {"replayStartedAt": now}})
+            .await
+    }
+
+    /// 记录一次重放结果并清除重放标记，失败时更新拒绝原因
+    pub async fn record_replay(&self, id: &ObjectId, error: Option<&str>) -> mongodb::error::Result<()> {
+        let now: DateTime = Utc::now().into();
+        let mut set = doc! {"lastReplayAt": now};
+        match error {
+            Some(reason) => {
+                set.insert("error", reason);
+            }
+            None => {
+                set.insert("status", bson::to_bson(&DeadLetterStatus::Replayed)?);
+            }
+        }
+        self.collection
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$set": set, "$unset": {"replayStartedAt": ""}, "$inc": {"replayCount": 1i32}},
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// 删除死信，返回是否找到该死信
+    pub async fn delete(&self, id: &str) -> mongodb::error::Result<bool> {
+        let obj_id = parse_id(id)?;
+        let result = self.collection.delete_one(doc! {"_id": obj_id}).await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+fn parse_id(id: &str) -> mongodb::error::Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|e| {
+        error!("{:?}", e);
+        mongodb::error::Error::custom(e)
+    })
+}
@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+use serde::{Deserialize, Serialize};
+
+/// 时间序列降采样方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownsampleMethod {
+    /// Largest-Triangle-Three-Buckets，保留曲线的视觉形状
+    #[default]
+    Lttb,
+    /// 每个桶保留最小值和最大值，不会漏掉尖峰
+    MinMax,
+}
+
+/// 把 `(x, y)` 序列降采样到最多 `threshold` 个点，返回按 `x` 顺序选中的下标
+///
+/// 点数不超过 `threshold` 时全部保留。`points` 需按 `x` 升序排列。
+pub fn downsample(points: &[(f64, f64)], threshold: usize, method: DownsampleMethod) -> Vec<usize> {
+    match method {
+        DownsampleMethod::Lttb => lttb(points, threshold),
+        DownsampleMethod::MinMax => min_max(points, threshold),
+    }
+}
+
+fn lttb(points: &[(f64, f64)], threshold: usize) -> Vec<usize> {
+    let n = points.len();
+    if threshold >= n || threshold < 3 {
+        return (0..n).collect();
+    }
+    // 首尾两点固定保留，其余点平均分到 threshold - 2 个桶中，第 k 个桶为 [bound(k), bound(k + 1))
+    let buckets = threshold - 2;
+    let bucket_size = (n - 2) as f64 / buckets as f64;
+    let bound = |k: usize| if k >= buckets { n - 1 } else { (k as f64 * bucket_size) as usize + 1 };
+
+    let mut selected = Vec::with_capacity(threshold);
+    selected.push(0);
+    let mut previous = 0;
+    for bucket in 0..buckets {
+        // 下一个桶的平均点作为三角形的第三个顶点，最后一个桶使用末尾点
+        let next_end = if bucket + 1 >= buckets { n } else { bound(bucket + 2) };
+        let next = &points[bound(bucket + 1)..next_end];
+        let count = next.len() as f64;
+        let (avg_x, avg_y) = next.iter().fold((0.0, 0.0), |(x, y), p| (x + p.0 / count, y + p.1 / count));
+
+        let (ax, ay) = points[previous];
+        let mut chosen = bound(bucket);
+        let mut max_area = -1.0;
+        for (i, &(x, y)) in points.iter().enumerate().take(bound(bucket + 1)).skip(bound(bucket)) {
+            let area = ((ax - avg_x) * (y - ay) - (ax - x) * (avg_y - ay)).abs();
+            if area > max_area {
+                max_area = area;
+                chosen = i;
+            }
+        }
+        selected.push(chosen);
+        previous = chosen;
+    }
+    selected.push(n - 1);
+    selected
+}
+
+fn min_max(points: &[(f64, f64)], threshold: usize) -> Vec<usize> {
+    let n = points.len();
+    if threshold >= n || threshold < 2 {
+        return (0..n).collect();
+    }
+    let buckets = threshold / 2;
+    let mut selected = Vec::with_capacity(buckets * 2);
+    for bucket in 0..buckets {
+        let (start, end) = (bucket * n / buckets, (bucket + 1) * n / buckets);
+        if start == end {
+            continue;
+        }
+        let compare = |a: &usize, b: &usize| points[*a].1.total_cmp(&points[*b].1);
+        let min = (start..end).min_by(compare).unwrap_or(start);
+        let max = (start..end).max_by(compare).unwrap_or(start);
+        // 按时间顺序输出
+        match min.cmp(&max) {
+            Ordering::Less => selected.extend([min, max]),
+            Ordering::Greater => selected.extend([max, min]),
+            Ordering::Equal => selected.push(min),
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(n: usize) -> Vec<(f64, f64)> {
+        (0..n).map(|i| (i as f64, (i as f64 / 7.0).sin())).collect()
+    }
+
+    #[test]
+    fn keeps_everything_when_threshold_covers_input() {
+        let points = series(10);
+        for method in [DownsampleMethod::Lttb, DownsampleMethod::MinMax] {
+            assert_eq!(downsample(&points, 10, method), (0..10).collect::<Vec<_>>());
+            assert_eq!(downsample(&points, 50, method), (0..10).collect::<Vec<_>>());
+            assert!(downsample(&[], 5, method).is_empty());
+        }
+    }
+
+    #[test]
+    fn lttb_keeps_endpoints_and_threshold() {
+        let points = series(1000);
+        for threshold in [3, 4, 100, 999] {
+            let selected = downsample(&points, threshold, DownsampleMethod::Lttb);
+            assert_eq!(selected.len(), threshold);
+            assert_eq!(selected.first(), Some(&0));
+            assert_eq!(selected.last(), Some(&999));
+            assert!(selected.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[test]
+    fn lttb_keeps_a_spike() {
+        let mut points: Vec<(f64, f64)> = (0..500).map(|i| (i as f64, 0.0)).collect();
+        points[250].1 = 100.0;
+        assert!(downsample(&points, 20, DownsampleMethod::Lttb).contains(&250));
+    }
+
+    #[test]
+    fn min_max_keeps_extremes_in_order() {
+        let mut points = series(1000);
+        points[123].1 = -50.0;
+        points[877].1 = 50.0;
+        let selected = downsample(&points, 100, DownsampleMethod::MinMax);
+        assert!(selected.len() <= 100);
+        assert!(selected.contains(&123) && selected.contains(&877));
+        assert!(selected.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn min_max_flat_series_spans_each_bucket() {
+        // 值相同时最小值取桶内第一个点、最大值取最后一个点
+        let points: Vec<(f64, f64)> = (0..100).map(|i| (i as f64, 1.0)).collect();
+        let selected = downsample(&points, 10, DownsampleMethod::MinMax);
+        assert_eq!(selected, vec![0, 19, 20, 39, 40, 59, 60, 79, 80, 99]);
+    }
+}
@@ -0,0 +1,198 @@
+use bson::{doc, Document};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::DateTime;
+use mongodb::options::{FindOptions, IndexOptions, ReturnDocument};
+use mongodb::{Collection, IndexModel};
+use crate::model::drone::{ActiveDocuments, CreateDroneRequestDto, Drone, UpdateDroneRequestDto};
+use crate::service::is_duplicate_key_error;
+
+pub struct DroneService {
+    pub collection: Collection<Drone>,
+}
+
+impl DroneService {
+    pub fn new(collection: Collection<Drone>) -> Self {
+        Self { collection }
+    }
+
+    /// 创建序列号唯一索引
+    pub async fn ensure_indexes(&self) -> mongodb::error::Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! {"serial": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.collection.create_index(index).await?;
+        Ok(())
+    }
+
+    /// 登记无人机，序列号已存在时返回 `None`
+    pub async fn create(&self, dto: CreateDroneRequestDto) -> mongodb::error::Result<Option<Drone>> {
+        let drone = Drone {
+            id: ObjectId::new(),
+            serial: dto.serial,
+            model: dto.model,
+            owner: dto.owner,
+            home_point: dto.home_point,
+            active_track_id: None,
+            active_flight_id: None,
+            created_at: Utc::now().into(),
+            low_battery: false,
+        };
+        match self.collection.insert_one(&drone).await {
+            Ok(_) => Ok(Some(drone)),
+            Err(e) if is_duplicate_key_error(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_by_serial(&self, serial: &str) -> mongodb::error::Result<Option<Drone>> {
+        self.collection.find_one(doc! {"serial": serial}).await
+    }
+
+    pub async fn list(&self, skip: u64, limit: i64) -> mongodb::error::Result<Vec<Drone>> {
+        let options = FindOptions::builder()
+            .sort(doc! {"serial": 1})
+            .skip(skip)
+            .limit(limit)
+            .build();
+        self.collection.find(doc! {}).with_options(options).await?.try_collect().await
+    }
+
+    /// 更新无人机信息，返回更新后的文档
+    pub async fn update(&self, serial: &str, dto: UpdateDroneRequestDto) -> mongodb::error::Result<Option<Drone>> {
+        let mut set = Document::new();
+        if let Some(model) = dto.model {
+            set.insert("model", model);
+        }
+        if let Some(owner) = dto.owner {
+            set.insert("owner", owner);
+        }
+        if let Some(home_point) = dto.home_point {
+            set.insert("homePoint", bson::to_bson(&home_point)?);
+        }
+        if set.is_empty() {
+            return self.get_by_serial(serial).await;
+        }
+        self.collection
+            .find_one_and_update(doc! {"serial": serial}, doc! {"$set": set})
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /// 删除登记信息，已有的航迹和flight文档保留
+    pub async fn delete(&self, serial: &str) -> mongodb::error::Result<bool> {
+        let result = self.collection.delete_one(doc! {"serial": serial}).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    /// 结束当前飞行，下一条遥测会开启新的航迹和flight
+    pub async fn end_active_flight(&self, serial: &str) -> mongodb::error::Result<Option<Drone>> {
+        self.collection
+            .find_one_and_update(
+                doc! {"serial": serial},
+                doc! {"$unset": {"activeTrackId": "", "activeFlightId": ""}},
+            )
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /// 无人机当前的航迹和flight是否仍为 `active`
+    pub async fn is_active(&self, serial: &str, active: &ActiveDocuments) -> mongodb::error::Result<bool> {
+        let drone = self
+            .collection
+            .clone_with_type::<Document>()
+            .find_one(doc! {
+                "serial": serial,
+                "activeTrackId": active.track_id,
+                "activeFlightId": active.flight_id,
+            })
+            .projection(doc! {"_id": 1})
+            .await?;
+        Ok(drone.is_some())
+    }
+
+    /// 记录电量是否低于告警阈值，返回状态是否发生变化
+    ///
+    /// 条件更新是单文档原子操作，多个实例处理同一无人机的状态消息时只有一方会看到变化。
+    pub async fn set_low_battery(&self, serial: &str, low: bool) -> mongodb::error::Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! {"serial": serial, "lowBattery": {"$ne": low}},
+                doc! {"$set": {"lowBattery": low}},
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// 当前航迹仍为 `current` 时切换到下一个分段，返回是否切换成功
+    pub async fn advance_track(
+        &self,
+        serial: &str,
+        current: &ObjectId,
+        next: &ObjectId,
+    ) -> mongodb::error::Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! {"serial": serial, "activeTrackId": current},
+                doc! {"$set": {"activeTrackId": next}},
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// 获取无人机当前的航迹和flight ID，未登记的无人机自动登记，没有进行中的飞行时分配新ID
+    ///
+    /// 两步都是单文档原子操作：并发调用时只有一方的ID会被写入，其余调用读取到同一组ID。
+    pub async fn resolve_active(&self, serial: &str) -> mongodb::error::Result<ActiveDocuments> {
+        let now: DateTime = Utc::now().into();
+        let drone = self.upsert_by_serial(serial, now).await?;
+        if let (Some(track_id), Some(flight_id)) = (drone.active_track_id, drone.active_flight_id) {
+            return Ok(ActiveDocuments { track_id, flight_id });
+        }
+
+        let candidate = ActiveDocuments {
+            track_id: ObjectId::new(),
+            flight_id: ObjectId::new(),
+        };
+        let assigned = self
+            .collection
+            .find_one_and_update(
+                doc! {"serial": serial, "activeTrackId": null},
+                doc! {"$set": {"activeTrackId": candidate.track_id, "activeFlightId": candidate.flight_id}},
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        if assigned.is_some() {
+            return Ok(candidate);
+        }
+
+        // 其他调用已分配，读取其结果
+        let drone = self.get_by_serial(serial).await?;
+        match drone.and_then(|d| Some(ActiveDocuments { track_id: d.active_track_id?, flight_id: d.active_flight_id? })) {
+            Some(active) => Ok(active),
+            None => Err(mongodb::error::Error::custom(format!("无法为无人机 {} 分配航迹", serial))),
+        }
+    }
+
+    async fn upsert_by_serial(&self, serial: &str, now: DateTime) -> mongodb::error::Result<Drone> {
+        let upsert = || {
+            self.collection
+                .find_one_and_update(
+                    doc! {"serial": serial},
+                    doc! {"$setOnInsert": {"createdAt": now}},
+                )
+                .upsert(true)
+                .return_document(ReturnDocument::After)
+        };
+        let drone = match upsert().await {
+            // 并发upsert时另一方已插入，重试一次即可读到
+            Err(e) if is_duplicate_key_error(&e) => upsert().await?,
+            result => result?,
+        };
+        drone.ok_or_else(|| mongodb::error::Error::custom(format!("无法登记无人机 {}", serial)))
+    }
+}
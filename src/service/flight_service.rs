@@ -1,18 +1,52 @@
-use bson::doc;
-use log::error;
+use std::cmp::Ordering;
+use bson::{doc, Bson, DateTime, Document};
+use futures::TryStreamExt;
+use log::{error, info};
 use mongodb::bson::oid::ObjectId;
-use mongodb::Collection;
-use mongodb::results::UpdateResult;
-use crate::model::flight::{Flight, FlightDto};
+use mongodb::options::{TimeseriesGranularity, TimeseriesOptions};
+use mongodb::{Collection, Database, IndexModel};
+use crate::model::flight::{
+    Flight, FlightDto, FlightMetric, FlightMetricsResponseDto, FlightSample, FlightSampleMeta, MetricPointDto,
+    MetricSeriesDto,
+};
+use crate::service::downsample::{downsample, DownsampleMethod};
+use crate::service::{is_duplicate_key_error, is_namespace_exists_error};
 
 pub struct FlightService{
     pub collection: Collection<Flight>,
+    /// 状态采样的时间序列集合
+    pub samples: Collection<FlightSample>,
 }
 impl FlightService {
-    pub fn new(collection: Collection<Flight>) -> Self {
-        Self { collection }
+    pub fn new(collection: Collection<Flight>, samples: Collection<FlightSample>) -> Self {
+        Self { collection, samples }
     }
 
+    /// 创建状态采样的时间序列集合及按flight和按无人机最新采样查询的索引，已存在时跳过
+    pub async fn ensure_sample_collection(&self, db: &Database) -> mongodb::error::Result<()> {
+        let name = self.samples.name();
+        let existing = db.list_collection_names().filter(doc! {"name": name}).await?;
+        if existing.is_empty() {
+            let options = TimeseriesOptions::builder()
+                .time_field("timestamp".to_string())
+                .meta_field(Some("meta".to_string()))
+                .granularity(Some(TimeseriesGranularity::Seconds))
+                .build();
+            match db.create_collection(name).timeseries(options).await {
+                Ok(()) => info!("已创建时间序列集合: {}", name),
+                Err(e) if is_namespace_exists_error(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let indexes = vec![
+            IndexModel::builder().keys(doc! {"meta.flightId": 1, "timestamp": 1}).build(),
+            IndexModel::builder().keys(doc! {"meta.droneId": 1, "timestamp": -1}).build(),
+        ];
+        self.samples.create_indexes(indexes).await?;
+        Ok(())
+    }
+
+    #[allow(dead_code)]
     pub async fn create(&self, flight: Flight) -> mongodb::error::Result<()> {
         self.collection.insert_one(flight).await?;
         Ok(())
@@ -26,6 +60,12 @@ impl FlightService {
         self.collection.find_one(doc! {"_id": obj_id}).await
     }
 
+    /// 查找关联到航迹的flight
+    pub async fn find_by_track(&self, track_id: &ObjectId) -> mongodb::error::Result<Option<Flight>> {
+        self.collection.find_one(doc! {"trackId": track_id}).await
+    }
+
+    #[allow(dead_code)]
     pub async fn update(&self, id: &str, flight: Flight) -> mongodb::error::Result<()> {
         let obj_id = ObjectId::parse_str(id).map_err(|e| {
             error!("{:?}", e);
@@ -34,20 +74,232 @@ impl FlightService {
         self.collection.replace_one(doc! {"_id": obj_id}, flight).await?;
         Ok(())
     }
-    pub async fn append_data_and_update(&self, id: &str, payload: FlightDto) -> mongodb::error::Result<UpdateResult> {
-        let obj_id = ObjectId::parse_str(id).map_err(|e| {
-            error!("{:?}",e);
-            mongodb::error::Error::custom(e)})?;
+    /// flight不存在时创建并关联到航迹和无人机，返回是否新建
+    pub async fn ensure_exists(
+        &self,
+        id: &ObjectId,
+        track_id: &ObjectId,
+        drone_id: &str,
+    ) -> mongodb::error::Result<bool> {
         let update = doc! {
-            "$push": {
-                "batteryCapacity": payload.battery_capacity,
-                "estimatedRemainingUsageTime": payload.estimated_remaining_usage_time,
-                "cabinTemperature": payload.cabin_temperature,
-                "aircraftAltitude": payload.aircraft_altitude,
-                "airPressure": payload.air_pressure,
-                "distanceToFan": payload.distance_to_fan,
+            "$setOnInsert": {
+                "trackId": track_id,
+                "droneId": drone_id,
             }
         };
-        self.collection.update_one(doc! {"_id": obj_id}, update).await
+        match self.collection.update_one(doc! {"_id": id}, update).upsert(true).await {
+            Ok(result) => Ok(result.upserted_id.is_some()),
+            // 并发upsert时另一方已插入
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 写入一条状态采样
+    pub async fn record_sample(
+        &self,
+        flight_id: &ObjectId,
+        drone_id: &str,
+        timestamp: DateTime,
+        state: FlightDto,
+    ) -> mongodb::error::Result<()> {
+        let meta = FlightSampleMeta {
+            flight_id: *flight_id,
+            drone_id: Some(drone_id.to_string()),
+            migrated: false,
+        };
+        self.samples.insert_one(FlightSample::new(meta, timestamp, state)).await?;
+        Ok(())
+    }
+
+    /// 按时间顺序查询flight在时间范围内的状态采样，边界为闭区间
+    pub async fn list_samples(
+        &self,
+        flight_id: &ObjectId,
+        from: Option<DateTime>,
+        to: Option<DateTime>,
+    ) -> mongodb::error::Result<Vec<FlightSample>> {
+        self.samples
+            .find(samples_filter(flight_id, from, to))
+            .sort(doc! {"timestamp": 1})
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /// 无人机最新的一条状态采样
+    pub async fn latest_sample(&self, drone_id: &str) -> mongodb::error::Result<Option<FlightSample>> {
+        self.samples
+            .find_one(doc! {"meta.droneId": drone_id})
+            .sort(doc! {"timestamp": -1})
+            .await
+    }
+
+    /// 查询flight在时间范围内的指标历史，每个指标分别降采样到最多 `max_points` 个点
+    ///
+    /// 采样数超过 `MAX_RAW_SAMPLES` 时不再读取全部采样，而是先在数据库中按时间分桶，
+    /// 每个桶只返回各指标的最小值和最大值，再对这些点降采样，尖峰不会丢失。
+    pub async fn metrics(
+        &self,
+        flight_id: &ObjectId,
+        from: Option<DateTime>,
+        to: Option<DateTime>,
+        metrics: &[FlightMetric],
+        max_points: usize,
+        method: DownsampleMethod,
+    ) -> mongodb::error::Result<FlightMetricsResponseDto> {
+        let filter = samples_filter(flight_id, from, to);
+        let source_points = self.samples.count_documents(filter.clone()).await? as usize;
+        let values: Vec<Vec<(DateTime, f64)>> = if source_points <= MAX_RAW_SAMPLES {
+            let samples = self.list_samples(flight_id, from, to).await?;
+            metrics
+                .iter()
+                .map(|&metric| samples.iter().map(|sample| (sample.timestamp, metric.value(sample))).collect())
+                .collect()
+        } else {
+            let buckets = self.bucket_extremes(filter, metrics).await?;
+            metrics.iter().map(|&metric| bucket_values(&buckets, metric)).collect()
+        };
+        let series = metrics
+            .iter()
+            .zip(values)
+            .map(|(&metric, values)| {
+                let xy: Vec<(f64, f64)> = values
+                    .iter()
+                    .map(|(timestamp, value)| (timestamp.timestamp_millis() as f64, *value))
+                    .collect();
+                let points = downsample(&xy, max_points, method)
+                    .into_iter()
+                    .map(|i| MetricPointDto { timestamp: values[i].0, value: values[i].1 })
+                    .collect();
+                MetricSeriesDto { metric: metric.as_str(), points }
+            })
+            .collect();
+        Ok(FlightMetricsResponseDto {
+            flight_id: *flight_id,
+            method,
+            source_points,
+            series,
+        })
+    }
+
+    /// 把时间范围等分为 `PRE_BUCKETS` 个桶，返回每个桶中各指标的最小值和最大值及其时间，按时间排序
+    async fn bucket_extremes(&self, filter: Document, metrics: &[FlightMetric]) -> mongodb::error::Result<Vec<Document>> {
+        let first = self.samples.find_one(filter.clone()).sort(doc! {"timestamp": 1}).await?;
+        let last = self.samples.find_one(filter.clone()).sort(doc! {"timestamp": -1}).await?;
+        let (Some(first), Some(last)) = (first, last) else {
+            return Ok(Vec::new());
+        };
+        let span = last.timestamp.timestamp_millis() - first.timestamp.timestamp_millis();
+        let width = span / PRE_BUCKETS as i64 + 1;
+
+        let mut group = doc! {
+            "_id": {"$floor": {"$divide": [{"$subtract": ["$timestamp", first.timestamp]}, width]}},
+        };
+        for metric in metrics {
+            // 文档按字段顺序比较，`v` 相同时取时间较早的采样
+            let extreme = doc! {"v": format!("${}", metric.as_str()), "t": "$timestamp"};
+            group.insert(format!("{}_min", metric.as_str()), doc! {"$min": extreme.clone()});
+            group.insert(format!("{}_max", metric.as_str()), doc! {"$max": extreme});
+        }
+        let pipeline = vec![doc! {"$match": filter}, doc! {"$group": group}, doc! {"$sort": {"_id": 1}}];
+        self.samples
+            .aggregate(pipeline)
+            .allow_disk_use(true)
+            .await?
+            .try_collect()
+            .await
+    }
+}
+
+/// 直接读取全部采样降采样的最大采样数
+const MAX_RAW_SAMPLES: usize = 20_000;
+
+/// 采样过多时在数据库中预先分桶的桶数，每个桶每个指标最多产生两个点
+const PRE_BUCKETS: usize = MAX_RAW_SAMPLES / 2;
+
+/// flight在时间范围内的采样，边界为闭区间
+fn samples_filter(flight_id: &ObjectId, from: Option<DateTime>, to: Option<DateTime>) -> Document {
+    let mut filter = doc! {"meta.flightId": flight_id};
+    let mut range = Document::new();
+    if let Some(from) = from {
+        range.insert("$gte", from);
+    }
+    if let Some(to) = to {
+        range.insert("$lte", to);
+    }
+    if !range.is_empty() {
+        filter.insert("timestamp", range);
+    }
+    filter
+}
+
+/// 从分桶结果中取出一个指标的点，每个桶按时间顺序输出最小值和最大值
+fn bucket_values(buckets: &[Document], metric: FlightMetric) -> Vec<(DateTime, f64)> {
+    let extreme = |bucket: &Document, suffix: &str| {
+        let extreme = bucket.get_document(format!("{}_{}", metric.as_str(), suffix)).ok()?;
+        Some((*extreme.get_datetime("t").ok()?, number(extreme.get("v")?)?))
+    };
+    let mut values = Vec::with_capacity(buckets.len() * 2);
+    for bucket in buckets {
+        let (Some(min), Some(max)) = (extreme(bucket, "min"), extreme(bucket, "max")) else {
+            continue;
+        };
+        match min.0.cmp(&max.0) {
+            Ordering::Less => values.extend([min, max]),
+            Ordering::Greater => values.extend([max, min]),
+            Ordering::Equal => values.push(min),
+        }
+    }
+    values
+}
+
+fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Double(v) => Some(*v),
+        Bson::Int32(v) => Some(*v as f64),
+        Bson::Int64(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extreme(value: f64, millis: i64) -> Document {
+        doc! {"v": value, "t": DateTime::from_millis(millis)}
+    }
+
+    #[test]
+    fn bucket_values_are_in_time_order() {
+        let buckets = vec![
+            doc! {"_id": 0.0, "airPressure_min": extreme(1000.0, 300), "airPressure_max": extreme(1010.0, 100)},
+            doc! {"_id": 1.0, "airPressure_min": extreme(990.0, 400), "airPressure_max": extreme(995.0, 500)},
+            // 桶中只有一条采样时最小值和最大值相同
+            doc! {"_id": 2.0, "airPressure_min": extreme(980.0, 600), "airPressure_max": extreme(980.0, 600)},
+        ];
+        let values: Vec<(i64, f64)> = bucket_values(&buckets, FlightMetric::AirPressure)
+            .into_iter()
+            .map(|(timestamp, value)| (timestamp.timestamp_millis(), value))
+            .collect();
+        assert_eq!(values, vec![(100, 1010.0), (300, 1000.0), (400, 990.0), (500, 995.0), (600, 980.0)]);
+    }
+
+    #[test]
+    fn bucket_values_skip_missing_metrics() {
+        let buckets = vec![doc! {"_id": 0.0, "airPressure_min": extreme(1.0, 1), "airPressure_max": extreme(2.0, 2)}];
+        assert!(bucket_values(&buckets, FlightMetric::CabinTemperature).is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn samples_filter_bounds_are_optional() {
+        let flight_id = ObjectId::new();
+        assert_eq!(samples_filter(&flight_id, None, None), doc! {"meta.flightId": flight_id});
+        let from = DateTime::from_millis(1);
+        assert_eq!(
+            samples_filter(&flight_id, Some(from), None),
+            doc! {"meta.flightId": flight_id, "timestamp": {"$gte": from}}
+        );
+    }
+}
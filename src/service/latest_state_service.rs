@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use futures::{stream, StreamExt, TryStreamExt};
+use crate::model::drone::DroneSnapshotDto;
+use crate::model::flight::FlightEventDto;
+use crate::model::ship_track::LocationEventDto;
+use crate::service::drone_service::DroneService;
+use crate::service::flight_service::FlightService;
+use crate::service::ship_track_service::ShipTrackService;
+
+/// 不指定无人机时最多查询的无人机数
+const MAX_SNAPSHOT_DRONES: i64 = 1000;
+/// 同时查询的无人机数
+const SNAPSHOT_CONCURRENCY: usize = 8;
+
+/// 从数据库查询无人机的最新位置和flight状态，启动时用于预热 [`LastKnownState`](crate::live::LastKnownState)
+pub struct LatestStateService {
+    drone_service: Arc<DroneService>,
+    track_service: Arc<ShipTrackService>,
+    flight_service: Arc<FlightService>,
+}
+
+impl LatestStateService {
+    pub fn new(
+        drone_service: Arc<DroneService>,
+        track_service: Arc<ShipTrackService>,
+        flight_service: Arc<FlightService>,
+    ) -> Self {
+        Self {
+            drone_service,
+            track_service,
+            flight_service,
+        }
+    }
+
+    /// 查询 `drones` 中每架无人机的最新状态，`None` 表示全部已登记的无人机；
+    /// 没有任何数据的无人机不出现在结果中
+    pub async fn snapshot(&self, drones: Option<&HashSet<String>>) -> mongodb::error::Result<Vec<DroneSnapshotDto>> {
+        let serials: Vec<String> = match drones {
+            Some(drones) => {
+                let mut serials: Vec<String> = drones.iter().cloned().collect();
+                serials.sort();
+                serials
+            }
+            None => self
+                .drone_service
+                .list(0, MAX_SNAPSHOT_DRONES)
+                .await?
+                .into_iter()
+                .map(|drone| drone.serial)
+                .collect(),
+        };
+
+        let snapshots: Vec<DroneSnapshotDto> = stream::iter(serials)
+            .map(|serial| self.drone_snapshot(serial))
+            .buffered(SNAPSHOT_CONCURRENCY)
+            .try_collect()
+            .await?;
+        Ok(snapshots
+            .into_iter()
+            .filter(|snapshot| snapshot.location.is_some() || snapshot.state.is_some())
+            .collect())
+    }
+
+    async fn drone_snapshot(&self, serial: String) -> mongodb::error::Result<DroneSnapshotDto> {
+        let location = self.track_service.get_latest_position(&serial).await?.and_then(|track| {
+            let point = *track.coordinates.last()?;
+            Some(LocationEventDto::new(
+                Some(serial.clone()),
+                track.id,
+                track.last_update.to_chrono(),
+                vec![point],
+            ))
+        });
+        let state = self.flight_service.latest_sample(&serial).await?.map(|sample| FlightEventDto {
+            drone_id: Some(serial.clone()),
+            flight_id: sample.meta.flight_id,
+            timestamp: sample.timestamp.to_chrono(),
+            data: sample.state(),
+        });
+        Ok(DroneSnapshotDto {
+            drone_id: serial,
+            location,
+            state,
+        })
+    }
+}
@@ -1,2 +1,22 @@
 pub(crate) mod ship_track_service;
-pub mod flight_service;
\ No newline at end of file
+pub mod flight_service;
+pub mod dead_letter_service;
+pub mod drone_service;
+pub mod provisioning_service;
+pub mod downsample;
+pub mod latest_state_service;
+
+use mongodb::error::{CommandError, Error, ErrorKind, WriteFailure};
+
+/// MongoDB唯一索引冲突（E11000），并发upsert同一文档时可能出现
+pub fn is_duplicate_key_error(error: &Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000
+    )
+}
+
+/// 集合已存在（NamespaceExists），多个实例同时启动创建集合时可能出现
+pub fn is_namespace_exists_error(error: &Error) -> bool {
+    matches!(error.kind.as_ref(), ErrorKind::Command(CommandError { code: 48, .. }))
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use log::info;
+use crate::model::drone::ActiveDocuments;
+use crate::service::drone_service::DroneService;
+use crate::service::flight_service::FlightService;
+use crate::service::ship_track_service::ShipTrackService;
+
+/// 缓存的ID在此时间后重新从数据库确认，使通过REST接口结束飞行等外部修改能及时生效
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// 首次收到遥测时自动创建航迹和关联的flight文档
+///
+/// 无人机序列号通过 [`DroneService`] 映射到当前的航迹和flight ID，两个文档都通过upsert创建，
+/// 位置和状态消息同时到达时也只会各插入一次。已确认存在的ID缓存在内存中，避免每条消息都访问数据库。
+///
+/// 共享订阅下同一无人机的消息由多个实例处理，其他实例可能已切换分段或结束飞行，
+/// 此时缓存的ID每次使用前都与无人机文档核对，只省去创建文档的upsert。
+pub struct ProvisioningService {
+    drone_service: Arc<DroneService>,
+    track_service: Arc<ShipTrackService>,
+    flight_service: Arc<FlightService>,
+    provisioned: Mutex<HashMap<String, (ActiveDocuments, Instant)>>,
+    /// 缓存的ID是否需要每次核对
+    verify_cached: bool,
+}
+
+impl ProvisioningService {
+    pub fn new(
+        drone_service: Arc<DroneService>,
+        track_service: Arc<ShipTrackService>,
+        flight_service: Arc<FlightService>,
+        verify_cached: bool,
+    ) -> Self {
+        Self {
+            drone_service,
+            track_service,
+            flight_service,
+            provisioned: Mutex::new(HashMap::new()),
+            verify_cached,
+        }
+    }
+
+    /// 确保无人机当前的航迹和flight文档存在，`first_seen` 作为新航迹的开始时间
+    pub async fn ensure_provisioned(
+        &self,
+        serial: &str,
+        first_seen: DateTime<Utc>,
+    ) -> mongodb::error::Result<ActiveDocuments> {
+        let cached = self.provisioned.lock().unwrap().get(serial).copied();
+        if let Some((active, _)) = cached.filter(|(_, cached_at)| cached_at.elapsed() < CACHE_TTL) {
+            if !self.verify_cached || self.drone_service.is_active(serial, &active).await? {
+                return Ok(active);
+            }
+            info!("无人机 {} 的当前航迹已被其他实例修改，重新获取", serial);
+        }
+
+        let active = self.drone_service.resolve_active(serial).await?;
+        if self
+            .track_service
+            .ensure_exists(&active.track_id, serial, &active.flight_id, first_seen.into())
+            .await?
+        {
+            info!("无人机 {} 开始新航迹: {}", serial, active.track_id);
+        }
+        if self.flight_service.ensure_exists(&active.flight_id, &active.track_id, serial).await? {
+            info!("无人机 {} 开始新flight: {}", serial, active.flight_id);
+        }
+        self.provisioned
+            .lock()
+            .unwrap()
+            .insert(serial.to_string(), (active, Instant::now()));
+        Ok(active)
+    }
+
+    /// 结束当前航迹分段，开启同一flight的下一个分段并返回新的当前文档
+    ///
+    /// 切换以无人机当前航迹仍为 `active.track_id` 为条件；其他实例已切换或飞行已结束时，
+    /// 删除刚插入的分段并重新获取当前文档。
+    pub async fn roll_segment(
+        &self,
+        serial: &str,
+        active: ActiveDocuments,
+        started_at: DateTime<Utc>,
+    ) -> mongodb::error::Result<ActiveDocuments> {
+        let next = self
+            .track_service
+            .open_next_segment(&active.track_id, serial, &active.flight_id, started_at.into())
+            .await?;
+        if !self.drone_service.advance_track(serial, &active.track_id, &next).await? {
+            self.track_service.delete(&next).await?;
+            self.forget(serial);
+            return self.ensure_provisioned(serial, started_at).await;
+        }
+        let rolled = ActiveDocuments { track_id: next, flight_id: active.flight_id };
+        self.provisioned
+            .lock()
+            .unwrap()
+            .insert(serial.to_string(), (rolled, Instant::now()));
+        Ok(rolled)
+    }
+
+    /// 清除缓存，用于结束飞行等改变当前文档的操作之后
+    pub fn forget(&self, serial: &str) {
+        self.provisioned.lock().unwrap().remove(serial);
+    }
+}
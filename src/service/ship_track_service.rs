@@ -1,31 +1,114 @@
-use crate::model::ship_track::ShipTrack;
-use bson::{Bson, DateTime};
+use std::time::Duration;
+use crate::config::SegmentsConfig;
+use crate::model::geo::Geometry;
+use crate::model::ship_track::{NearbyDroneDto, ShipTrack, TrackPoint};
+use crate::service::is_duplicate_key_error;
+use bson::{Bson, DateTime, Document};
 use chrono::Utc;
+use futures::TryStreamExt;
 use log::error;
 use mongodb::results::UpdateResult;
-use mongodb::{bson::{doc, oid::ObjectId}, options::FindOneOptions, Collection};
+use mongodb::{bson::{doc, oid::ObjectId}, options::FindOneOptions, Collection, IndexModel};
 
 pub struct ShipTrackService {
     pub collection: Collection<ShipTrack>,
+    /// 分段上限
+    limits: SegmentsConfig,
+}
+
+/// 开启新分段的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RolloverReason {
+    MaxPoints,
+    MaxBytes,
+    Gap,
+}
+
+impl std::fmt::Display for RolloverReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RolloverReason::MaxPoints => write!(f, "点数达到上限"),
+            RolloverReason::MaxBytes => write!(f, "大小达到上限"),
+            RolloverReason::Gap => write!(f, "位置消息间隔过长"),
+        }
+    }
 }
 
 impl ShipTrackService{
-    pub fn new(collection: Collection<ShipTrack>) -> Self {
-        Self { collection }
+    pub fn new(collection: Collection<ShipTrack>, limits: SegmentsConfig) -> Self {
+        Self { collection, limits }
     }
 
-    pub async fn create(&self, track: ShipTrack) -> mongodb::error::Result<()> {
-        self.collection.insert_one(track).await?;
+    /// 创建空间索引、按flight拼接分段的索引和查询无人机最新航迹的索引
+    pub async fn ensure_indexes(&self) -> mongodb::error::Result<()> {
+        let indexes = vec![
+            IndexModel::builder().keys(doc! {"path": "2dsphere"}).build(),
+            IndexModel::builder().keys(doc! {"lastPosition": "2dsphere"}).build(),
+            IndexModel::builder().keys(doc! {"flightId": 1, "segmentIndex": 1}).build(),
+            IndexModel::builder().keys(doc! {"droneId": 1, "lastUpdate": -1}).build(),
+        ];
+        self.collection.create_indexes(indexes).await?;
         Ok(())
     }
 
-    pub async fn get(&self, id: &str) -> mongodb::error::Result<Option<ShipTrack>> {
-        let obj_id = ObjectId::parse_str(id).map_err(|e| {
-            error!("{:?}",e);
-            mongodb::error::Error::custom(e)})?;
-        self.collection.find_one(doc! {"_id": obj_id}).await
+    /// 用给定的航迹点创建独立航迹，不关联flight，航迹点与追加时一样按时间排序
+    pub async fn create(&self, drone_id: Option<String>, mut points: Vec<TrackPoint>) -> mongodb::error::Result<ShipTrack> {
+        points.sort_by_key(|point| point.timestamp);
+        let now: DateTime = Utc::now().into();
+        let start_time = points.iter().filter_map(|point| point.timestamp).min().unwrap_or(now);
+        let id = ObjectId::new();
+        let track = ShipTrack {
+            id,
+            size_bytes: empty_segment_size(id, drone_id.as_deref(), None, None)? + points_size(&points)?,
+            drone_id,
+            flight_id: None,
+            segment_index: 0,
+            prev_segment_id: None,
+            start_time,
+            last_update: now,
+            total_points: points.len() as u32,
+            path: (!points.is_empty()).then(|| Geometry::MultiPoint {
+                coordinates: points.iter().map(|point| [point.longitude, point.latitude]).collect(),
+            }),
+            last_position: points.last().map(|point| Geometry::point(point.longitude, point.latitude)),
+            coordinates: points,
+        };
+        self.collection.insert_one(&track).await?;
+        Ok(track)
+    }
+
+    pub async fn get(&self, id: &ObjectId) -> mongodb::error::Result<Option<ShipTrack>> {
+        self.collection.find_one(doc! {"_id": id}).await
     }
 
+    /// 按条件分页列出航迹，按最后更新时间倒序，结果不含航迹点
+    pub async fn list(&self, filter: &TrackFilter, skip: u64, limit: i64) -> mongodb::error::Result<Vec<ShipTrack>> {
+        let mut query = Document::new();
+        if let Some(drone_id) = &filter.drone_id {
+            query.insert("droneId", drone_id);
+        }
+        if let Some(flight_id) = filter.flight_id {
+            query.insert("flightId", flight_id);
+        }
+        // 时间范围与航迹的 [startTime, lastUpdate] 有交集即匹配
+        if let Some(from) = filter.from {
+            query.insert("lastUpdate", doc! {"$gte": from});
+        }
+        if let Some(to) = filter.to {
+            query.insert("startTime", doc! {"$lte": to});
+        }
+        self.collection
+            .find(query)
+            .projection(summary_projection())
+            .sort(doc! {"lastUpdate": -1})
+            .skip(skip)
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    #[allow(dead_code)]
     pub async fn update(&self, id: &str, track: ShipTrack) -> mongodb::error::Result<()> {
         let obj_id = ObjectId::parse_str(id).map_err(|e| {
             error!("{:?}",e);
@@ -33,51 +116,543 @@ impl ShipTrackService{
         self.collection.replace_one(doc! {"_id": obj_id}, track).await?;
         Ok(())
     }
-    // 新增方法：追加坐标并更新相关字段
-    pub async fn append_coordinates_and_update(
+    /// 航迹不存在时创建flight的第一个空分段，返回是否新建
+    ///
+    /// 使用 `$setOnInsert` upsert，并发调用时只有一次会插入文档，`startTime` 不会被覆盖。
+    pub async fn ensure_exists(
         &self,
-        id: &str,
-        coordinates_to_add: Vec<[f64; 2]>,
-    ) -> mongodb::error::Result<UpdateResult> {
-        let obj_id = ObjectId::parse_str(id).map_err(|e| {
-            error!("{:?}",e);
-            mongodb::error::Error::custom(e)})?;
+        id: &ObjectId,
+        drone_id: &str,
+        flight_id: &ObjectId,
+        start_time: DateTime,
+    ) -> mongodb::error::Result<bool> {
+        let update = doc! {
+            "$setOnInsert": {
+                "droneId": drone_id,
+                "flightId": flight_id,
+                "segmentIndex": 0i32,
+                "sizeBytes": empty_segment_size(*id, Some(drone_id), Some(*flight_id), None)?,
+                "startTime": start_time,
+                "lastUpdate": start_time,
+                "totalPoints": 0i32,
+                "coordinates": [],
+            }
+        };
+        match self.collection.update_one(doc! {"_id": id}, update).upsert(true).await {
+            Ok(result) => Ok(result.upserted_id.is_some()),
+            // 并发upsert时另一方已插入
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
 
-        let current_time: DateTime = Utc::now().into();
-        let mut update_document_parts = doc! { "$set": { "lastUpdate": current_time } };
+    /// 判断追加一批航迹点前是否需要开启新分段，不需要时返回 `None`
+    ///
+    /// 空分段不会滚动，单批超过上限的航迹点仍写入同一分段。分段不存在时返回 `None`。
+    pub async fn rollover_reason(
+        &self,
+        segment_id: &ObjectId,
+        points: &[TrackPoint],
+        now: DateTime,
+    ) -> mongodb::error::Result<Option<RolloverReason>> {
+        let projection = doc! {"totalPoints": 1, "sizeBytes": 1, "lastUpdate": 1};
+        let Some(segment) = self
+            .collection
+            .clone_with_type::<Document>()
+            .find_one(doc! {"_id": segment_id})
+            .projection(projection)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let usage = SegmentUsage::from_document(&segment);
+        Ok(usage.rollover_reason(&self.limits, points.len(), points_size(points)?, now))
+    }
+
+    /// 单批航迹点本身超过分段上限时返回原因，这样的一批点无法放入任何分段
+    pub fn oversized_batch(&self, points: &[TrackPoint]) -> mongodb::error::Result<Option<RolloverReason>> {
+        if points.len() as u64 > self.limits.max_points as u64 {
+            return Ok(Some(RolloverReason::MaxPoints));
+        }
+        if points_size(points)? > self.limits.max_bytes {
+            return Ok(Some(RolloverReason::MaxBytes));
+        }
+        Ok(None)
+    }
 
-        if !coordinates_to_add.is_empty() {
-            let bson_coordinates_to_add: Vec<Bson> = coordinates_to_add
-                .into_iter()
-                .map(|coord_pair| Bson::Array(vec![Bson::Double(coord_pair[0]), Bson::Double(coord_pair[1])]))
-                .collect();
+    /// 在 `current_id` 之后插入同一flight的下一个空分段，返回新分段ID
+    pub async fn open_next_segment(
+        &self,
+        current_id: &ObjectId,
+        drone_id: &str,
+        flight_id: &ObjectId,
+        start_time: DateTime,
+    ) -> mongodb::error::Result<ObjectId> {
+        let index = self
+            .collection
+            .clone_with_type::<Document>()
+            .find_one(doc! {"_id": current_id})
+            .projection(doc! {"segmentIndex": 1})
+            .await?
+            .map(|segment| integer_field(&segment, "segmentIndex"))
+            .unwrap_or(0);
+        let id = ObjectId::new();
+        let segment = ShipTrack {
+            id,
+            drone_id: Some(drone_id.to_string()),
+            flight_id: Some(*flight_id),
+            segment_index: index as u32 + 1,
+            prev_segment_id: Some(*current_id),
+            size_bytes: empty_segment_size(id, Some(drone_id), Some(*flight_id), Some(*current_id))?,
+            start_time,
+            last_update: start_time,
+            coordinates: Vec::new(),
+            total_points: 0,
+            path: None,
+            last_position: None,
+        };
+        self.collection.insert_one(&segment).await?;
+        Ok(segment.id)
+    }
 
-            update_document_parts.insert("$push", doc! { "coordinates": { "$each": bson_coordinates_to_add } });
-            update_document_parts.insert("$inc", doc! { "totalPoints": 1i32 }); // totalPoints 增加 1
+    /// 按 `segmentIndex` 顺序列出flight的所有分段
+    ///
+    /// 分段功能之前创建的航迹没有 `flightId`，通过flight文档中的 `first_segment_id` 一并查出。
+    pub async fn list_segments(
+        &self,
+        flight_id: &ObjectId,
+        first_segment_id: Option<ObjectId>,
+    ) -> mongodb::error::Result<Vec<ShipTrack>> {
+        let mut filter = doc! {"flightId": flight_id};
+        if let Some(first_segment_id) = first_segment_id {
+            filter = doc! {"$or": [filter, {"_id": first_segment_id}]};
         }
-        // self.collection
-        //     .find_one_and_update(doc! {"_id": obj_id}, update_document_parts)
-        //     .with_options(options)
-        //     .await
         self.collection
-            .update_one(doc! {"_id": obj_id}, update_document_parts)
+            .find(filter)
+            .sort(doc! {"segmentIndex": 1, "startTime": 1})
+            .await?
+            .try_collect()
             .await
     }
-    pub async fn delete(&self, id: &str) -> mongodb::error::Result<()> {
-        let obj_id = ObjectId::parse_str(id).map_err(|e| {
-            error!("{:?}",e);
-            mongodb::error::Error::custom(e)})?;
-        self.collection.delete_one(doc! {"_id": obj_id}).await?;
-        Ok(())
+
+    /// 查询与多边形相交的航迹，按最后更新时间倒序，结果不含航迹点
+    pub async fn find_intersecting(&self, polygon: &Geometry, limit: i64) -> mongodb::error::Result<Vec<ShipTrack>> {
+        let filter = doc! {"path": {"$geoIntersects": {"$geometry": bson::to_bson(polygon)?}}};
+        self.collection
+            .find(filter)
+            .projection(summary_projection())
+            .sort(doc! {"lastUpdate": -1})
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /// 查询最新位置在 `center` 周围 `radius` 米内、且在 `since` 之后更新过的无人机，按距离排序
+    ///
+    /// 每架无人机只取最近更新的分段。
+    pub async fn find_nearby_drones(
+        &self,
+        center: [f64; 2],
+        radius: f64,
+        since: DateTime,
+        limit: i64,
+    ) -> mongodb::error::Result<Vec<NearbyDroneDto>> {
+        let pipeline = vec![
+            doc! {"$geoNear": {
+                "near": bson::to_bson(&Geometry::point(center[0], center[1]))?,
+                // 集合有多个 2dsphere 索引，需要指定字段
+                "key": "lastPosition",
+                "distanceField": "distance",
+                "maxDistance": radius,
+                "spherical": true,
+                "query": {"droneId": {"$exists": true}, "lastUpdate": {"$gte": since}},
+            }},
+            doc! {"$sort": {"lastUpdate": -1}},
+            doc! {"$group": {
+                "_id": "$droneId",
+                "trackId": {"$first": "$_id"},
+                "lastPosition": {"$first": "$lastPosition"},
+                "distance": {"$first": "$distance"},
+                "lastUpdate": {"$first": "$lastUpdate"},
+            }},
+            doc! {"$sort": {"distance": 1}},
+            doc! {"$limit": limit},
+            doc! {"$project": {
+                "_id": 0,
+                "droneId": "$_id",
+                "trackId": 1,
+                "longitude": {"$arrayElemAt": ["$lastPosition.coordinates", 0]},
+                "latitude": {"$arrayElemAt": ["$lastPosition.coordinates", 1]},
+                "distance": 1,
+                "lastUpdate": 1,
+            }},
+        ];
+        self.collection
+            .aggregate(pipeline)
+            .with_type::<NearbyDroneDto>()
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /// 查询时间窗口内经过矩形区域的航迹，`coordinates` 只保留区域和窗口内的航迹点
+    ///
+    /// `min`、`max` 为 `[经度, 纬度]`。没有设备时间的航迹点按所在分段的时间范围判断。
+    /// 索引查询使用的矩形边为大圆弧，与经纬度矩形在高纬度有偏差，航迹点再按经纬度精确过滤。
+    pub async fn find_in_area(
+        &self,
+        min: [f64; 2],
+        max: [f64; 2],
+        from: DateTime,
+        to: DateTime,
+        limit: i64,
+    ) -> mongodb::error::Result<Vec<ShipTrack>> {
+        let in_window = doc! {"$and": [
+            {"$gte": ["$$point.timestamp", from]},
+            {"$lte": ["$$point.timestamp", to]},
+        ]};
+        let pipeline = vec![
+            doc! {"$match": {
+                "path": {"$geoIntersects": {"$geometry": bson::to_bson(&Geometry::bbox(min, max))?}},
+                "startTime": {"$lte": to},
+                "lastUpdate": {"$gte": from},
+            }},
+            doc! {"$set": {"coordinates": {"$filter": {
+                "input": "$coordinates",
+                "as": "point",
+                "cond": {"$and": [
+                    {"$gte": ["$$point.longitude", min[0]]},
+                    {"$lte": ["$$point.longitude", max[0]]},
+                    {"$gte": ["$$point.latitude", min[1]]},
+                    {"$lte": ["$$point.latitude", max[1]]},
+                    {"$or": [{"$eq": [{"$type": "$$point.timestamp"}, "missing"]}, in_window]},
+                ]},
+            }}}},
+            doc! {"$match": {"coordinates.0": {"$exists": true}}},
+            doc! {"$sort": {"startTime": 1}},
+            doc! {"$limit": limit},
+            doc! {"$project": {"path": 0, "lastPosition": 0}},
+        ];
+        self.collection
+            .aggregate(pipeline)
+            .with_type::<ShipTrack>()
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /// 追加航迹点并更新 `lastUpdate`、`path` 和 `lastPosition`
+    ///
+    /// 多实例共享订阅时同一无人机的消息可能由不同实例并发处理，写入顺序不一定是采样顺序，
+    /// 因此 `coordinates` 按 `timestamp` 排序插入（没有时间的航迹点排在最前），
+    /// 只有这批航迹点不早于已有航迹点时才更新 `lastPosition`。`path` 是MultiPoint，与顺序无关。
+    pub async fn append_coordinates_and_update(
+        &self,
+        id: &ObjectId,
+        coordinates_to_add: Vec<TrackPoint>,
+    ) -> mongodb::error::Result<UpdateResult> {
+        let current_time: DateTime = Utc::now().into();
+        // 时间相同时取靠后的航迹点
+        let Some(latest) = coordinates_to_add.iter().max_by_key(|point| point.timestamp) else {
+            return self
+                .collection
+                .update_one(doc! {"_id": id}, doc! {"$set": {"lastUpdate": current_time}})
+                .await;
+        };
+        let last_position = bson::to_bson(&Geometry::point(latest.longitude, latest.latitude))?;
+        let latest_timestamp = latest.timestamp;
+        let bson_coordinates_to_add = coordinates_to_add
+            .iter()
+            .map(bson::to_bson)
+            .collect::<Result<Vec<Bson>, _>>()?;
+        let positions: Vec<Bson> = coordinates_to_add
+            .iter()
+            .map(|point| Bson::Array(vec![point.longitude.into(), point.latitude.into()]))
+            .collect();
+        let size_bytes = points_size(&coordinates_to_add)?;
+
+        let set = doc! {"lastUpdate": current_time, "path.type": "MultiPoint"};
+        let update = |set: Document| {
+            doc! {
+                "$set": set,
+                "$push": {
+                    "coordinates": { "$each": &bson_coordinates_to_add, "$sort": { "timestamp": 1 } },
+                    "path.coordinates": { "$each": &positions },
+                },
+                "$inc": { "totalPoints": coordinates_to_add.len() as i32, "sizeBytes": size_bytes },
+            }
+        };
+        // 已有更晚的航迹点时不匹配，改为不更新 `lastPosition` 再写一次
+        let mut newest = doc! {"_id": id};
+        if let Some(timestamp) = latest_timestamp {
+            newest.insert("coordinates", doc! {"$not": {"$elemMatch": {"timestamp": {"$gt": timestamp}}}});
+        }
+        let mut newest_set = set.clone();
+        newest_set.insert("lastPosition", last_position);
+        let result = self.collection.update_one(newest, update(newest_set)).await?;
+        if result.matched_count > 0 || latest_timestamp.is_none() {
+            return Ok(result);
+        }
+        self.collection.update_one(doc! {"_id": id}, update(set)).await
     }
 
-    pub async fn get_latest(&self) -> mongodb::error::Result<Option<ShipTrack>> {
+    /// 删除航迹，返回被删除的文档
+    pub async fn delete(&self, id: &ObjectId) -> mongodb::error::Result<Option<ShipTrack>> {
+        self.collection.find_one_and_delete(doc! {"_id": id}).await
+    }
+
+    /// 最近更新的航迹，可限定无人机
+    pub async fn get_latest(&self, drone_id: Option<&str>) -> mongodb::error::Result<Option<ShipTrack>> {
+        let filter = match drone_id {
+            Some(drone_id) => doc! {"droneId": drone_id},
+            None => doc! {},
+        };
         let find_options = FindOneOptions::builder().sort(doc! {"lastUpdate": -1}).build();
-        self.collection.find_one(doc! {}).with_options(find_options).await
+        self.collection.find_one(filter).with_options(find_options).await
+    }
+
+    /// 无人机最新的航迹，只读取最后一个航迹点
+    pub async fn get_latest_position(&self, drone_id: &str) -> mongodb::error::Result<Option<ShipTrack>> {
+        self.collection
+            .find_one(doc! {"droneId": drone_id})
+            .sort(doc! {"lastUpdate": -1})
+            .projection(doc! {"coordinates": {"$slice": -1}, "path": 0})
+            .await
+    }
+}
+
+/// 航迹列表的过滤条件
+#[derive(Debug, Default)]
+pub struct TrackFilter {
+    pub drone_id: Option<String>,
+    pub flight_id: Option<ObjectId>,
+    /// 与 `to` 一起筛选时间范围有交集的航迹
+    pub from: Option<DateTime>,
+    pub to: Option<DateTime>,
+}
+
+/// 数组元素下标占用的字节数上限，16MB的文档中数组长度不超过7位数
+const ARRAY_KEY_BYTES: i64 = 7;
+
+/// `path.coordinates` 中一个 `[经度, 纬度]` 数组的字节数
+const POSITION_BYTES: i64 = 27;
+
+/// 数组元素除值以外的字节数：类型、下标和下标结尾的0
+const ARRAY_ELEMENT_OVERHEAD: i64 = 1 + ARRAY_KEY_BYTES + 1;
+
+/// 航迹点写入分段后增加的字节数（上限）
+///
+/// 每个航迹点在 `coordinates` 和 `path.coordinates` 中各存一份。
+fn points_size(points: &[TrackPoint]) -> mongodb::error::Result<i64> {
+    let mut size = 0i64;
+    for point in points {
+        size += ARRAY_ELEMENT_OVERHEAD + bson::to_raw_document_buf(point)?.as_bytes().len() as i64;
+        size += ARRAY_ELEMENT_OVERHEAD + POSITION_BYTES;
     }
+    Ok(size)
 }
 
+/// 不含航迹点的分段文档的字节数，包含写入第一个航迹点后才有的 `path` 和 `lastPosition`
+fn empty_segment_size(
+    id: ObjectId,
+    drone_id: Option<&str>,
+    flight_id: Option<ObjectId>,
+    prev_segment_id: Option<ObjectId>,
+) -> mongodb::error::Result<i64> {
+    let now = DateTime::now();
+    let segment = ShipTrack {
+        id,
+        drone_id: drone_id.map(str::to_string),
+        flight_id,
+        segment_index: 0,
+        prev_segment_id,
+        size_bytes: 0,
+        start_time: now,
+        last_update: now,
+        total_points: 0,
+        coordinates: Vec::new(),
+        path: Some(Geometry::MultiPoint { coordinates: Vec::new() }),
+        last_position: Some(Geometry::point(0.0, 0.0)),
+    };
+    Ok(bson::to_vec(&segment)?.len() as i64)
+}
 
+/// 分段当前的点数、大小和最后更新时间
+#[derive(Debug, Clone, Copy)]
+struct SegmentUsage {
+    total_points: i64,
+    size_bytes: i64,
+    last_update: Option<DateTime>,
+}
 
+impl SegmentUsage {
+    fn from_document(segment: &Document) -> Self {
+        Self {
+            total_points: integer_field(segment, "totalPoints"),
+            size_bytes: integer_field(segment, "sizeBytes"),
+            last_update: segment.get_datetime("lastUpdate").ok().copied(),
+        }
+    }
+
+    /// 追加 `batch_points` 个、共 `batch_bytes` 字节的航迹点前是否需要开启新分段
+    ///
+    /// 依次检查点数、大小和时间间隔，刚好达到上限时不滚动；空分段不会滚动。
+    fn rollover_reason(
+        &self,
+        limits: &SegmentsConfig,
+        batch_points: usize,
+        batch_bytes: i64,
+        now: DateTime,
+    ) -> Option<RolloverReason> {
+        if self.total_points == 0 {
+            return None;
+        }
+        if self.total_points + batch_points as i64 > limits.max_points as i64 {
+            return Some(RolloverReason::MaxPoints);
+        }
+        if self.size_bytes + batch_bytes > limits.max_bytes {
+            return Some(RolloverReason::MaxBytes);
+        }
+        if let Some(last_update) = self.last_update {
+            let gap = now.timestamp_millis() - last_update.timestamp_millis();
+            if gap > Duration::from_secs(limits.max_gap_secs).as_millis() as i64 {
+                return Some(RolloverReason::Gap);
+            }
+        }
+        None
+    }
+}
+
+/// 排除航迹点的投影，列表类查询只返回航迹摘要
+fn summary_projection() -> Document {
+    doc! {"coordinates": 0, "path": 0, "lastPosition": 0}
+}
+
+/// 读取整数字段，兼容Int32和Int64存储，缺失时为0
+fn integer_field(document: &Document, field: &str) -> i64 {
+    match document.get(field) {
+        Some(Bson::Int32(v)) => *v as i64,
+        Some(Bson::Int64(v)) => *v,
+        Some(Bson::Double(v)) => *v as i64,
+        _ => 0,
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn point(i: usize) -> TrackPoint {
+        TrackPoint {
+            altitude: Some(120.5),
+            timestamp: Some(DateTime::from_millis(1_700_000_000_000 + i as i64 * 1000)),
+            ground_speed: Some(12.0),
+            heading: Some(90.0),
+            accuracy: None,
+            ..TrackPoint::from([120.0 + i as f64 * 1e-5, 30.0])
+        }
+    }
+
+    /// 按追加后的内容构造分段，`sizeBytes` 按写入时的方式累加
+    fn segment_with(points: Vec<TrackPoint>) -> ShipTrack {
+        let id = ObjectId::new();
+        let flight_id = ObjectId::new();
+        let mut size_bytes = empty_segment_size(id, Some("drone-1"), Some(flight_id), Some(ObjectId::new())).unwrap();
+        for batch in points.chunks(7) {
+            size_bytes += points_size(batch).unwrap();
+        }
+        let last = points.last().copied().unwrap();
+        ShipTrack {
+            id,
+            drone_id: Some("drone-1".to_string()),
+            flight_id: Some(flight_id),
+            segment_index: 3,
+            prev_segment_id: Some(ObjectId::new()),
+            size_bytes,
+            start_time: DateTime::now(),
+            last_update: DateTime::now(),
+            total_points: points.len() as u32,
+            path: Some(Geometry::MultiPoint {
+                coordinates: points.iter().map(|point| [point.longitude, point.latitude]).collect(),
+            }),
+            last_position: Some(Geometry::point(last.longitude, last.latitude)),
+            coordinates: points,
+        }
+    }
+
+    #[test]
+    fn size_bytes_covers_whole_document() {
+        for count in [1, 10, 1000, 20_000] {
+            let segment = segment_with((0..count).map(point).collect());
+            let actual = bson::to_vec(&segment).unwrap().len() as i64;
+            assert!(segment.size_bytes >= actual, "{} 个点: sizeBytes {} < 实际 {}", count, segment.size_bytes, actual);
+            // 只有数组下标按上限估算
+            assert!(segment.size_bytes - actual <= count as i64 * 2 * ARRAY_KEY_BYTES);
+        }
+    }
+
+    #[test]
+    fn position_bytes_matches_encoding() {
+        let position = bson::to_vec(&doc! {"0": 120.0, "1": 30.0}).unwrap();
+        assert_eq!(position.len() as i64, POSITION_BYTES);
+    }
+
+    fn limits() -> SegmentsConfig {
+        SegmentsConfig { max_points: 100, max_bytes: 10_000, max_gap_secs: 60 }
+    }
+
+    fn usage(total_points: i64, size_bytes: i64) -> SegmentUsage {
+        SegmentUsage { total_points, size_bytes, last_update: Some(DateTime::from_millis(1_000_000)) }
+    }
+
+    fn after_secs(secs: i64) -> DateTime {
+        DateTime::from_millis(1_000_000 + secs * 1000)
+    }
+
+    #[test]
+    fn rollover_on_max_points_boundary() {
+        let limits = limits();
+        assert_eq!(usage(99, 0).rollover_reason(&limits, 1, 0, after_secs(1)), None);
+        assert_eq!(usage(99, 0).rollover_reason(&limits, 2, 0, after_secs(1)), Some(RolloverReason::MaxPoints));
+        assert_eq!(usage(100, 0).rollover_reason(&limits, 1, 0, after_secs(1)), Some(RolloverReason::MaxPoints));
+    }
+
+    #[test]
+    fn rollover_on_max_bytes_boundary() {
+        let limits = limits();
+        assert_eq!(usage(10, 9_000).rollover_reason(&limits, 1, 1_000, after_secs(1)), None);
+        assert_eq!(usage(10, 9_000).rollover_reason(&limits, 1, 1_001, after_secs(1)), Some(RolloverReason::MaxBytes));
+    }
+
+    #[test]
+    fn rollover_on_gap_boundary() {
+        let limits = limits();
+        assert_eq!(usage(10, 0).rollover_reason(&limits, 1, 0, after_secs(60)), None);
+        assert_eq!(usage(10, 0).rollover_reason(&limits, 1, 0, after_secs(61)), Some(RolloverReason::Gap));
+        let unknown = SegmentUsage { last_update: None, ..usage(10, 0) };
+        assert_eq!(unknown.rollover_reason(&limits, 1, 0, after_secs(3600)), None);
+    }
+
+    #[test]
+    fn points_checked_before_bytes_and_gap() {
+        let limits = limits();
+        assert_eq!(usage(100, 10_000).rollover_reason(&limits, 1, 1, after_secs(3600)), Some(RolloverReason::MaxPoints));
+        assert_eq!(usage(10, 10_000).rollover_reason(&limits, 1, 1, after_secs(3600)), Some(RolloverReason::MaxBytes));
+    }
+
+    #[test]
+    fn empty_segment_never_rolls_over() {
+        let limits = limits();
+        assert_eq!(usage(0, 9_999).rollover_reason(&limits, 1000, 100_000, after_secs(3600)), None);
+    }
+
+    #[test]
+    fn usage_reads_int32_and_int64_fields() {
+        let segment = doc! {"totalPoints": 5i32, "sizeBytes": 4096i64, "lastUpdate": DateTime::from_millis(42)};
+        let usage = SegmentUsage::from_document(&segment);
+        assert_eq!((usage.total_points, usage.size_bytes), (5, 4096));
+        assert_eq!(usage.last_update, Some(DateTime::from_millis(42)));
+        assert!(SegmentUsage::from_document(&doc! {}).last_update.is_none());
+    }
+}
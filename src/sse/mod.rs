@@ -1,3 +1,4 @@
+pub mod replay;
 pub mod server;
 
-pub use server::start_sse_server;
+pub use server::{routes, SseState};
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::stream::Stream;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Instant};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::api::error::{parse_object_id, ApiError, ApiJson, ApiQuery};
+use mongodb::bson::oid::ObjectId;
+use crate::model::flight::{FlightEventDto, FlightSample};
+use crate::model::ship_track::{LocationEventDto, ShipTrack, TrackPoint};
+use super::server::{until_shutdown, SseState};
+
+/// 回放速度范围
+const MIN_SPEED: f64 = 0.5;
+const MAX_SPEED: f64 = 20.0;
+/// 回放事件的缓冲区，客户端读取慢时回放暂停推进
+const EVENT_BUFFER: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct ReplayQuery {
+    /// 回放速度，默认1倍速
+    pub speed: Option<f64>,
+    /// 起始位置，距回放开始（flight第一个分段开始）的毫秒数
+    pub position_ms: Option<i64>,
+    /// 以暂停状态开始
+    #[serde(default)]
+    pub paused: bool,
+}
+
+/// 回放控制命令，通过 `POST /sse/replay/sessions/{session_id}` 发送
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ReplayCommand {
+    Pause,
+    Resume,
+    /// 跳转到距回放开始 `positionMs` 毫秒处
+    Seek {
+        #[serde(rename = "positionMs")]
+        position_ms: i64,
+    },
+    Speed { speed: f64 },
+}
+
+/// 进行中的回放会话，会话在SSE连接断开时移除
+///
+/// 会话只保存在建立SSE连接的实例中，多实例部署时控制请求必须发到同一实例，
+/// 发到其他实例时返回404，负载均衡需按会话保持。
+#[derive(Default)]
+pub struct ReplayRegistry {
+    sessions: Mutex<HashMap<String, mpsc::Sender<ReplayCommand>>>,
+}
+
+impl ReplayRegistry {
+    fn register(&self) -> (String, mpsc::Receiver<ReplayCommand>) {
+        let (tx, rx) = mpsc::channel(16);
+        let mut sessions = self.sessions.lock().unwrap();
+        let id = loop {
+            let id = format!("{:016x}", rand::random::<u64>());
+            if !sessions.contains_key(&id) {
+                break id;
+            }
+        };
+        sessions.insert(id.clone(), tx);
+        (id, rx)
+    }
+
+    fn sender(&self, id: &str) -> Option<mpsc::Sender<ReplayCommand>> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    fn remove(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+}
+
+/// 回放时间线上的一条数据
+enum Frame {
+    /// 航迹点及其所在的分段
+    Location(ObjectId, TrackPoint),
+    Flight(FlightSample),
+}
+
+struct TimedFrame {
+    /// 距时间线开始的毫秒数
+    offset_ms: i64,
+    /// 记录时间（Unix毫秒）
+    recorded_at: i64,
+    frame: Frame,
+}
+
+/// 按记录时间排序的航迹点和状态采样
+struct Timeline {
+    frames: Vec<TimedFrame>,
+    duration_ms: i64,
+}
+
+impl Timeline {
+    /// 拼接各分段的航迹点，没有设备时间的航迹点（早期数据）按所在分段的起止时间均匀分布
+    fn build(segments: Vec<ShipTrack>, samples: Vec<FlightSample>) -> Self {
+        let start = segments.first().map(|segment| segment.start_time.timestamp_millis()).unwrap_or(0);
+        let mut frames: Vec<(i64, Frame)> = Vec::new();
+        for segment in segments {
+            let segment_start = segment.start_time.timestamp_millis();
+            let span = (segment.last_update.timestamp_millis() - segment_start).max(0);
+            let count = segment.coordinates.len();
+            frames.extend(segment.coordinates.into_iter().enumerate().map(|(i, point)| {
+                let at = match point.timestamp {
+                    Some(timestamp) => timestamp.timestamp_millis(),
+                    None if count > 1 => segment_start + span * i as i64 / (count as i64 - 1),
+                    None => segment_start,
+                };
+                (at, Frame::Location(segment.id, point))
+            }));
+        }
+        frames.extend(
+            samples
+                .into_iter()
+                .map(|sample| (sample.timestamp.timestamp_millis(), Frame::Flight(sample))),
+        );
+        // 稳定排序，同一时刻的航迹点先于状态
+        frames.sort_by_key(|(at, _)| *at);
+        let origin = frames.first().map(|(at, _)| *at).unwrap_or(start);
+        let duration_ms = frames.last().map(|(at, _)| at - origin).unwrap_or(0);
+        Timeline {
+            frames: frames
+                .into_iter()
+                .map(|(at, frame)| TimedFrame { offset_ms: at - origin, recorded_at: at, frame })
+                .collect(),
+            duration_ms,
+        }
+    }
+
+    /// 位置 `position_ms` 之后（含）的第一条数据
+    fn index_at(&self, position_ms: i64) -> usize {
+        self.frames.partition_point(|frame| frame.offset_ms < position_ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PlaybackState {
+    Playing,
+    Paused,
+    Ended,
+}
+
+/// 回放进度，客户端通过 `replay` 事件获取
+#[derive(Debug, Serialize)]
+struct ReplayStatus<'a> {
+    #[serde(rename = "sessionId")]
+    session_id: &'a str,
+    #[serde(rename = "trackId")]
+    track_id: &'a str,
+    state: PlaybackState,
+    #[serde(rename = "positionMs")]
+    position_ms: i64,
+    #[serde(rename = "durationMs")]
+    duration_ms: i64,
+    speed: f64,
+}
+
+struct Player {
+    session_id: String,
+    track_id: String,
+    drone_id: Option<String>,
+    timeline: Timeline,
+    cursor: usize,
+    speed: f64,
+    paused: bool,
+    /// 上次更新进度时的位置和时刻，播放时位置随时间推进
+    position_ms: i64,
+    anchored_at: Instant,
+}
+
+impl Player {
+    fn state(&self) -> PlaybackState {
+        if self.cursor >= self.timeline.frames.len() {
+            PlaybackState::Ended
+        } else if self.paused {
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Playing
+        }
+    }
+
+    /// 当前位置，不超过下一条数据的时间
+    fn position(&self) -> i64 {
+        if self.state() != PlaybackState::Playing {
+            return self.position_ms;
+        }
+        let elapsed = (self.anchored_at.elapsed().as_secs_f64() * 1000.0 * self.speed) as i64;
+        let next = self.timeline.frames[self.cursor].offset_ms;
+        (self.position_ms + elapsed).min(next)
+    }
+
+    fn anchor(&mut self, position_ms: i64) {
+        self.position_ms = position_ms;
+        self.anchored_at = Instant::now();
+    }
+
+    /// 距下一条数据的等待时间，暂停或已结束时为 `None`
+    fn next_delay(&self) -> Option<Duration> {
+        if self.state() != PlaybackState::Playing {
+            return None;
+        }
+        let remaining = (self.timeline.frames[self.cursor].offset_ms - self.position_ms).max(0);
+        Some(Duration::from_secs_f64(remaining as f64 / 1000.0 / self.speed))
+    }
+
+    fn apply(&mut self, command: ReplayCommand) {
+        let position = self.position();
+        self.anchor(position);
+        match command {
+            ReplayCommand::Pause => self.paused = true,
+            ReplayCommand::Resume => self.paused = false,
+            ReplayCommand::Seek { position_ms } => {
+                let position_ms = position_ms.clamp(0, self.timeline.duration_ms);
+                self.cursor = self.timeline.index_at(position_ms);
+                self.anchor(position_ms);
+            }
+            ReplayCommand::Speed { speed } => self.speed = speed,
+        }
+    }
+
+    fn status_event(&self) -> Event {
+        let status = ReplayStatus {
+            session_id: &self.session_id,
+            track_id: &self.track_id,
+            state: self.state(),
+            position_ms: self.position(),
+            duration_ms: self.timeline.duration_ms,
+            speed: self.speed,
+        };
+        Event::default()
+            .event("replay")
+            .data(serde_json::to_string(&status).unwrap_or_default())
+    }
+
+    /// 取出下一条数据，格式与实时推送相同：位置为 `location` 事件，状态为 `flight` 事件
+    fn next_event(&mut self) -> Option<Event> {
+        let frame = self.timeline.frames.get(self.cursor)?;
+        let timestamp = chrono::DateTime::from_timestamp_millis(frame.recorded_at).unwrap_or_default();
+        let event = match &frame.frame {
+            Frame::Location(segment_id, point) => {
+                let location = LocationEventDto::new(self.drone_id.clone(), *segment_id, timestamp, vec![*point]);
+                Event::default().event("location").data(serde_json::to_string(&location).unwrap_or_default())
+            }
+            Frame::Flight(sample) => {
+                let flight = FlightEventDto {
+                    drone_id: sample.meta.drone_id.clone().or_else(|| self.drone_id.clone()),
+                    flight_id: sample.meta.flight_id,
+                    timestamp,
+                    data: sample.state(),
+                };
+                Event::default().event("flight").data(serde_json::to_string(&flight).unwrap_or_default())
+            }
+        };
+        let offset_ms = frame.offset_ms;
+        self.cursor += 1;
+        self.anchor(offset_ms);
+        Some(event)
+    }
+}
+
+/// 以SSE回放存储的航迹及其flight状态
+///
+/// 航迹属于flight时按分段顺序回放整个flight，`location` 事件的 `trackId` 为航迹点所在的分段。
+/// 第一条 `replay` 事件中的 `sessionId` 用于控制回放，状态变化时会再次发送 `replay` 事件。
+pub async fn replay_sse_handler(
+    State(state): State<Arc<SseState>>,
+    Path(track_id): Path<String>,
+    ApiQuery(query): ApiQuery<ReplayQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let id = parse_object_id(&track_id)?;
+    let speed = query.speed.unwrap_or(1.0);
+    validate_speed(speed)?;
+    let track = state
+        .track_service
+        .get(&id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("航迹不存在: {}", track_id)))?;
+
+    let flight = match track.flight_id {
+        Some(flight_id) => state.flight_service.get(&flight_id.to_hex()).await?,
+        None => state.flight_service.find_by_track(&id).await?,
+    };
+    let flight_id = flight.as_ref().map(|flight| flight.id).or(track.flight_id);
+    let drone_id = track.drone_id.clone().or_else(|| flight.as_ref().and_then(|flight| flight.drone_id.clone()));
+    let mut segments = match flight_id {
+        Some(flight_id) => {
+            let first_segment_id = flight.as_ref().map(|flight| flight.track_id);
+            state.track_service.list_segments(&flight_id, first_segment_id).await?
+        }
+        None => Vec::new(),
+    };
+    if segments.is_empty() {
+        segments.push(track);
+    }
+    let start_time = segments.iter().map(|segment| segment.start_time).min();
+    let end_time = segments.iter().map(|segment| segment.last_update).max();
+    let samples = match flight_id {
+        Some(flight_id) => state.flight_service.list_samples(&flight_id, start_time, end_time).await?,
+        None => Vec::new(),
+    };
+
+    let timeline = Timeline::build(segments, samples);
+    let position_ms = query.position_ms.unwrap_or(0).clamp(0, timeline.duration_ms);
+    let (session_id, commands) = state.replays.register();
+    info!("开始回放航迹 {}，会话 {}，{} 条数据", track_id, session_id, timeline.frames.len());
+    let player = Player {
+        session_id,
+        track_id,
+        drone_id,
+        cursor: timeline.index_at(position_ms),
+        timeline,
+        speed,
+        paused: query.paused,
+        position_ms,
+        anchored_at: Instant::now(),
+    };
+
+    let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+    tokio::spawn(run_replay(player, commands, tx, state.clone()));
+    Ok(Sse::new(until_shutdown(ReceiverStream::new(rx), state.shutdown.clone())).keep_alive(KeepAlive::default()))
+}
+
+/// 控制回放：暂停、继续、跳转和调整速度，会话不在本实例时返回404（见 [`ReplayRegistry`]）
+pub async fn replay_control_handler(
+    State(state): State<Arc<SseState>>,
+    Path(session_id): Path<String>,
+    ApiJson(command): ApiJson<ReplayCommand>,
+) -> Result<StatusCode, ApiError> {
+    match &command {
+        ReplayCommand::Speed { speed } => validate_speed(*speed)?,
+        ReplayCommand::Seek { position_ms } if *position_ms < 0 => {
+            return Err(ApiError::BadRequest("positionMs 不能为负数".to_string()));
+        }
+        _ => {}
+    }
+    let sender = state
+        .replays
+        .sender(&session_id)
+        .ok_or_else(|| ApiError::NotFound(format!("回放会话不存在: {}", session_id)))?;
+    sender
+        .send(command)
+        .await
+        .map_err(|_| ApiError::NotFound(format!("回放会话已结束: {}", session_id)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn validate_speed(speed: f64) -> Result<(), ApiError> {
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(ApiError::BadRequest(format!("speed 必须在{}到{}之间", MIN_SPEED, MAX_SPEED)));
+    }
+    Ok(())
+}
+
+/// 按记录时间推送数据，直到客户端断开；回放结束后保持连接，客户端仍可跳转
+async fn run_replay(
+    mut player: Player,
+    mut commands: mpsc::Receiver<ReplayCommand>,
+    events: mpsc::Sender<Result<Event, Infallible>>,
+    state: Arc<SseState>,
+) {
+    let mut connected = events.send(Ok(player.status_event())).await.is_ok();
+    while connected {
+        let delay = player.next_delay();
+        tokio::select! {
+            _ = sleep(delay.unwrap_or_default()), if delay.is_some() => {
+                if let Some(event) = player.next_event() {
+                    connected = events.send(Ok(event)).await.is_ok();
+                }
+                if connected && player.state() == PlaybackState::Ended {
+                    connected = events.send(Ok(player.status_event())).await.is_ok();
+                }
+            }
+            command = commands.recv() => {
+                let Some(command) = command else { break };
+                player.apply(command);
+                connected = events.send(Ok(player.status_event())).await.is_ok();
+            }
+            _ = events.closed() => connected = false,
+        }
+    }
+    state.replays.remove(&player.session_id);
+    info!("回放会话 {} 已结束", player.session_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::DateTime;
+
+    fn segment(start_ms: i64, end_ms: i64, points: Vec<TrackPoint>) -> ShipTrack {
+        ShipTrack {
+            id: ObjectId::new(),
+            drone_id: Some("A1".to_string()),
+            flight_id: None,
+            segment_index: 0,
+            prev_segment_id: None,
+            size_bytes: 0,
+            start_time: DateTime::from_millis(start_ms),
+            last_update: DateTime::from_millis(end_ms),
+            total_points: points.len() as u32,
+            coordinates: points,
+            path: None,
+            last_position: None,
+        }
+    }
+
+    fn at(ms: i64) -> TrackPoint {
+        TrackPoint { timestamp: Some(DateTime::from_millis(ms)), ..TrackPoint::from([120.0, 30.0]) }
+    }
+
+    fn locations(timeline: &Timeline) -> Vec<(ObjectId, i64)> {
+        timeline
+            .frames
+            .iter()
+            .filter_map(|frame| match frame.frame {
+                Frame::Location(segment_id, _) => Some((segment_id, frame.offset_ms)),
+                Frame::Flight(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chains_segments_in_time_order() {
+        let first = segment(1_000, 3_000, vec![at(1_000), at(3_000)]);
+        let second = segment(10_000, 12_000, vec![at(10_000), at(12_000)]);
+        let ids = (first.id, second.id);
+        let timeline = Timeline::build(vec![first, second], Vec::new());
+        assert_eq!(timeline.duration_ms, 11_000);
+        assert_eq!(
+            locations(&timeline),
+            vec![(ids.0, 0), (ids.0, 2_000), (ids.1, 9_000), (ids.1, 11_000)]
+        );
+        assert_eq!(timeline.index_at(5_000), 2);
+    }
+
+    #[test]
+    fn untimed_points_spread_within_their_segment() {
+        let untimed = vec![TrackPoint::from([120.0, 30.0]); 3];
+        let first = segment(0, 2_000, untimed.clone());
+        let second = segment(5_000, 5_000, untimed[..1].to_vec());
+        let timeline = Timeline::build(vec![first, second], Vec::new());
+        let offsets: Vec<i64> = locations(&timeline).into_iter().map(|(_, offset)| offset).collect();
+        assert_eq!(offsets, vec![0, 1_000, 2_000, 5_000]);
+    }
+}
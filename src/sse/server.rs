@@ -1,63 +1,216 @@
+use std::collections::HashSet;
 use std::sync::Arc;
-use std::time::Duration;
 use axum::{
-    extract::State,
+    extract::{Path, State},
+    http::{header::HeaderName, HeaderMap},
     response::Sse,
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use axum::response::sse::{Event, KeepAlive};
-use futures_util::stream::{self, Stream};
-use log::{error, info};
-use tokio::sync::broadcast;
-use tokio_stream::wrappers::BroadcastStream;
-use tokio_stream::StreamExt;
-use tower_http::cors::CorsLayer;
+use futures_util::stream::{self, Stream, StreamExt};
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
 
+use crate::api::error::ApiQuery;
+use crate::live::{
+    ClientHandle, ClientKind, DroneStateDto, LastKnownState, LiveClients, LocationHistory, ResyncEventDto, Resume,
+    SnapshotEventDto,
+};
+use crate::mqtt::{LiveEvent, LiveKind};
+use crate::server::AppState;
+use crate::service::flight_service::FlightService;
+use crate::service::ship_track_service::ShipTrackService;
+use super::replay::{replay_control_handler, replay_sse_handler, ReplayRegistry};
+
+/// 浏览器的EventSource断线重连时带上的最后一个事件ID
+const LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
 
 pub struct SseState {
-    pub location_tx: Arc<broadcast::Sender<String>>,
+    pub location_tx: Arc<broadcast::Sender<LiveEvent>>,
+    /// 用于回放的航迹和flight数据
+    pub track_service: Arc<ShipTrackService>,
+    pub flight_service: Arc<FlightService>,
+    pub replays: ReplayRegistry,
+    /// 连接建立和重新同步时发送的最近位置
+    pub last_known: Arc<LastKnownState>,
+    /// 断线重连时补发的位置事件
+    pub history: Arc<LocationHistory>,
+    pub clients: Arc<LiveClients>,
+    pub shutdown: CancellationToken,
 }
 
-pub async fn start_sse_server(
-    location_broadcaster: Arc<broadcast::Sender<String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let state = SseState {
-        location_tx: location_broadcaster,
-    };
-
-    let app = Router::new()
+/// SSE路由：实时位置和航迹回放
+pub fn routes() -> Router<AppState> {
+    Router::new()
         .route("/sse/location", get(location_sse_handler))
-      
-        .layer(CorsLayer::permissive())
-        .with_state(Arc::new(state));
+        .route("/sse/location/{drone_id}", get(drone_location_sse_handler))
+        .route("/sse/replay/{track_id}", get(replay_sse_handler))
+        .route("/sse/replay/sessions/{session_id}", post(replay_control_handler))
+}
+
+/// 收到关闭信号时结束事件流，否则优雅关闭会一直等待SSE长连接
+pub fn until_shutdown<S: Stream>(stream: S, shutdown: CancellationToken) -> impl Stream<Item = S::Item> {
+    stream.take_until(shutdown.cancelled_owned())
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await?;
-    info!("SSE服务器启动在端口3001");
-    info!("位置更新SSE端点: http://localhost:3001/sse/location");
-    axum::serve(listener, app).await?;
-    Ok(())
+#[derive(Debug, Deserialize)]
+pub struct LocationStreamQuery {
+    /// 逗号分隔的无人机序列号，不指定时推送全部无人机
+    pub drones: Option<String>,
 }
 
+/// 推送位置事件，可通过 `drones` 参数只接收指定的无人机
 async fn location_sse_handler(
     State(state): State<Arc<SseState>>,
+    headers: HeaderMap,
+    ApiQuery(query): ApiQuery<LocationStreamQuery>,
 ) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
-    info!("新的SSE位置连接建立");
-    let rx = state.location_tx.subscribe();
-    let stream = BroadcastStream::new(rx)
-        .filter_map(|result| {
-            match result {
-                Ok(data) => {
-                    info!("向SSE客户端发送位置数据: {}", data);
-                    Some(Ok(Event::default().data(data)))
-                },
-                Err(e) => {
-                    error!("SSE位置广播错误: {}", e);
-                    None
-                }
-            }
-        });
+    let drones = query.drones.map(|drones| {
+        drones
+            .split(',')
+            .map(str::trim)
+            .filter(|drone| !drone.is_empty())
+            .map(str::to_string)
+            .collect()
+    });
+    location_stream(state, drones, last_event_id(&headers))
+}
+
+/// 只推送一架无人机的位置事件
+async fn drone_location_sse_handler(
+    State(state): State<Arc<SseState>>,
+    headers: HeaderMap,
+    Path(drone_id): Path<String>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    location_stream(state, Some(HashSet::from([drone_id])), last_event_id(&headers))
+}
+
+/// 浏览器断线重连时自动带上的 `Last-Event-ID`，不是合法字符串时返回空ID，按无法补发处理
+fn last_event_id(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(LAST_EVENT_ID)?;
+    Some(value.to_str().map(|id| id.trim().to_string()).unwrap_or_default())
+}
 
-    Sse::new(stream).keep_alive(KeepAlive::default())
+/// 一个SSE位置连接的状态
+struct LocationSubscriber {
+    rx: broadcast::Receiver<LiveEvent>,
+    drones: Option<HashSet<String>>,
+    state: Arc<SseState>,
+    client: ClientHandle,
+    /// 已发送给客户端的最大事件ID，ID不大于它的事件不再发送
+    last_id: u64,
 }
 
+impl LocationSubscriber {
+    /// 关注的无人机最近的位置
+    fn last_known(&self) -> Vec<DroneStateDto> {
+        self.state.last_known.snapshot(|kind, drone_id| {
+            kind == LiveKind::Location && self.drones.as_ref().is_none_or(|drones| drones.contains(drone_id))
+        })
+    }
+}
+
+/// 位置事件流，事件类型为 `location`，事件ID见 [`LocationHistory`]；`drones` 为 `None` 时不过滤
+///
+/// 连接建立后首先发送 `snapshot` 事件（[`SnapshotEventDto`]），包含关注的无人机最近的位置。
+/// 带 `Last-Event-ID` 重连时改为补发该ID之后的位置事件，事件已被淘汰时发送 `resync` 事件
+/// （[`ResyncEventDto`]）。客户端处理过慢丢失消息时同样发送 `resync` 事件，连接保持不变。
+/// `snapshot` 和 `resync` 事件的ID为生成时最后分配的ID，重连时从这里继续。
+fn location_stream(
+    state: Arc<SseState>,
+    drones: Option<HashSet<String>>,
+    last_event_id: Option<String>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let client = state.clients.register(ClientKind::Sse);
+    info!(
+        "新的SSE位置连接建立: 客户端{}，无人机: {:?}，Last-Event-ID: {:?}",
+        client.id(),
+        drones,
+        last_event_id
+    );
+    let shutdown = state.shutdown.clone();
+    let subscription = state.history.subscribe(&state.location_tx, last_event_id.as_deref(), |drone_id| {
+        drones.as_ref().is_none_or(|drones| drones.contains(drone_id))
+    });
+    let subscriber = LocationSubscriber {
+        rx: subscription.rx,
+        drones,
+        state,
+        client,
+        last_id: subscription.latest_id,
+    };
+
+    // 订阅广播后再读取缓存，两者之间的事件可能重复，但不会丢失
+    let initial: Vec<Result<Event, axum::Error>> = match subscription.resume {
+        Resume::Fresh => {
+            let snapshot = SnapshotEventDto::new(subscriber.last_known());
+            vec![Event::default().id(subscriber.state.history.event_id(subscriber.last_id)).event("snapshot").json_data(snapshot)]
+        }
+        Resume::Replay(events) => {
+            info!("向SSE客户端{}补发{}条位置事件", subscriber.client.id(), events.len());
+            events.iter().map(|event| Ok(location_event(&subscriber.state.history, event))).collect()
+        }
+        Resume::Gap => {
+            warn!("SSE客户端{}的 Last-Event-ID 之后的事件已被淘汰，发送重新同步事件", subscriber.client.id());
+            let resync = ResyncEventDto::evicted("location", subscriber.last_known());
+            vec![Event::default().id(subscriber.state.history.event_id(subscriber.last_id)).event("resync").json_data(resync)]
+        }
+    };
+    let updates = stream::unfold(subscriber, |mut subscriber| async move {
+        let event = next_location_event(&mut subscriber).await?;
+        Some((event, subscriber))
+    });
+    let stream = stream::iter(initial).chain(updates);
+
+    Sse::new(until_shutdown(stream, shutdown)).keep_alive(KeepAlive::default())
+}
+
+fn location_event(history: &LocationHistory, event: &LiveEvent) -> Event {
+    Event::default()
+        .id(history.event_id(event.id))
+        .event("location")
+        .data(event.payload.as_ref())
+}
+
+/// 下一条要推送的事件，广播通道关闭时返回 `None`
+async fn next_location_event(subscriber: &mut LocationSubscriber) -> Option<Result<Event, axum::Error>> {
+    loop {
+        match subscriber.rx.recv().await {
+            Ok(event) => {
+                if event.id <= subscriber.last_id
+                    || subscriber.drones.as_ref().is_some_and(|drones| !drones.contains(event.drone_id.as_ref()))
+                {
+                    continue;
+                }
+                info!("向SSE客户端发送位置数据: {}", event.payload);
+                subscriber.last_id = event.id;
+                return Some(Ok(location_event(&subscriber.state.history, &event)));
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                let lag_count = subscriber.client.record_lag(skipped);
+                warn!(
+                    "SSE客户端{}处理过慢，丢弃了{}条位置消息（累计{}次），发送重新同步事件",
+                    subscriber.client.id(),
+                    skipped,
+                    lag_count
+                );
+                // 重新同步事件包含最近的位置，通道中比它旧的事件不再发送
+                subscriber.last_id = subscriber.state.history.latest_id();
+                let resync = ResyncEventDto::lagged("location", skipped, lag_count, subscriber.last_known());
+                return Some(
+                    Event::default()
+                        .id(subscriber.state.history.event_id(subscriber.last_id))
+                        .event("resync")
+                        .json_data(resync),
+                );
+            }
+            Err(RecvError::Closed) => {
+                error!("SSE位置广播通道已关闭");
+                return None;
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use prost::Message;
+use serde_json::Value;
+
+use crate::model::flight::FlightDto;
+use crate::model::geo::validate_position;
+use crate::model::ship_track::TrackPoint;
+use crate::telemetry::proto::{FlightState, LocationBatch, PROTOBUF_VERSIONS};
+use crate::telemetry::schema::{decode_location, decode_state, resolve_version, SchemaError};
+
+/// 遥测消息的编码格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadCodec {
+    Json,
+    Cbor,
+    MessagePack,
+    /// 消息结构见 `proto/telemetry.proto`
+    Protobuf,
+}
+
+impl PayloadCodec {
+    /// 从主题后缀中的一层解析编码，如 `drone/A1/location/cbor`
+    pub fn from_suffix_segment(segment: &str) -> Option<Self> {
+        match segment {
+            "json" => Some(PayloadCodec::Json),
+            "cbor" => Some(PayloadCodec::Cbor),
+            "msgpack" => Some(PayloadCodec::MessagePack),
+            "pb" | "protobuf" => Some(PayloadCodec::Protobuf),
+            _ => None,
+        }
+    }
+
+    /// 从MQTT v5的content-type解析编码，忽略 `;` 之后的参数
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/json" | "text/json" => Some(PayloadCodec::Json),
+            "application/cbor" => Some(PayloadCodec::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(PayloadCodec::MessagePack)
+            }
+            "application/protobuf" | "application/x-protobuf" | "application/vnd.google.protobuf" => {
+                Some(PayloadCodec::Protobuf)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PayloadCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PayloadCodec::Json => "JSON",
+            PayloadCodec::Cbor => "CBOR",
+            PayloadCodec::MessagePack => "MessagePack",
+            PayloadCodec::Protobuf => "Protobuf",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 主题后缀中声明的编码和版本，如 `v2/cbor`，各层顺序不限
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PayloadFormat {
+    pub codec: Option<PayloadCodec>,
+    pub version: Option<u32>,
+}
+
+impl PayloadFormat {
+    /// 解析主题后缀，没有后缀时两者都为 `None`
+    pub fn from_suffix(suffix: Option<&str>) -> Result<Self, SchemaError> {
+        let raw = suffix.unwrap_or_default();
+        let invalid = || SchemaError::InvalidSuffix(raw.to_string());
+        let mut format = PayloadFormat::default();
+        for segment in raw.split('/').filter(|s| !s.is_empty()) {
+            if let Some(version) = parse_version_segment(segment) {
+                if format.version.replace(version).is_some() {
+                    return Err(invalid());
+                }
+            } else if let Some(codec) = PayloadCodec::from_suffix_segment(segment) {
+                if format.codec.replace(codec).is_some() {
+                    return Err(invalid());
+                }
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(format)
+    }
+
+    /// 确定最终使用的编码：主题后缀与content-type都声明时必须一致，都未声明时按JSON处理
+    fn codec(&self, content_type: Option<&str>) -> Result<PayloadCodec, SchemaError> {
+        let declared = match content_type {
+            Some(content_type) => Some(
+                PayloadCodec::from_content_type(content_type)
+                    .ok_or_else(|| SchemaError::UnsupportedContentType(content_type.to_string()))?,
+            ),
+            None => None,
+        };
+        match (self.codec, declared) {
+            (Some(topic), Some(content_type)) if topic != content_type => {
+                Err(SchemaError::CodecMismatch { topic, content_type })
+            }
+            (Some(codec), _) | (None, Some(codec)) => Ok(codec),
+            (None, None) => Ok(PayloadCodec::Json),
+        }
+    }
+}
+
+fn parse_version_segment(segment: &str) -> Option<u32> {
+    segment.strip_prefix('v')?.parse().ok()
+}
+
+/// 按主题后缀和content-type选择编码，解码位置消息为航迹点列表
+pub fn decode_location_payload(
+    payload: &[u8],
+    suffix: Option<&str>,
+    content_type: Option<&str>,
+) -> Result<Vec<TrackPoint>, SchemaError> {
+    let format = PayloadFormat::from_suffix(suffix)?;
+    let points = match format.codec(content_type)? {
+        PayloadCodec::Protobuf => {
+            let batch = decode_protobuf::<LocationBatch>(payload)?;
+            resolve_version("位置", format.version, protobuf_version(batch.version), PROTOBUF_VERSIONS)?;
+            batch.into()
+        }
+        codec => decode_location(decode_value(codec, payload)?, format.version)?,
+    };
+    // 超出范围的坐标无法写入 2dsphere 索引，会使整批写入失败
+    for point in &points {
+        validate_position(&[point.longitude, point.latitude]).map_err(SchemaError::InvalidCoordinate)?;
+    }
+    Ok(points)
+}
+
+/// 按主题后缀和content-type选择编码，解码状态消息为 [`FlightDto`]
+pub fn decode_state_payload(
+    payload: &[u8],
+    suffix: Option<&str>,
+    content_type: Option<&str>,
+) -> Result<FlightDto, SchemaError> {
+    let format = PayloadFormat::from_suffix(suffix)?;
+    match format.codec(content_type)? {
+        PayloadCodec::Protobuf => {
+            let state = decode_protobuf::<FlightState>(payload)?;
+            resolve_version("状态", format.version, protobuf_version(state.version), PROTOBUF_VERSIONS)?;
+            Ok(state.into())
+        }
+        codec => decode_state(decode_value(codec, payload)?, format.version),
+    }
+}
+
+/// 自描述格式（JSON、CBOR、MessagePack）先解码为通用的JSON值，再交给版本适配器
+fn decode_value(codec: PayloadCodec, payload: &[u8]) -> Result<Value, SchemaError> {
+    let decode_error = |message: String| SchemaError::Decode { codec, message };
+    match codec {
+        PayloadCodec::Json => serde_json::from_slice(payload).map_err(|e| decode_error(e.to_string())),
+        PayloadCodec::Cbor => ciborium::from_reader(payload).map_err(|e| decode_error(e.to_string())),
+        PayloadCodec::MessagePack => rmp_serde::from_slice(payload).map_err(|e| decode_error(e.to_string())),
+        PayloadCodec::Protobuf => unreachable!("Protobuf消息不经过通用JSON值解码"),
+    }
+}
+
+fn decode_protobuf<T: Message + Default>(payload: &[u8]) -> Result<T, SchemaError> {
+    T::decode(payload).map_err(|e| SchemaError::Decode {
+        codec: PayloadCodec::Protobuf,
+        message: e.to_string(),
+    })
+}
+
+/// proto3中未设置的 `version` 为0，视为未声明
+fn protobuf_version(version: u32) -> Option<u32> {
+    (version != 0).then_some(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::proto::LocationPoint;
+    use serde_json::json;
+
+    #[test]
+    fn suffix_segments_in_any_order() {
+        let expected = PayloadFormat { codec: Some(PayloadCodec::Cbor), version: Some(2) };
+        assert_eq!(PayloadFormat::from_suffix(Some("v2/cbor")), Ok(expected));
+        assert_eq!(PayloadFormat::from_suffix(Some("cbor/v2")), Ok(expected));
+        assert_eq!(PayloadFormat::from_suffix(None), Ok(PayloadFormat::default()));
+        assert_eq!(
+            PayloadFormat::from_suffix(Some("pb")),
+            Ok(PayloadFormat { codec: Some(PayloadCodec::Protobuf), version: None })
+        );
+    }
+
+    #[test]
+    fn invalid_suffixes_are_rejected() {
+        for suffix in ["xml", "v2/v3", "cbor/json", "v", "vx"] {
+            assert_eq!(
+                PayloadFormat::from_suffix(Some(suffix)),
+                Err(SchemaError::InvalidSuffix(suffix.to_string())),
+                "{}",
+                suffix
+            );
+        }
+    }
+
+    #[test]
+    fn content_type_selects_codec() {
+        assert_eq!(PayloadCodec::from_content_type("application/cbor"), Some(PayloadCodec::Cbor));
+        assert_eq!(
+            PayloadCodec::from_content_type("Application/JSON; charset=utf-8"),
+            Some(PayloadCodec::Json)
+        );
+        assert_eq!(PayloadCodec::from_content_type("application/x-msgpack"), Some(PayloadCodec::MessagePack));
+        assert_eq!(PayloadCodec::from_content_type("text/plain"), None);
+
+        let none = PayloadFormat::default();
+        assert_eq!(none.codec(None), Ok(PayloadCodec::Json));
+        assert_eq!(none.codec(Some("application/cbor")), Ok(PayloadCodec::Cbor));
+        assert_eq!(
+            none.codec(Some("text/plain")),
+            Err(SchemaError::UnsupportedContentType("text/plain".to_string()))
+        );
+        let cbor = PayloadFormat { codec: Some(PayloadCodec::Cbor), version: None };
+        assert_eq!(cbor.codec(Some("application/cbor")), Ok(PayloadCodec::Cbor));
+        assert_eq!(
+            cbor.codec(Some("application/json")),
+            Err(SchemaError::CodecMismatch { topic: PayloadCodec::Cbor, content_type: PayloadCodec::Json })
+        );
+    }
+
+    #[test]
+    fn self_describing_codecs_decode_the_same_points() {
+        let value = json!({"version": 2, "points": [{"longitude": 120.1, "latitude": 30.2}]});
+        let json = serde_json::to_vec(&value).unwrap();
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&value, &mut cbor).unwrap();
+        let msgpack = rmp_serde::to_vec_named(&value).unwrap();
+
+        let expected = vec![TrackPoint::from([120.1, 30.2])];
+        assert_eq!(decode_location_payload(&json, None, None), Ok(expected.clone()));
+        assert_eq!(decode_location_payload(&cbor, Some("cbor"), None), Ok(expected.clone()));
+        assert_eq!(decode_location_payload(&msgpack, None, Some("application/msgpack")), Ok(expected));
+    }
+
+    #[test]
+    fn protobuf_location_batch() {
+        let batch = LocationBatch {
+            version: 0,
+            points: vec![LocationPoint {
+                longitude: 120.1,
+                latitude: 30.2,
+                altitude: Some(50.0),
+                timestamp_ms: Some(1_718_000_000_000),
+                ground_speed: None,
+                heading: None,
+                accuracy: None,
+            }],
+        };
+        let points = decode_location_payload(&batch.encode_to_vec(), Some("pb"), None).unwrap();
+        assert_eq!(points[0].altitude, Some(50.0));
+        assert_eq!(points[0].timestamp, Some(bson::DateTime::from_millis(1_718_000_000_000)));
+
+        let unsupported = LocationBatch { version: 2, ..batch };
+        assert!(matches!(
+            decode_location_payload(&unsupported.encode_to_vec(), Some("pb"), None),
+            Err(SchemaError::UnsupportedVersion { version: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn out_of_range_coordinates_are_rejected() {
+        let payload = serde_json::to_vec(&json!([[181.0, 30.0]])).unwrap();
+        assert!(matches!(
+            decode_location_payload(&payload, None, None),
+            Err(SchemaError::InvalidCoordinate(_))
+        ));
+    }
+}
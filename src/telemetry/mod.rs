@@ -0,0 +1,5 @@
+pub mod codec;
+pub mod proto;
+pub mod schema;
+
+pub use codec::*;
@@ -0,0 +1,90 @@
+//! `proto/telemetry.proto` 对应的Protobuf消息，字段编号必须与proto文件一致。
+
+use chrono::{TimeZone, Utc};
+
+use crate::model::flight::FlightDto;
+use crate::model::ship_track::TrackPoint;
+
+/// 当前支持的Protobuf消息版本
+pub const PROTOBUF_VERSIONS: &[u32] = &[1];
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LocationPoint {
+    #[prost(double, tag = "1")]
+    pub longitude: f64,
+    #[prost(double, tag = "2")]
+    pub latitude: f64,
+    #[prost(double, optional, tag = "3")]
+    pub altitude: Option<f64>,
+    /// 设备采样时间（Unix毫秒）
+    #[prost(int64, optional, tag = "4")]
+    pub timestamp_ms: Option<i64>,
+    #[prost(double, optional, tag = "5")]
+    pub ground_speed: Option<f64>,
+    #[prost(double, optional, tag = "6")]
+    pub heading: Option<f64>,
+    #[prost(double, optional, tag = "7")]
+    pub accuracy: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LocationBatch {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    #[prost(message, repeated, tag = "2")]
+    pub points: Vec<LocationPoint>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightState {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    #[prost(double, tag = "2")]
+    pub battery_capacity: f64,
+    #[prost(double, tag = "3")]
+    pub estimated_remaining_usage_time: f64,
+    #[prost(double, tag = "4")]
+    pub cabin_temperature: f64,
+    #[prost(double, tag = "5")]
+    pub aircraft_altitude: f64,
+    #[prost(double, tag = "6")]
+    pub distance_to_fan: f64,
+    #[prost(double, tag = "7")]
+    pub air_pressure: f64,
+}
+
+impl From<LocationPoint> for TrackPoint {
+    fn from(point: LocationPoint) -> Self {
+        TrackPoint {
+            longitude: point.longitude,
+            latitude: point.latitude,
+            altitude: point.altitude,
+            timestamp: point
+                .timestamp_ms
+                .and_then(|ms| Utc.timestamp_millis_opt(ms).single())
+                .map(Into::into),
+            ground_speed: point.ground_speed,
+            heading: point.heading,
+            accuracy: point.accuracy,
+        }
+    }
+}
+
+impl From<LocationBatch> for Vec<TrackPoint> {
+    fn from(batch: LocationBatch) -> Self {
+        batch.points.into_iter().map(Into::into).collect()
+    }
+}
+
+impl From<FlightState> for FlightDto {
+    fn from(state: FlightState) -> Self {
+        FlightDto {
+            battery_capacity: state.battery_capacity,
+            estimated_remaining_usage_time: state.estimated_remaining_usage_time,
+            cabin_temperature: state.cabin_temperature,
+            aircraft_altitude: state.aircraft_altitude,
+            distance_to_fan: state.distance_to_fan,
+            air_pressure: state.air_pressure,
+        }
+    }
+}
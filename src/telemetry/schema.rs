@@ -0,0 +1,340 @@
+use std::fmt;
+use chrono::{TimeZone, Utc};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::model::flight::FlightDto;
+use crate::model::ship_track::TrackPoint;
+use crate::telemetry::codec::PayloadCodec;
+
+/// 当前支持的位置消息版本
+pub const LOCATION_VERSIONS: &[u32] = &[1, 2, 3];
+/// 当前支持的状态消息版本
+pub const STATE_VERSIONS: &[u32] = &[1, 2];
+
+/// 遥测消息无法按任何已支持的版本解析
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    /// 消息或主题声明的版本不受支持
+    UnsupportedVersion { kind: &'static str, version: u32, supported: &'static [u32] },
+    /// 消息内容中的 `version` 不是有效的版本号
+    InvalidVersion { kind: &'static str, version: String },
+    /// 主题后缀和消息内容声明的版本不一致
+    VersionMismatch { topic: u32, payload: u32 },
+    /// 主题后缀无法识别
+    InvalidSuffix(String),
+    /// 消息内容不符合对应版本的格式
+    Invalid { kind: &'static str, version: u32, message: String },
+    /// 消息内容无法按所选编码解码
+    Decode { codec: PayloadCodec, message: String },
+    /// MQTT v5 content-type 无法识别
+    UnsupportedContentType(String),
+    /// 主题后缀和content-type声明的编码不一致
+    CodecMismatch { topic: PayloadCodec, content_type: PayloadCodec },
+    /// 航迹点的经纬度超出WGS84范围
+    InvalidCoordinate(String),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::UnsupportedVersion { kind, version, supported } => {
+                let supported: Vec<String> = supported.iter().map(|v| format!("v{}", v)).collect();
+                write!(f, "不支持的{}消息版本 v{}，支持的版本: {}", kind, version, supported.join(", "))
+            }
+            SchemaError::InvalidVersion { kind, version } => {
+                write!(f, "{}消息的版本号无效: {}，应为正整数", kind, version)
+            }
+            SchemaError::VersionMismatch { topic, payload } => {
+                write!(f, "主题声明的版本 v{} 与消息内容中的版本 v{} 不一致", topic, payload)
+            }
+            SchemaError::InvalidSuffix(suffix) => write!(f, "无法识别的主题后缀: \"{}\"", suffix),
+            SchemaError::Invalid { kind, version, message } => {
+                write!(f, "{}消息不符合 v{} 格式: {}", kind, version, message)
+            }
+            SchemaError::Decode { codec, message } => write!(f, "{}解码失败: {}", codec, message),
+            SchemaError::UnsupportedContentType(content_type) => {
+                write!(f, "不支持的content-type: \"{}\"", content_type)
+            }
+            SchemaError::CodecMismatch { topic, content_type } => {
+                write!(f, "主题声明的编码 {} 与content-type声明的编码 {} 不一致", topic, content_type)
+            }
+            SchemaError::InvalidCoordinate(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// v1 位置消息：`[[经度, 纬度], ...]`
+type LocationV1 = Vec<[f64; 2]>;
+
+/// v2 位置消息：`{"version": 2, "points": [{"longitude": .., "latitude": ..}]}`
+#[derive(Debug, Deserialize)]
+struct LocationV2 {
+    points: Vec<LocationPointV2>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LocationPointV2 {
+    longitude: f64,
+    latitude: f64,
+}
+
+impl From<LocationV2> for LocationV1 {
+    fn from(v2: LocationV2) -> Self {
+        v2.points.into_iter().map(|p| [p.longitude, p.latitude]).collect()
+    }
+}
+
+/// v3 位置消息：在v2基础上每个点带设备时间戳，并可携带海拔、地速、航向和定位精度
+///
+/// `{"version": 3, "points": [{"longitude": .., "latitude": .., "timestamp": 1718000000000,
+/// "altitude": .., "groundSpeed": .., "heading": .., "accuracy": ..}]}`
+#[derive(Debug, Deserialize)]
+struct LocationV3 {
+    points: Vec<LocationPointV3>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LocationPointV3 {
+    longitude: f64,
+    latitude: f64,
+    timestamp: DeviceTimestamp,
+    altitude: Option<f64>,
+    ground_speed: Option<f64>,
+    heading: Option<f64>,
+    accuracy: Option<f64>,
+}
+
+/// 设备时间戳：Unix毫秒或RFC 3339字符串
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DeviceTimestamp {
+    Millis(i64),
+    Text(chrono::DateTime<Utc>),
+}
+
+impl LocationPointV3 {
+    fn into_track_point(self) -> Result<TrackPoint, String> {
+        let timestamp = match self.timestamp {
+            DeviceTimestamp::Millis(ms) => Utc
+                .timestamp_millis_opt(ms)
+                .single()
+                .ok_or_else(|| format!("时间戳超出范围: {}", ms))?,
+            DeviceTimestamp::Text(time) => time,
+        };
+        Ok(TrackPoint {
+            longitude: self.longitude,
+            latitude: self.latitude,
+            altitude: self.altitude,
+            timestamp: Some(timestamp.into()),
+            ground_speed: self.ground_speed,
+            heading: self.heading,
+            accuracy: self.accuracy,
+        })
+    }
+}
+
+/// v1 状态消息即 [`FlightDto`]
+type StateV1 = FlightDto;
+
+/// v2 状态消息：`{"version": 2, ...}`，驼峰命名，电池信息嵌套在 `battery` 中
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StateV2 {
+    battery: BatteryV2,
+    cabin_temperature: f64,
+    altitude: f64,
+    distance_to_fan: f64,
+    air_pressure: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatteryV2 {
+    capacity: f64,
+    /// 预计剩余可用时间
+    remaining_time: f64,
+}
+
+impl From<StateV2> for StateV1 {
+    fn from(v2: StateV2) -> Self {
+        FlightDto {
+            battery_capacity: v2.battery.capacity,
+            estimated_remaining_usage_time: v2.battery.remaining_time,
+            cabin_temperature: v2.cabin_temperature,
+            aircraft_altitude: v2.altitude,
+            distance_to_fan: v2.distance_to_fan,
+            air_pressure: v2.air_pressure,
+        }
+    }
+}
+
+/// 解码位置消息为航迹点列表，v1、v2 的航迹点只有经纬度
+pub fn decode_location(value: Value, topic_version: Option<u32>) -> Result<Vec<TrackPoint>, SchemaError> {
+    const KIND: &str = "位置";
+    // 裸数组是未带版本号的v1格式
+    let payload_version = match &value {
+        Value::Array(_) => Some(1),
+        _ => payload_version(KIND, &value)?,
+    };
+    match resolve_version(KIND, topic_version, payload_version, LOCATION_VERSIONS)? {
+        1 => parse::<LocationV1>(KIND, 1, value).map(track_points),
+        2 => parse::<LocationV2>(KIND, 2, value).map(|v2| track_points(v2.into())),
+        3 => parse::<LocationV3>(KIND, 3, value)?
+            .points
+            .into_iter()
+            .map(LocationPointV3::into_track_point)
+            .collect::<Result<_, _>>()
+            .map_err(|message| SchemaError::Invalid { kind: KIND, version: 3, message }),
+        _ => unreachable!("resolve_version只返回支持的版本"),
+    }
+}
+
+fn track_points(v1: LocationV1) -> Vec<TrackPoint> {
+    v1.into_iter().map(TrackPoint::from).collect()
+}
+
+/// 解码状态消息为当前的 [`FlightDto`]
+pub fn decode_state(value: Value, topic_version: Option<u32>) -> Result<FlightDto, SchemaError> {
+    const KIND: &str = "状态";
+    let payload_version = payload_version(KIND, &value)?;
+    match resolve_version(KIND, topic_version, payload_version, STATE_VERSIONS)? {
+        1 => parse::<StateV1>(KIND, 1, value),
+        2 => parse::<StateV2>(KIND, 2, value).map(Into::into),
+        _ => unreachable!("resolve_version只返回支持的版本"),
+    }
+}
+
+/// 读取消息中的 `version` 字段，没有该字段时返回 `None`
+fn payload_version(kind: &'static str, value: &Value) -> Result<Option<u32>, SchemaError> {
+    let Some(version) = value.get("version") else {
+        return Ok(None);
+    };
+    version
+        .as_u64()
+        .and_then(|v| u32::try_from(v).ok())
+        .map(Some)
+        .ok_or_else(|| SchemaError::InvalidVersion { kind, version: version.to_string() })
+}
+
+/// 确定消息版本：主题与消息内容都声明时必须一致，都未声明时按v1处理
+pub(crate) fn resolve_version(
+    kind: &'static str,
+    topic_version: Option<u32>,
+    payload_version: Option<u32>,
+    supported: &'static [u32],
+) -> Result<u32, SchemaError> {
+    let version = match (topic_version, payload_version) {
+        (Some(topic), Some(payload)) if topic != payload => {
+            return Err(SchemaError::VersionMismatch { topic, payload });
+        }
+        (Some(version), _) | (None, Some(version)) => version,
+        (None, None) => 1,
+    };
+    if !supported.contains(&version) {
+        return Err(SchemaError::UnsupportedVersion { kind, version, supported });
+    }
+    Ok(version)
+}
+
+fn parse<T: DeserializeOwned>(kind: &'static str, version: u32, value: Value) -> Result<T, SchemaError> {
+    serde_json::from_value(value).map_err(|e| SchemaError::Invalid {
+        kind,
+        version,
+        message: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn location_versions_decode_to_same_points() {
+        let v1 = decode_location(json!([[120.1, 30.2], [120.3, 30.4]]), None).unwrap();
+        let v2 = decode_location(
+            json!({"version": 2, "points": [{"longitude": 120.1, "latitude": 30.2}, {"longitude": 120.3, "latitude": 30.4}]}),
+            None,
+        )
+        .unwrap();
+        assert_eq!(v1, v2);
+        assert_eq!(v1[1], TrackPoint::from([120.3, 30.4]));
+    }
+
+    #[test]
+    fn location_v3_keeps_device_fields() {
+        let points = decode_location(
+            json!({"version": 3, "points": [
+                {"longitude": 120.1, "latitude": 30.2, "timestamp": 1718000000000i64, "altitude": 88.0, "groundSpeed": 5.5},
+                {"longitude": 120.2, "latitude": 30.3, "timestamp": "2024-06-10T06:13:20Z", "heading": 270.0},
+            ]}),
+            None,
+        )
+        .unwrap();
+        assert_eq!(points[0].timestamp, Some(bson::DateTime::from_millis(1_718_000_000_000)));
+        assert_eq!(points[0].altitude, Some(88.0));
+        assert_eq!(points[0].ground_speed, Some(5.5));
+        assert_eq!(points[1].timestamp, Some(bson::DateTime::from_millis(1_718_000_000_000)));
+        assert_eq!(points[1].heading, Some(270.0));
+    }
+
+    #[test]
+    fn location_v3_requires_timestamp() {
+        let err = decode_location(json!({"version": 3, "points": [{"longitude": 120.1, "latitude": 30.2}]}), None)
+            .unwrap_err();
+        assert!(matches!(err, SchemaError::Invalid { version: 3, .. }));
+    }
+
+    #[test]
+    fn state_v2_maps_to_v1() {
+        let v1 = decode_state(
+            json!({
+                "battery_capacity": 80.0, "estimated_remaining_usage_time": 1200.0, "cabin_temperature": 25.0,
+                "aircraft_altitude": 100.0, "distance_to_fan": 3.0, "air_pressure": 1013.0,
+            }),
+            None,
+        )
+        .unwrap();
+        let v2 = decode_state(
+            json!({
+                "version": 2, "battery": {"capacity": 80.0, "remainingTime": 1200.0}, "cabinTemperature": 25.0,
+                "altitude": 100.0, "distanceToFan": 3.0, "airPressure": 1013.0,
+            }),
+            None,
+        )
+        .unwrap();
+        assert_eq!(serde_json::to_value(v1).unwrap(), serde_json::to_value(v2).unwrap());
+    }
+
+    #[test]
+    fn topic_and_payload_versions_must_agree() {
+        assert_eq!(resolve_version("位置", None, None, LOCATION_VERSIONS), Ok(1));
+        assert_eq!(resolve_version("位置", Some(3), None, LOCATION_VERSIONS), Ok(3));
+        assert_eq!(resolve_version("位置", Some(2), Some(2), LOCATION_VERSIONS), Ok(2));
+        assert_eq!(
+            resolve_version("位置", Some(2), Some(3), LOCATION_VERSIONS),
+            Err(SchemaError::VersionMismatch { topic: 2, payload: 3 })
+        );
+        assert!(matches!(
+            resolve_version("状态", None, Some(3), STATE_VERSIONS),
+            Err(SchemaError::UnsupportedVersion { version: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_payload_version_is_rejected() {
+        for version in [json!(4294967297u64), json!("2"), json!(2.5), json!(-1), json!(null)] {
+            let err = decode_location(json!({"version": version, "points": []}), None).unwrap_err();
+            assert!(matches!(err, SchemaError::InvalidVersion { .. }), "version {}: {:?}", version, err);
+            let err = decode_state(json!({"version": version}), None).unwrap_err();
+            assert!(matches!(err, SchemaError::InvalidVersion { .. }), "version {}: {:?}", version, err);
+        }
+        // 超出u32的版本号不能截断成v1
+        assert!(decode_location(json!({"version": 4294967297u64, "points": []}), Some(1)).is_err());
+    }
+}
@@ -1,75 +1,227 @@
 use std::sync::Arc;
-use axum::extract::ws::{Message, WebSocket};
-use tokio::sync::broadcast;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
 use futures::{sink::SinkExt, stream::StreamExt};
-use log::info;
+use log::{info, warn};
+
+use crate::live::{ClientHandle, ClientKind, ResyncEventDto, SnapshotEventDto};
+use crate::model::drone::DroneResponseDto;
+use crate::mqtt::LiveEvent;
+
+use super::protocol::{Ack, Channel, ChannelEvent, ClientRequest, Subscriptions};
+use super::WsState;
+
+/// `list_drones` 最多返回的无人机数
+const MAX_LISTED_DRONES: i64 = 1000;
+
+/// 一轮循环的结果
+enum Step {
+    Send(String),
+    /// 对客户端请求的回复：确认，以及订阅后的最近状态
+    Reply(Vec<String>),
+    /// 处理过慢丢失了消息，发送重新同步事件
+    Resync { channel: Channel, skipped: u64 },
+    Skip,
+    Close,
+    /// 服务器关闭，发送关闭帧后断开
+    Shutdown,
+}
 
 /// 处理WebSocket连接
-pub async fn handle_websocket(socket: WebSocket, flight_broadcaster: Arc<broadcast::Sender<String>>) {
+///
+/// 连接建立后默认订阅全部无人机的flight状态，并首先收到一条 [`SnapshotEventDto`]，
+/// 包含已订阅无人机最近的状态。客户端可以通过 [`ClientRequest`] 按无人机和频道调整订阅，
+/// 每个请求都会收到一条确认，新的订阅同样先收到最近的状态。
+/// 已订阅的实时事件以 [`ChannelEvent`] 包装后推送。
+/// 客户端处理过慢丢失消息时不断开连接，而是发送 [`ResyncEventDto`]。
+pub async fn handle_websocket(socket: WebSocket, state: Arc<WsState>) {
     let (mut sender, mut receiver) = socket.split();
-    let mut flight_rx = flight_broadcaster.subscribe();
-    
-    info!("新的WebSocket连接已建立");
+    // 先订阅广播再读取缓存，两者之间的事件可能重复，但不会丢失
+    let mut flight_rx = state.flight_tx.subscribe();
+    let mut location_rx = state.location_tx.subscribe();
+    let mut alert_rx = state.alert_tx.subscribe();
+    let mut subscriptions = Subscriptions::default();
+    let client = state.clients.register(ClientKind::WebSocket);
 
-    // 创建一个任务来处理从广播通道接收消息并发送到WebSocket
-    let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = flight_rx.recv().await {
-            if sender.send(Message::Text(msg.into())).await.is_err() {
-                info!("WebSocket发送失败，连接已断开");
+    info!("新的WebSocket连接已建立: 客户端{}", client.id());
+
+    let snapshot = SnapshotEventDto::new(state.last_known.snapshot(|kind, drone_id| {
+        subscriptions.matches(Channel::of(kind), drone_id)
+    }));
+    if let Some(json) = to_json(&snapshot)
+        && sender.send(Message::Text(json.into())).await.is_err()
+    {
+        info!("WebSocket发送失败，连接已断开");
+        return;
+    }
+
+    'connection: loop {
+        let step = tokio::select! {
+            _ = state.shutdown.cancelled() => Step::Shutdown,
+            event = flight_rx.recv() => forward(Channel::State, event, &subscriptions),
+            event = location_rx.recv() => forward(Channel::Location, event, &subscriptions),
+            event = alert_rx.recv() => forward(Channel::Alerts, event, &subscriptions),
+            msg = receiver.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let (ack, snapshot) = handle_request(&state, &mut subscriptions, &text).await;
+                    let replies = to_json(&ack).into_iter().chain(snapshot.as_ref().and_then(to_json));
+                    Step::Reply(replies.collect())
+                }
+                Some(Ok(Message::Binary(_))) => {
+                    info!("收到WebSocket二进制消息，已忽略");
+                    Step::Skip
+                }
+                // Axum会自动处理Ping/Pong
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => Step::Skip,
+                Some(Ok(Message::Close(_))) => {
+                    info!("收到WebSocket关闭消息，客户端主动断开连接");
+                    Step::Close
+                }
+                Some(Err(e)) => {
+                    info!("WebSocket接收消息出错，连接异常断开: {}", e);
+                    Step::Close
+                }
+                None => Step::Close,
+            },
+        };
+
+        let messages = match step {
+            Step::Send(text) => vec![text],
+            Step::Reply(texts) => texts,
+            Step::Resync { channel, skipped } => {
+                resync(&state, &client, &subscriptions, channel, skipped).into_iter().collect()
+            }
+            Step::Skip => continue,
+            Step::Close => break,
+            Step::Shutdown => {
+                let frame = CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "服务器关闭".into(),
+                };
+                if let Err(e) = sender.send(Message::Close(Some(frame))).await {
+                    info!("WebSocket关闭帧发送失败: {}", e);
+                }
                 break;
             }
+        };
+        for text in messages {
+            if sender.send(Message::Text(text.into())).await.is_err() {
+                info!("WebSocket发送失败，连接已断开");
+                break 'connection;
+            }
         }
-        info!("发送任务结束 - 可能是广播通道关闭或连接断开");
-    });
+    }
 
-    // 创建一个任务来处理从WebSocket接收消息（用于保持连接和心跳检测）
-    let mut recv_task = tokio::spawn(async move {
-        while let Some(msg) = receiver.next().await {
-            match msg {
-                Ok(msg) => {
-                    match msg {
-                        Message::Text(text) => {
-                            info!("收到WebSocket消息: {}", text);
-                            // 这里可以处理客户端发送的消息，但不结束任务
-                        }
-                        Message::Binary(_) => {
-                            info!("收到WebSocket二进制消息");
-                        }
-                        Message::Ping(_) => {
-                            info!("收到WebSocket Ping消息");
-                            // Axum会自动处理Ping/Pong，这里只是记录
-                        }
-                        Message::Pong(_) => {
-                            info!("收到WebSocket Pong消息");
-                        }
-                        Message::Close(_) => {
-                            info!("收到WebSocket关闭消息，客户端主动断开连接");
-                            break;
-                        }
-                    }
-                }
+    info!("WebSocket连接已断开: 客户端{}", client.id());
+}
+
+/// 只转发已订阅的事件
+fn forward(channel: Channel, event: Result<LiveEvent, RecvError>, subscriptions: &Subscriptions) -> Step {
+    match event {
+        Ok(event) if subscriptions.matches(channel, &event.drone_id) => {
+            match ChannelEvent::new(channel, &event.payload) {
+                Ok(message) => to_json(&message).map_or(Step::Skip, Step::Send),
                 Err(e) => {
-                    info!("WebSocket接收消息出错，连接异常断开: {}", e);
-                    break;
+                    warn!("{}事件不是合法的JSON: {}", channel.as_str(), e);
+                    Step::Skip
                 }
             }
         }
-        info!("接收任务结束 - 连接已断开");
-    });
-
-    // 等待任一任务完成，这通常意味着连接已断开
-    tokio::select! {
-        _ = &mut send_task => {
-            info!("发送任务完成，可能是连接断开或广播通道关闭");
-            // 如果发送任务结束，说明连接可能已断开，终止接收任务
-            recv_task.abort();
-        },
-        _ = &mut recv_task => {
-            info!("接收任务完成，客户端已断开连接");
-            // 如果接收任务结束，说明客户端断开了连接，终止发送任务
-            send_task.abort();
+        Ok(_) => Step::Skip,
+        Err(RecvError::Lagged(skipped)) => Step::Resync { channel, skipped },
+        Err(RecvError::Closed) => {
+            info!("{}广播通道已关闭", channel.as_str());
+            Step::Close
         }
     }
+}
+
+fn to_json<T: Serialize>(message: &T) -> Option<String> {
+    serde_json::to_string(message)
+        .inspect_err(|e| warn!("WebSocket消息序列化失败: {}", e))
+        .ok()
+}
+
+/// 记录丢失并生成包含已订阅无人机最近状态的重新同步事件
+fn resync(
+    state: &WsState,
+    client: &ClientHandle,
+    subscriptions: &Subscriptions,
+    channel: Channel,
+    skipped: u64,
+) -> Option<String> {
+    let lag_count = client.record_lag(skipped);
+    warn!(
+        "WebSocket客户端{}处理过慢，{}频道丢弃了{}条消息（累计{}次），发送重新同步事件",
+        client.id(),
+        channel.as_str(),
+        skipped,
+        lag_count
+    );
+    let drones = state.last_known.snapshot(|kind, drone_id| subscriptions.matches(Channel::of(kind), drone_id));
+    to_json(&ResyncEventDto::lagged(channel.as_str(), skipped, lag_count, drones))
+}
+
+/// 处理客户端请求，生成确认；订阅成功时同时返回新订阅部分最近的状态
+async fn handle_request(
+    state: &WsState,
+    subscriptions: &mut Subscriptions,
+    text: &str,
+) -> (Ack, Option<SnapshotEventDto>) {
+    let request: ClientRequest = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => {
+            // 请求无法解析时尽量带回客户端的id
+            let id = serde_json::from_str::<serde_json::Value>(text)
+                .ok()
+                .and_then(|value| value.get("id").cloned());
+            return (Ack::error(id, None, format!("无效的请求: {}", e)), None);
+        }
+    };
 
-    info!("WebSocket连接已断开");
+    match &request {
+        ClientRequest::Subscribe { drones, channels, .. }
+        | ClientRequest::Unsubscribe { drones, channels, .. } => {
+            if drones.as_ref().is_some_and(Vec::is_empty) {
+                let error = "drones不能为空，省略表示全部无人机".to_string();
+                return (Ack::error(request.id(), Some(request.action()), error), None);
+            }
+            if channels.as_ref().is_some_and(Vec::is_empty) {
+                let error = "channels不能为空，省略表示全部频道".to_string();
+                return (Ack::error(request.id(), Some(request.action()), error), None);
+            }
+            let channels = channels.as_deref().unwrap_or(&Channel::ALL);
+            let snapshot = if matches!(request, ClientRequest::Subscribe { .. }) {
+                subscriptions.subscribe(channels, drones.as_deref());
+                let drones = state.last_known.snapshot(|kind, drone_id| {
+                    channels.contains(&Channel::of(kind))
+                        && drones.as_ref().is_none_or(|drones| drones.iter().any(|drone| drone == drone_id))
+                });
+                Some(SnapshotEventDto::new(drones))
+            } else {
+                subscriptions.unsubscribe(channels, drones.as_deref());
+                None
+            };
+            let ack = Ack {
+                subscriptions: Some(subscriptions.summary()),
+                ..Ack::ok(&request)
+            };
+            (ack, snapshot)
+        }
+        ClientRequest::ListDrones { .. } => match state.drone_service.list(0, MAX_LISTED_DRONES).await {
+            Ok(drones) => {
+                let ack = Ack {
+                    drones: Some(drones.into_iter().map(DroneResponseDto::from).collect()),
+                    ..Ack::ok(&request)
+                };
+                (ack, None)
+            }
+            Err(e) => {
+                warn!("查询无人机列表失败: {}", e);
+                let error = "查询无人机列表失败".to_string();
+                (Ack::error(request.id(), Some(request.action()), error), None)
+            }
+        },
+    }
 }
@@ -1,5 +1,6 @@
 pub mod server;
 pub mod handler;
+pub mod protocol;
 
-pub use server::start_websocket_server;
+pub use server::{routes, WsState};
 pub use handler::handle_websocket;
@@ -0,0 +1,350 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+use crate::model::drone::DroneResponseDto;
+use crate::mqtt::LiveKind;
+
+/// 可订阅的频道
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// flight状态
+    State,
+    /// 位置更新
+    Location,
+    /// 告警
+    Alerts,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::State, Channel::Location, Channel::Alerts];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::State => "state",
+            Channel::Location => "location",
+            Channel::Alerts => "alerts",
+        }
+    }
+
+    /// 实时数据对应的频道
+    pub fn of(kind: LiveKind) -> Self {
+        match kind {
+            LiveKind::Flight => Channel::State,
+            LiveKind::Location => Channel::Location,
+            LiveKind::Alert => Channel::Alerts,
+        }
+    }
+}
+
+/// 客户端请求，`action` 区分类型；请求中的 `id` 原样返回在确认消息中
+///
+/// ```json
+/// {"id": 1, "action": "subscribe", "drones": ["A1"], "channels": ["state", "location"]}
+/// {"id": 2, "action": "unsubscribe", "channels": ["location"]}
+/// {"id": 3, "action": "list_drones"}
+/// ```
+///
+/// 省略 `drones` 表示全部无人机，省略 `channels` 表示全部频道。
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClientRequest {
+    Subscribe {
+        #[serde(default)]
+        id: Option<Value>,
+        drones: Option<Vec<String>>,
+        channels: Option<Vec<Channel>>,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        id: Option<Value>,
+        drones: Option<Vec<String>>,
+        channels: Option<Vec<Channel>>,
+    },
+    ListDrones {
+        #[serde(default)]
+        id: Option<Value>,
+    },
+}
+
+impl ClientRequest {
+    pub fn id(&self) -> Option<Value> {
+        match self {
+            ClientRequest::Subscribe { id, .. }
+            | ClientRequest::Unsubscribe { id, .. }
+            | ClientRequest::ListDrones { id } => id.clone(),
+        }
+    }
+
+    pub fn action(&self) -> &'static str {
+        match self {
+            ClientRequest::Subscribe { .. } => "subscribe",
+            ClientRequest::Unsubscribe { .. } => "unsubscribe",
+            ClientRequest::ListDrones { .. } => "list_drones",
+        }
+    }
+}
+
+/// 对每个请求的确认，`ok` 为 `false` 时 `error` 说明原因
+#[derive(Debug, Serialize)]
+pub struct Ack {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: Option<Value>,
+    pub action: Option<&'static str>,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 处理请求后的全部订阅
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscriptions: Option<Vec<SubscriptionDto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drones: Option<Vec<DroneResponseDto>>,
+}
+
+impl Ack {
+    pub fn ok(request: &ClientRequest) -> Self {
+        Ack {
+            kind: "ack",
+            id: request.id(),
+            action: Some(request.action()),
+            ok: true,
+            error: None,
+            subscriptions: None,
+            drones: None,
+        }
+    }
+
+    pub fn error(id: Option<Value>, action: Option<&'static str>, error: String) -> Self {
+        Ack {
+            kind: "ack",
+            id,
+            action,
+            ok: false,
+            error: Some(error),
+            subscriptions: None,
+            drones: None,
+        }
+    }
+}
+
+/// 一个频道的订阅，`drones` 为 `null` 表示全部无人机（`except` 中的除外）
+#[derive(Debug, Serialize)]
+pub struct SubscriptionDto {
+    pub channel: Channel,
+    pub drones: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub except: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum DroneSet {
+    All { except: HashSet<String> },
+    Only(HashSet<String>),
+}
+
+/// 一个WebSocket连接的订阅
+#[derive(Debug, Clone)]
+pub struct Subscriptions {
+    channels: HashMap<Channel, DroneSet>,
+}
+
+impl Default for Subscriptions {
+    /// 新连接默认订阅全部无人机的flight状态，与引入订阅协议之前的行为一致
+    fn default() -> Self {
+        Self {
+            channels: HashMap::from([(Channel::State, DroneSet::All { except: HashSet::new() })]),
+        }
+    }
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, channels: &[Channel], drones: Option<&[String]>) {
+        for &channel in channels {
+            let set = match (self.channels.remove(&channel), drones) {
+                (_, None) => DroneSet::All { except: HashSet::new() },
+                (None, Some(drones)) => DroneSet::Only(drones.iter().cloned().collect()),
+                (Some(DroneSet::Only(mut only)), Some(drones)) => {
+                    only.extend(drones.iter().cloned());
+                    DroneSet::Only(only)
+                }
+                (Some(DroneSet::All { mut except }), Some(drones)) => {
+                    drones.iter().for_each(|drone| {
+                        except.remove(drone);
+                    });
+                    DroneSet::All { except }
+                }
+            };
+            self.channels.insert(channel, set);
+        }
+    }
+
+    pub fn unsubscribe(&mut self, channels: &[Channel], drones: Option<&[String]>) {
+        for &channel in channels {
+            let set = match (self.channels.remove(&channel), drones) {
+                (None, _) | (_, None) => continue,
+                (Some(DroneSet::Only(mut only)), Some(drones)) => {
+                    drones.iter().for_each(|drone| {
+                        only.remove(drone);
+                    });
+                    if only.is_empty() {
+                        continue;
+                    }
+                    DroneSet::Only(only)
+                }
+                (Some(DroneSet::All { mut except }), Some(drones)) => {
+                    except.extend(drones.iter().cloned());
+                    DroneSet::All { except }
+                }
+            };
+            self.channels.insert(channel, set);
+        }
+    }
+
+    pub fn matches(&self, channel: Channel, drone_id: &str) -> bool {
+        match self.channels.get(&channel) {
+            Some(DroneSet::All { except }) => !except.contains(drone_id),
+            Some(DroneSet::Only(only)) => only.contains(drone_id),
+            None => false,
+        }
+    }
+
+    pub fn summary(&self) -> Vec<SubscriptionDto> {
+        Channel::ALL
+            .into_iter()
+            .filter_map(|channel| {
+                let sorted = |set: &HashSet<String>| {
+                    let mut drones: Vec<String> = set.iter().cloned().collect();
+                    drones.sort();
+                    drones
+                };
+                let (drones, except) = match self.channels.get(&channel)? {
+                    DroneSet::All { except } => (None, sorted(except)),
+                    DroneSet::Only(only) => (Some(sorted(only)), Vec::new()),
+                };
+                Some(SubscriptionDto { channel, drones, except })
+            })
+            .collect()
+    }
+}
+
+/// 推送给客户端的实时事件，`data` 为广播的事件内容
+///
+/// ```json
+/// {"channel": "state", "data": {"droneId": "A1", ...}}
+/// ```
+#[derive(Debug, Serialize)]
+pub struct ChannelEvent<'a> {
+    pub channel: Channel,
+    /// 原样嵌入事件JSON，不必为每个连接重新构造一次事件
+    pub data: &'a RawValue,
+}
+
+impl<'a> ChannelEvent<'a> {
+    /// 事件内容不是合法JSON时返回错误
+    pub fn new(channel: Channel, payload: &'a str) -> serde_json::Result<Self> {
+        Ok(ChannelEvent { channel, data: serde_json::from_str(payload)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn drones(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn default_is_all_drones_on_state() {
+        let subscriptions = Subscriptions::default();
+        assert!(subscriptions.matches(Channel::State, "A1"));
+        assert!(!subscriptions.matches(Channel::Location, "A1"));
+        assert!(!subscriptions.matches(Channel::Alerts, "A1"));
+    }
+
+    #[test]
+    fn subscribe_specific_drones_accumulates() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(&[Channel::Location], Some(&drones(&["A1"])));
+        subscriptions.subscribe(&[Channel::Location], Some(&drones(&["B2"])));
+        assert!(subscriptions.matches(Channel::Location, "A1"));
+        assert!(subscriptions.matches(Channel::Location, "B2"));
+        assert!(!subscriptions.matches(Channel::Location, "C3"));
+
+        // 订阅全部无人机后覆盖之前的列表
+        subscriptions.subscribe(&[Channel::Location], None);
+        assert!(subscriptions.matches(Channel::Location, "C3"));
+    }
+
+    #[test]
+    fn unsubscribe_from_all_keeps_exceptions() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.unsubscribe(&[Channel::State], Some(&drones(&["A1"])));
+        assert!(!subscriptions.matches(Channel::State, "A1"));
+        assert!(subscriptions.matches(Channel::State, "B2"));
+
+        // 重新订阅移出例外
+        subscriptions.subscribe(&[Channel::State], Some(&drones(&["A1"])));
+        assert!(subscriptions.matches(Channel::State, "A1"));
+
+        subscriptions.unsubscribe(&Channel::ALL, None);
+        assert!(!subscriptions.matches(Channel::State, "B2"));
+        assert!(subscriptions.summary().is_empty());
+    }
+
+    #[test]
+    fn unsubscribe_last_drone_removes_channel() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(&[Channel::Alerts], Some(&drones(&["A1", "B2"])));
+        subscriptions.unsubscribe(&[Channel::Alerts], Some(&drones(&["A1", "B2"])));
+        assert!(!subscriptions.matches(Channel::Alerts, "A1"));
+        let summary = serde_json::to_value(subscriptions.summary()).unwrap();
+        assert_eq!(summary, json!([{"channel": "state", "drones": null}]));
+    }
+
+    #[test]
+    fn summary_lists_sorted_drones_and_exceptions() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.unsubscribe(&[Channel::State], Some(&drones(&["Z9", "A1"])));
+        subscriptions.subscribe(&[Channel::Location], Some(&drones(&["C3", "B2"])));
+        let summary = serde_json::to_value(subscriptions.summary()).unwrap();
+        assert_eq!(
+            summary,
+            json!([
+                {"channel": "state", "drones": null, "except": ["A1", "Z9"]},
+                {"channel": "location", "drones": ["B2", "C3"]},
+            ])
+        );
+    }
+
+    #[test]
+    fn requests_are_tagged_by_action() {
+        let request: ClientRequest =
+            serde_json::from_value(json!({"id": 7, "action": "subscribe", "channels": ["alerts"]})).unwrap();
+        assert_eq!(request.action(), "subscribe");
+        assert_eq!(request.id(), Some(json!(7)));
+        assert!(serde_json::from_value::<ClientRequest>(json!({"action": "subscribe", "topic": "x"})).is_err());
+        assert!(serde_json::from_value::<ClientRequest>(json!({"action": "publish"})).is_err());
+    }
+
+    #[test]
+    fn event_is_wrapped_in_channel_envelope() {
+        let event = ChannelEvent::new(Channel::State, r#"{"droneId":"A1","value":1.5}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"channel":"state","data":{"droneId":"A1","value":1.5}}"#
+        );
+        let event = ChannelEvent::new(Channel::Alerts, "{ }").unwrap();
+        assert_eq!(serde_json::to_value(&event).unwrap(), json!({"channel": "alerts", "data": {}}));
+    }
+
+    #[test]
+    fn invalid_payload_is_rejected() {
+        assert!(ChannelEvent::new(Channel::Location, "{").is_err());
+        assert!(ChannelEvent::new(Channel::Location, "").is_err());
+    }
+}
@@ -6,29 +6,43 @@ use axum::{
     Router,
 };
 use tokio::sync::broadcast;
-use tower_http::cors::CorsLayer;
-use log::info;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::live::{LastKnownState, LiveClients};
+use crate::mqtt::LiveEvent;
+use crate::server::AppState;
+use crate::service::drone_service::DroneService;
 
 use super::handle_websocket;
 
-/// 启动WebSocket服务器
-pub async fn start_websocket_server(flight_broadcaster: Arc<broadcast::Sender<String>>) -> Result<(), Box<dyn std::error::Error>> {
-    let app = Router::new()
-        .route("/flight_ws", get(websocket_handler))
-        .layer(CorsLayer::permissive())
-        .with_state(flight_broadcaster);
+/// WebSocket共享状态
+pub struct WsState {
+    pub flight_tx: Arc<broadcast::Sender<LiveEvent>>,
+    pub location_tx: Arc<broadcast::Sender<LiveEvent>>,
+    pub alert_tx: Arc<broadcast::Sender<LiveEvent>>,
+    pub drone_service: Arc<DroneService>,
+    /// 连接建立和重新同步时发送的最近状态
+    pub last_known: Arc<LastKnownState>,
+    pub clients: Arc<LiveClients>,
+    /// 取消后各连接发送关闭帧并退出
+    pub shutdown: CancellationToken,
+    /// 已升级的连接不再由HTTP服务跟踪，关闭时通过它等待连接退出
+    pub connections: TaskTracker,
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
-    info!("WebSocket服务器启动在 ws://0.0.0.0:8080/flight_ws");
-    
-    axum::serve(listener, app).await?;
-    Ok(())
+/// WebSocket路由
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/flight_ws", get(websocket_handler))
 }
 
 /// WebSocket升级处理器
 async fn websocket_handler(
     ws: WebSocketUpgrade,
-    State(flight_broadcaster): State<Arc<broadcast::Sender<String>>>,
+    State(state): State<Arc<WsState>>,
 ) -> Response {
-    ws.on_upgrade(|socket| handle_websocket(socket, flight_broadcaster))
+    ws.on_upgrade(|socket| {
+        let connections = state.connections.clone();
+        connections.track_future(handle_websocket(socket, state))
+    })
 }